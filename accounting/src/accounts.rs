@@ -1,11 +1,327 @@
-use std::collections::HashMap;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt, io,
+    sync::{Arc, Mutex, PoisonError},
+};
 
-use crate::{errors::AccountingError, tx::Tx};
+use crate::{
+    assets::{Asset, AssetRegistry},
+    bookkeeping::{GeneralLedger, TrialBalance},
+    clock::{Clock, SharedClock},
+    codec::{invalid_data, Decode, Decoder, Encode, Encoder},
+    errors::AccountingError,
+    fees::{Fee, FeeOn, FeeSchedule, FEE_ACCOUNT},
+    tx::{Details, Envelope, Tx},
+    velocity::{SharedRules, VelocityRules, WINDOW},
+};
 
-/// A type for managing accounts and their current currency balance
+/// A type for managing accounts and their current balance in each asset
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Accounts {
-    accounts: HashMap<String, u64>,
+    accounts: HashMap<String, Account>,
+    /// The assets balances can be held in
+    assets: AssetRegistry,
+    /// The id of the last [`Tx::Transfer`] issued by [`Accounts::send`]
+    last_transfer_id: u64,
+    /// Double-entry books kept alongside the balances, if enabled
+    general_ledger: Option<GeneralLedger>,
+    /// Funds reserved by [`Accounts::hold`], keyed by hold id
+    holds: BTreeMap<u64, Hold>,
+    /// The id of the last hold issued by [`Accounts::hold`]
+    last_hold_id: u64,
+    /// The envelope each idempotency key was first recorded in
+    idempotency_keys: HashMap<String, Envelope>,
+    /// The sequence id of the last recorded [`Envelope`]
+    last_seq: u64,
+    /// The timestamp of the last recorded [`Envelope`]; later ones are never stamped
+    /// earlier, so the history stays ordered by time even if the clock goes back
+    last_timestamp: u64,
+    /// Every recorded envelope, in sequence order; not encoded, the journal keeps it, see
+    /// [`Accounts::restore_history`]
+    history: Vec<Envelope>,
+    /// The sequence id of the last envelope missing from the start of `history`; set when
+    /// decoding, until [`Accounts::restore_history`] puts them back
+    history_missing_until: u64,
+    /// Positions in `history` of the envelopes touching each account; rebuilt on decode
+    history_index: HashMap<String, Vec<usize>>,
+    /// Set by [`Accounts::discard_history`]; new envelopes are then left out of `history`
+    history_discarded: bool,
+    /// The fees charged on withdrawals and transfers
+    fees: FeeSchedule,
+    /// Every disputed deposit, keyed by the deposit's sequence id
+    disputes: BTreeMap<u64, Dispute>,
+    /// The sequence ids of the envelopes undone by [`Accounts::reverse`]
+    reversed: BTreeSet<u64>,
+    /// Withdrawals and transfers above these amounts, keyed by asset, wait for approval
+    approval_thresholds: BTreeMap<String, u64>,
+    /// Withdrawals and transfers waiting for approval, keyed by request id
+    pending_approvals: BTreeMap<u64, PendingApproval>,
+    /// The id of the last request for approval
+    last_approval_id: u64,
+    /// Who operations are made by; only set while [`Accounts::as_operator`] runs one
+    operator: Option<String>,
+    /// Timestamps new envelopes
+    clock: SharedClock,
+    /// The limits withdrawals, transfers and holds are checked against
+    velocity: SharedRules,
+    /// Overrides for the next recorded envelope; only set while an operation runs
+    stamp: Option<Stamp>,
+    /// Past states [`Accounts::as_of`] replays from
+    checkpoints: Checkpoints,
+}
+
+/// How many envelopes apart the states kept by [`Checkpoints`] are
+const CHECKPOINT_EVERY: usize = 1_000;
+
+/// Past states of a ledger, without their history, keyed by how many envelopes of the
+/// history they include.
+///
+/// They are filled lazily as [`Accounts::as_of`] replays the history, so they are not
+/// part of the ledger's state: any two compare equal and they are not encoded.
+#[derive(Default)]
+struct Checkpoints(Mutex<BTreeMap<usize, Arc<Accounts>>>);
+
+impl Checkpoints {
+    fn states(&self) -> std::sync::MutexGuard<'_, BTreeMap<usize, Arc<Accounts>>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clone for Checkpoints {
+    fn clone(&self) -> Self {
+        Checkpoints(Mutex::new(self.states().clone()))
+    }
+}
+
+impl fmt::Debug for Checkpoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.states().keys()).finish()
+    }
+}
+
+impl PartialEq for Checkpoints {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Checkpoints {}
+
+/// A point in a ledger's history
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AsOf {
+    /// Right after the envelope with this sequence id was recorded
+    Seq(u64),
+    /// The end of this second since the Unix epoch
+    Timestamp(u64),
+}
+
+/// What [`Accounts::annotated`] or a replayed [`Envelope`] stamps on recorded transactions
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Stamp {
+    /// Replayed envelopes keep their original sequence id and timestamp
+    recorded: Option<(u64, u64)>,
+    details: Details,
+}
+
+/// One account's position in one asset, in minor units
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Balance {
+    /// Funds in the account, including held funds; `0` while credit is drawn
+    pub total: u64,
+    /// The part of `total` reserved by open holds
+    pub held: u64,
+    /// How far the balance is below zero
+    pub credit_used: u64,
+}
+
+impl Balance {
+    /// The balance with drawn credit counted as negative
+    pub fn net(&self) -> i128 {
+        i128::from(self.total) - i128::from(self.credit_used)
+    }
+}
+
+/// A read-only view of an account
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountSummary<'a> {
+    pub signer: &'a str,
+    pub state: AccountState,
+    /// Keyed by asset code
+    pub balances: BTreeMap<&'a str, Balance>,
+}
+
+/// The order [`Accounts::list_accounts`] returns accounts in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccountOrder<'a> {
+    /// Alphabetically by signer
+    Name,
+    /// By net balance in the asset, largest first; ties are ordered by signer
+    Balance(&'a str),
+}
+
+/// One transfer of a batch applied by [`Accounts::apply_batch`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Leg {
+    pub from: String,
+    pub to: String,
+    pub asset: String,
+    pub amount: u64,
+}
+
+/// A withdrawal or transfer above its asset's approval threshold, waiting for an
+/// operator other than its maker to [`Accounts::approve`] or [`Accounts::reject`] it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingApproval {
+    /// The operator who made the request, or the account holder if no operator did
+    pub maker: String,
+    pub from: String,
+    /// The recipient of a transfer; `None` for a withdrawal
+    pub to: Option<String>,
+    pub asset: String,
+    pub amount: u64,
+    pub idempotency_key: Option<String>,
+}
+
+impl PendingApproval {
+    /// The funds held while the request waits
+    fn funds(&self) -> Hold {
+        Hold {
+            account: self.from.clone(),
+            asset: self.asset.clone(),
+            amount: self.amount,
+        }
+    }
+}
+
+/// A page of an account's history returned by [`Accounts::account_history`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryPage<'a> {
+    /// Oldest first
+    pub envelopes: Vec<&'a Envelope>,
+    /// The cursor to pass for the next page; `None` on the last page
+    pub next_after: Option<u64>,
+}
+
+/// Where an account is in its lifecycle
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccountState {
+    /// Can send and receive funds
+    #[default]
+    Open,
+    /// Can receive funds but not send them
+    Frozen,
+    /// Emptied and shut down; cannot be used again
+    Closed,
+    /// Locked by a chargeback; can neither send nor receive funds
+    Locked,
+}
+
+/// Where a disputed deposit is in the dispute workflow
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisputeState {
+    /// The deposit's funds are held until the dispute is settled
+    Open,
+    /// Settled in the account's favour; the funds were released
+    Resolved,
+    /// Settled against the account; the funds were withdrawn and the account locked
+    ChargedBack,
+}
+
+/// The state of a single signer's account
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Account {
+    state: AccountState,
+    /// Total balance in minor units, keyed by asset code
+    balances: BTreeMap<String, u64>,
+    /// The part of each balance reserved by open holds, keyed by asset code
+    held: BTreeMap<String, u64>,
+    /// How far below zero each balance may go, keyed by asset code
+    credit_limits: BTreeMap<String, u64>,
+    /// How much of each credit line is drawn, keyed by asset code; a balance is only
+    /// positive once its credit line is repaid
+    credit_used: BTreeMap<String, u64>,
+}
+
+impl Account {
+    fn balance(&self, asset: &str) -> u64 {
+        self.balances.get(asset).copied().unwrap_or_default()
+    }
+
+    fn held(&self, asset: &str) -> u64 {
+        self.held.get(asset).copied().unwrap_or_default()
+    }
+
+    fn credit_limit(&self, asset: &str) -> u64 {
+        self.credit_limits.get(asset).copied().unwrap_or_default()
+    }
+
+    fn credit_used(&self, asset: &str) -> u64 {
+        self.credit_used.get(asset).copied().unwrap_or_default()
+    }
+
+    /// The part of the balance that is not reserved by a hold
+    fn available(&self, asset: &str) -> u64 {
+        self.balance(asset).saturating_sub(self.held(asset))
+    }
+
+    /// The available balance plus the undrawn part of the credit line
+    fn spendable(&self, asset: &str) -> u64 {
+        let headroom = self
+            .credit_limit(asset)
+            .saturating_sub(self.credit_used(asset));
+        self.balance(asset)
+            .saturating_add(headroom)
+            .saturating_sub(self.held(asset))
+    }
+}
+
+fn summarize<'a>(signer: &'a str, account: &'a Account) -> AccountSummary<'a> {
+    let assets = account
+        .balances
+        .keys()
+        .chain(account.credit_used.keys())
+        .chain(account.held.keys());
+    AccountSummary {
+        signer,
+        state: account.state,
+        balances: assets
+            .map(|asset| {
+                let balance = Balance {
+                    total: account.balance(asset),
+                    held: account.held(asset),
+                    credit_used: account.credit_used(asset),
+                };
+                (asset.as_str(), balance)
+            })
+            .collect(),
+    }
+}
+
+/// Stores a per-asset figure, dropping it once it is zero
+fn set_or_remove(figures: &mut BTreeMap<String, u64>, asset: &str, value: u64) {
+    if value == 0 {
+        figures.remove(asset);
+    } else {
+        figures.insert(asset.to_string(), value);
+    }
+}
+
+/// Funds reserved in an account until they are released or captured
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Hold {
+    account: String,
+    asset: String,
+    amount: u64,
+}
+
+/// A deposit disputed by [`Accounts::dispute`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Dispute {
+    /// The deposit's funds, held while the dispute is open
+    funds: Hold,
+    state: DisputeState,
 }
 
 impl Accounts {
@@ -13,146 +329,3793 @@ impl Accounts {
     pub fn new() -> Self {
         Accounts {
             accounts: Default::default(),
+            assets: Default::default(),
+            last_transfer_id: 0,
+            general_ledger: None,
+            holds: BTreeMap::new(),
+            last_hold_id: 0,
+            idempotency_keys: HashMap::new(),
+            last_seq: 0,
+            last_timestamp: 0,
+            history: vec![],
+            history_missing_until: 0,
+            history_index: HashMap::new(),
+            history_discarded: false,
+            fees: FeeSchedule::default(),
+            disputes: BTreeMap::new(),
+            reversed: BTreeSet::new(),
+            approval_thresholds: BTreeMap::new(),
+            pending_approvals: BTreeMap::new(),
+            last_approval_id: 0,
+            operator: None,
+            clock: SharedClock::default(),
+            velocity: SharedRules::default(),
+            stamp: None,
+            checkpoints: Checkpoints::default(),
+        }
+    }
+
+    /// Returns an empty instance that also posts every [`Tx`] to double-entry books
+    pub fn with_double_entry() -> Self {
+        Accounts {
+            general_ledger: Some(GeneralLedger::default()),
+            ..Accounts::new()
+        }
+    }
+
+    /// The trial balance of the double-entry books; `None` unless created with
+    /// [`Accounts::with_double_entry`]
+    pub fn trial_balance(&self) -> Option<TrialBalance> {
+        self.general_ledger
+            .as_ref()
+            .map(GeneralLedger::trial_balance)
+    }
+
+    /// Replaces the clock new envelopes are timestamped with; the system clock by default.
+    /// An envelope is never stamped earlier than the one before it, whatever the clock says.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = SharedClock::new(clock);
+    }
+
+    /// Replaces the limits withdrawals, transfers and holds are checked against; none by
+    /// default.
+    ///
+    /// Daily limits count what the history holds, so only per-transaction limits apply
+    /// once [`Accounts::discard_history`] was called.
+    pub fn set_velocity_rules(&mut self, rules: VelocityRules) {
+        self.velocity = SharedRules::new(rules);
+    }
+
+    /// The limits withdrawals, transfers and holds are checked against
+    pub fn velocity_rules(&self) -> &VelocityRules {
+        self.velocity.rules()
+    }
+
+    /// The current time of the ledger's clock, in seconds since the Unix epoch
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// The sequence id of the last recorded [`Envelope`]; `0` if nothing was recorded yet
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// The envelopes recorded after sequence id `seq`, oldest first
+    pub fn history_since(&self, seq: u64) -> &[Envelope] {
+        let start = self.history.partition_point(|envelope| envelope.seq <= seq);
+        &self.history[start..]
+    }
+
+    /// Puts back the history of a ledger decoded from a snapshot, which does not carry it,
+    /// in front of the envelopes recorded or replayed since.
+    ///
+    /// `envelopes` must be the ones the snapshot covered, oldest first, e.g. read back with
+    /// [`Journal::history`](crate::journal::Journal::history); they are not applied again.
+    ///
+    /// # Errors
+    /// - an envelope is not the one right after the one before it, or was not left out
+    /// - `envelopes` stop before the last one that was left out
+    pub fn restore_history(
+        &mut self,
+        envelopes: impl IntoIterator<Item = Envelope>,
+    ) -> Result<(), AccountingError> {
+        let mut restored: Vec<Envelope> = vec![];
+        for envelope in envelopes {
+            let after = restored.last().map_or(0, |last| last.seq);
+            if envelope.seq != after + 1 || envelope.seq > self.history_missing_until {
+                return Err(AccountingError::EnvelopeOutOfOrder(envelope.seq));
+            }
+            restored.push(envelope);
+        }
+        if restored.len() as u64 != self.history_missing_until {
+            return Err(AccountingError::HistoryUnavailable(
+                self.history_missing_until,
+            ));
+        }
+
+        let since = std::mem::take(&mut self.history);
+        self.history_index = HashMap::new();
+        self.history_missing_until = 0;
+        for envelope in restored.into_iter().chain(since) {
+            self.push_history(envelope);
+        }
+        Ok(())
+    }
+
+    /// Drops the history and stops keeping it, for ledgers that only need their current
+    /// state, e.g. while processing a large batch, where it would grow with every envelope.
+    ///
+    /// Queries on the history such as [`Accounts::account_history`] and
+    /// [`Accounts::as_of`] then find nothing. The setting is not encoded.
+    pub fn discard_history(&mut self) {
+        self.history = vec![];
+        self.history_index = HashMap::new();
+        self.history_discarded = true;
+    }
+
+    /// Runs `operation` with `details` attached to every envelope it records, e.g.
+    /// `accounts.annotated(details, |accounts| accounts.deposit("alice", "USD", 500))`.
+    ///
+    /// # Errors
+    /// - the error returned by `operation`
+    pub fn annotated<T>(
+        &mut self,
+        details: Details,
+        operation: impl FnOnce(&mut Accounts) -> Result<T, AccountingError>,
+    ) -> Result<T, AccountingError> {
+        self.stamped(
+            Stamp {
+                recorded: None,
+                details,
+            },
+            operation,
+        )
+    }
+
+    /// Whether recorded envelopes are being replayed, see [`Accounts::apply`]
+    fn is_replaying(&self) -> bool {
+        matches!(
+            self.stamp,
+            Some(Stamp {
+                recorded: Some(_),
+                ..
+            })
+        )
+    }
+
+    fn stamped<T>(
+        &mut self,
+        stamp: Stamp,
+        operation: impl FnOnce(&mut Accounts) -> Result<T, AccountingError>,
+    ) -> Result<T, AccountingError> {
+        let previous = self.stamp.replace(stamp);
+        let result = operation(self);
+        self.stamp = previous;
+        result
+    }
+
+    /// The fees charged on withdrawals and transfers
+    pub fn fee_schedule(&self) -> &FeeSchedule {
+        &self.fees
+    }
+
+    /// Replaces the fee charged on `on` operations in `asset`; `None` makes them free.
+    ///
+    /// # Errors
+    /// - unknown asset
+    pub fn set_fee(
+        &mut self,
+        on: FeeOn,
+        asset: &str,
+        fee: Option<Fee>,
+    ) -> Result<Envelope, AccountingError> {
+        self.assets.require(asset)?;
+        self.fees.set(on, asset, fee.clone());
+
+        Ok(self.record(Tx::SetFee {
+            on,
+            asset: asset.to_string(),
+            fee,
+        }))
+    }
+
+    /// The assets this ledger accepts
+    pub fn assets(&self) -> &AssetRegistry {
+        &self.assets
+    }
+
+    /// Registers a new asset whose amounts have `decimals` digits after the decimal point.
+    ///
+    /// # Errors
+    /// - the asset is already registered
+    /// - `decimals` is larger than [`crate::assets::MAX_DECIMALS`]
+    pub fn register_asset(
+        &mut self,
+        asset: &str,
+        decimals: u8,
+    ) -> Result<Envelope, AccountingError> {
+        self.assets.register(Asset {
+            code: asset.to_string(),
+            decimals,
+        })?;
+
+        Ok(self.record(Tx::RegisterAsset {
+            asset: asset.to_string(),
+            decimals,
+        }))
+    }
+
+    /// Either deposits the `amount` provided into the `signer` account or adds the amount to the existing account.
+    ///
+    /// A first deposit opens the account implicitly; see [`Accounts::open`].
+    ///
+    /// # Errors
+    /// - unknown asset
+    /// - the account is closed
+    /// - attempted overflow
+    pub fn deposit(
+        &mut self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Envelope, AccountingError> {
+        self.deposit_with_key(signer, asset, amount, None)
+    }
+
+    /// Like [`Accounts::deposit`], but a retry with the same `idempotency_key` returns the
+    /// original [`Envelope`] without depositing again.
+    ///
+    /// # Errors
+    /// - the key was already used with different parameters
+    /// - the same errors as [`Accounts::deposit`]
+    pub fn deposit_idempotent(
+        &mut self,
+        idempotency_key: &str,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Envelope, AccountingError> {
+        self.deposit_with_key(signer, asset, amount, Some(idempotency_key))
+    }
+
+    fn deposit_with_key(
+        &mut self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+        idempotency_key: Option<&str>,
+    ) -> Result<Envelope, AccountingError> {
+        let tx = Tx::Deposit {
+            account: signer.to_string(),
+            asset: asset.to_string(),
+            amount,
+            idempotency_key: idempotency_key.map(str::to_string),
+        };
+        if let Some(original) = self.previously_applied(&tx)? {
+            return Ok(original);
+        }
+
+        self.assets.require(asset)?;
+        self.ensure_can_receive(signer)?;
+        self.credit(signer, asset, amount)?;
+
+        Ok(self.record(tx))
+    }
+
+    /// Withdraws the `amount` from the `signer` account.
+    ///
+    /// A withdrawal fee, if configured, is charged in the same step and recorded
+    /// right after the returned envelope as a [`Tx::Fee`]. Above the asset's approval
+    /// threshold the funds are held instead and the returned envelope is a
+    /// [`Tx::ApprovalRequested`]; see [`Accounts::approve`].
+    ///
+    /// # Errors
+    /// - unknown asset
+    /// - insufficient funds, including the fee
+    /// - the request goes over the account's credit limit
+    /// - funds are reserved by a hold
+    /// - inexistent account
+    /// - the account is frozen or closed
+    /// - the amount goes over the account's velocity limits, see
+    ///   [`Accounts::set_velocity_rules`]
+    pub fn withdraw(
+        &mut self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Envelope, AccountingError> {
+        self.withdraw_with_key(signer, asset, amount, None)
+    }
+
+    /// Like [`Accounts::withdraw`], but a retry with the same `idempotency_key` returns the
+    /// original [`Envelope`] without withdrawing again. The original is the
+    /// [`Tx::ApprovalRequested`] one until the request is approved.
+    ///
+    /// # Errors
+    /// - the key was already used with different parameters
+    /// - [`AccountingError::ApprovalPending`] if the key was used with different parameters
+    ///   by a request still waiting for approval
+    /// - the same errors as [`Accounts::withdraw`]
+    pub fn withdraw_idempotent(
+        &mut self,
+        idempotency_key: &str,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Envelope, AccountingError> {
+        self.withdraw_with_key(signer, asset, amount, Some(idempotency_key))
+    }
+
+    fn withdraw_with_key(
+        &mut self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+        idempotency_key: Option<&str>,
+    ) -> Result<Envelope, AccountingError> {
+        let tx = Tx::Withdraw {
+            account: signer.to_string(),
+            asset: asset.to_string(),
+            amount,
+            idempotency_key: idempotency_key.map(str::to_string),
+        };
+        if let Some(original) = self.previously_applied(&tx)? {
+            return Ok(original);
+        }
+        self.check_velocity(signer, asset, amount, 0)?;
+        if self.needs_approval(asset, amount) {
+            let id = self.last_approval_id + 1;
+            return self.request_approval(id, signer, None, asset, amount, idempotency_key);
+        }
+        self.make_withdrawal(signer, asset, amount, idempotency_key)
+    }
+
+    /// Withdraws without checking the idempotency key or the approval threshold
+    fn make_withdrawal(
+        &mut self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+        idempotency_key: Option<&str>,
+    ) -> Result<Envelope, AccountingError> {
+        self.assets.require(asset)?;
+        let fee = self.fee_for(FeeOn::Withdrawal, signer, asset, amount)?;
+        self.ensure_can_spend(signer, asset, amount)?;
+        self.debit(signer, asset, amount);
+
+        let envelope = self.record(Tx::Withdraw {
+            account: signer.to_string(),
+            asset: asset.to_string(),
+            amount,
+            idempotency_key: idempotency_key.map(str::to_string),
+        });
+        self.charge_fee(signer, asset, fee)?;
+        Ok(envelope)
+    }
+
+    /// The envelope first recorded with `idempotency_key`, if any
+    pub fn idempotent_tx(&self, idempotency_key: &str) -> Option<&Envelope> {
+        self.idempotency_keys.get(idempotency_key)
+    }
+
+    /// Looks up the idempotency key carried by `tx`.
+    ///
+    /// Returns the original envelope if `tx` is a retry of its transaction, or of the
+    /// withdrawal or transfer a request for approval was recorded for. Replayed transactions
+    /// are never retries: the journal only holds the ones that were applied, including the
+    /// approved operation recorded under its request's key.
+    ///
+    /// # Errors
+    /// - the key was already used for a different transaction
+    fn previously_applied(&self, tx: &Tx) -> Result<Option<Envelope>, AccountingError> {
+        let Some(key) = tx.idempotency_key().filter(|_| !self.is_replaying()) else {
+            return Ok(None);
+        };
+        match self.idempotency_keys.get(key) {
+            Some(original) if is_retry_of(&original.tx, tx) => Ok(Some(original.clone())),
+            Some(Envelope {
+                tx: Tx::ApprovalRequested { id, .. },
+                ..
+            }) if self.pending_approvals.contains_key(id) => {
+                Err(AccountingError::ApprovalPending(*id))
+            }
+            Some(_) => Err(AccountingError::IdempotencyKeyReused(key.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Withdraws the amount from the sender account and deposits it in the recipient account.
+    ///
+    /// Both legs are validated before any balance is touched, so a failed transfer
+    /// leaves the accounts unchanged. The returned [`Tx::Transfer`] carries a new
+    /// transfer id. A transfer fee, if configured, is paid by the `sender` in the same
+    /// step and recorded right after the returned envelope as a [`Tx::Fee`]. Above the
+    /// asset's approval threshold the funds are held instead and the returned envelope is
+    /// a [`Tx::ApprovalRequested`]; see [`Accounts::approve`].
+    ///
+    /// # Errors
+    /// - unknown asset
+    /// - inexistent `sender` account
+    /// - `sender` has insufficient funds, including the fee, or they are reserved by a hold
+    /// - the request goes over the `sender`'s credit limit
+    /// - `sender` is frozen or closed
+    /// - `recipient` is closed
+    /// - deposit can cause overflow for `recipient`
+    /// - the amount goes over the `sender`'s velocity limits
+    pub fn send(
+        &mut self,
+        sender: &str,
+        recipient: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Envelope, AccountingError> {
+        self.send_with_key(sender, recipient, asset, amount, None)
+    }
+
+    /// Like [`Accounts::send`], but a retry with the same `idempotency_key` returns the
+    /// original [`Envelope`] without sending again. The original is the
+    /// [`Tx::ApprovalRequested`] one until the request is approved.
+    ///
+    /// # Errors
+    /// - the key was already used with different parameters
+    /// - [`AccountingError::ApprovalPending`] if the key was used with different parameters
+    ///   by a request still waiting for approval
+    /// - the same errors as [`Accounts::send`]
+    pub fn send_idempotent(
+        &mut self,
+        idempotency_key: &str,
+        sender: &str,
+        recipient: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Envelope, AccountingError> {
+        self.send_with_key(sender, recipient, asset, amount, Some(idempotency_key))
+    }
+
+    fn send_with_key(
+        &mut self,
+        sender: &str,
+        recipient: &str,
+        asset: &str,
+        amount: u64,
+        idempotency_key: Option<&str>,
+    ) -> Result<Envelope, AccountingError> {
+        let tx = Tx::Transfer {
+            id: self.last_transfer_id + 1,
+            from: sender.to_string(),
+            to: recipient.to_string(),
+            asset: asset.to_string(),
+            amount,
+            idempotency_key: idempotency_key.map(str::to_string),
+        };
+        if let Some(original) = self.previously_applied(&tx)? {
+            return Ok(original);
+        }
+        self.check_velocity(sender, asset, amount, 0)?;
+        if self.needs_approval(asset, amount) {
+            let id = self.last_approval_id + 1;
+            return self.request_approval(
+                id,
+                sender,
+                Some(recipient),
+                asset,
+                amount,
+                idempotency_key,
+            );
+        }
+        self.send_charged(sender, recipient, asset, amount, idempotency_key)
+            .map(|(transfer, _)| transfer)
+    }
+
+    /// Applies every leg in order, as if each were sent with [`Accounts::send`], or none of
+    /// them, and returns the envelopes recorded, fees included.
+    ///
+    /// Each leg is checked against the balances the legs before it leave, so funds received
+    /// in a leg can be sent on in a later one, and against velocity limits counting what the
+    /// legs before it send. Legs cannot wait for approval, so a leg above its asset's
+    /// approval threshold fails the batch.
+    ///
+    /// # Errors
+    /// - [`AccountingError::BatchLegFailed`] with the index of the first leg that fails and
+    ///   its error: [`AccountingError::ApprovalRequired`] or one of those of
+    ///   [`Accounts::send`]
+    pub fn apply_batch(&mut self, legs: Vec<Leg>) -> Result<Vec<Envelope>, AccountingError> {
+        let mut scratch = self.scratch();
+        for (index, leg) in legs.iter().enumerate() {
+            scratch.copy_account(self, &leg.from);
+            scratch.copy_account(self, &leg.to);
+            let sent = scratch.outflow(&leg.from, &leg.asset);
+            self.require_no_approval(&leg.asset, leg.amount)
+                .and_then(|()| self.check_velocity(&leg.from, &leg.asset, leg.amount, sent))
+                .and_then(|()| scratch.send(&leg.from, &leg.to, &leg.asset, leg.amount))
+                .map_err(|e| AccountingError::BatchLegFailed(index, Box::new(e)))?;
+        }
+
+        let mut envelopes = vec![];
+        for (index, leg) in legs.iter().enumerate() {
+            let (transfer, fee) = self
+                .send_charged(&leg.from, &leg.to, &leg.asset, leg.amount, None)
+                .map_err(|e| AccountingError::BatchLegFailed(index, Box::new(e)))?;
+            envelopes.push(transfer);
+            envelopes.extend(fee);
         }
+        Ok(envelopes)
+    }
+
+    /// Sends like [`Accounts::send`] and also returns the envelope of the fee, if one was
+    /// charged
+    fn send_charged(
+        &mut self,
+        sender: &str,
+        recipient: &str,
+        asset: &str,
+        amount: u64,
+        idempotency_key: Option<&str>,
+    ) -> Result<(Envelope, Option<Envelope>), AccountingError> {
+        self.assets.require(asset)?;
+        let fee = self.fee_for(FeeOn::Transfer, sender, asset, amount)?;
+        self.transfer(sender, recipient, asset, amount)?;
+        self.last_transfer_id += 1;
+
+        let envelope = self.record(Tx::Transfer {
+            id: self.last_transfer_id,
+            from: sender.to_string(),
+            to: recipient.to_string(),
+            asset: asset.to_string(),
+            amount,
+            idempotency_key: idempotency_key.map(str::to_string),
+        });
+        let fee = self.charge_fee(sender, asset, fee)?;
+        Ok((envelope, fee))
+    }
+
+    /// Checks a withdrawal, transfer or hold of `amount` out of `signer` against the velocity
+    /// rules, counting `earlier` as moved out on top of what the history holds; never while
+    /// replaying, where the operation already passed the check.
+    pub(crate) fn check_velocity(
+        &self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+        earlier: u64,
+    ) -> Result<(), AccountingError> {
+        if self.is_replaying() {
+            return Ok(());
+        }
+        self.velocity.rules().check(signer, asset, amount, || {
+            self.outflow(signer, asset).saturating_add(earlier)
+        })
+    }
+
+    /// What `signer` withdrew, sent and captured of `asset` over the last [`WINDOW`] seconds
+    pub(crate) fn outflow(&self, signer: &str, asset: &str) -> u64 {
+        let since = self.now().saturating_sub(WINDOW);
+        self.history_index
+            .get(signer)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .rev()
+            .map(|position| &self.history[*position])
+            .take_while(|envelope| envelope.timestamp > since)
+            .filter_map(|envelope| match &envelope.tx {
+                Tx::Withdraw {
+                    account,
+                    asset: withdrawn,
+                    amount,
+                    ..
+                } if account == signer && withdrawn == asset => Some(*amount),
+                Tx::Transfer {
+                    from,
+                    asset: sent,
+                    amount,
+                    ..
+                } if from == signer && sent == asset => Some(*amount),
+                Tx::Capture {
+                    account,
+                    asset: captured,
+                    amount,
+                    ..
+                } if account == signer && captured == asset => Some(*amount),
+                _ => None,
+            })
+            .fold(0, u64::saturating_add)
+    }
+
+    /// A ledger with an empty history that holds a copy of [`FEE_ACCOUNT`] and the settings
+    /// operations are checked against, for trying out operations on accounts copied in
+    /// with [`Accounts::copy_account`] without touching this ledger.
+    ///
+    /// Envelopes recorded on it get the ids they would get on this ledger.
+    pub(crate) fn scratch(&self) -> Accounts {
+        let mut scratch = Accounts {
+            assets: self.assets.clone(),
+            fees: self.fees.clone(),
+            approval_thresholds: self.approval_thresholds.clone(),
+            clock: self.clock.clone(),
+            last_transfer_id: self.last_transfer_id,
+            last_approval_id: self.last_approval_id,
+            last_seq: self.last_seq,
+            last_timestamp: self.last_timestamp,
+            ..Accounts::new()
+        };
+        scratch.copy_account(self, FEE_ACCOUNT);
+        scratch
+    }
+
+    /// Copies the `signer`'s account from `base`, unless this ledger already has one
+    pub(crate) fn copy_account(&mut self, base: &Accounts, signer: &str) {
+        if self.accounts.contains_key(signer) {
+            return;
+        }
+        if let Some(account) = base.accounts.get(signer) {
+            self.accounts.insert(signer.to_string(), account.clone());
+        }
+    }
+
+    /// The fee due on an operation moving `amount` of `asset` out of `signer`, after
+    /// checking that `signer` can pay it on top of `amount` and [`FEE_ACCOUNT`] can
+    /// receive it.
+    ///
+    /// Replayed operations are charged nothing: their recorded [`Tx::Fee`] follows them.
+    fn fee_for(
+        &self,
+        on: FeeOn,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<u64, AccountingError> {
+        let fee = if self.is_replaying() {
+            0
+        } else {
+            self.fees.charge(on, asset, amount)
+        };
+        if fee == 0 {
+            return Ok(0);
+        }
+
+        let total = amount
+            .checked_add(fee)
+            .ok_or_else(|| AccountingError::AccountUnderFunded(signer.to_string(), amount))?;
+        self.ensure_can_spend(signer, asset, total)?;
+        self.ensure_can_receive(FEE_ACCOUNT)?;
+        self.ensure_can_credit(FEE_ACCOUNT, asset, fee)?;
+        Ok(fee)
+    }
+
+    /// Moves a `fee` checked by [`Accounts::fee_for`] into [`FEE_ACCOUNT`] and returns the
+    /// recorded envelope; `None` if the fee is `0`
+    fn charge_fee(
+        &mut self,
+        signer: &str,
+        asset: &str,
+        fee: u64,
+    ) -> Result<Option<Envelope>, AccountingError> {
+        if fee == 0 {
+            return Ok(None);
+        }
+        self.transfer(signer, FEE_ACCOUNT, asset, fee)?;
+        Ok(Some(self.record(Tx::Fee {
+            account: signer.to_string(),
+            asset: asset.to_string(),
+            amount: fee,
+        })))
+    }
+
+    /// Moves `amount` from `from` to `to` as a single step.
+    fn transfer(
+        &mut self,
+        from: &str,
+        to: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<(), AccountingError> {
+        self.assets.require(asset)?;
+        self.ensure_can_spend(from, asset, amount)?;
+
+        self.ensure_can_receive(to)?;
+
+        // Sending to yourself nets out, so only a distinct recipient can overflow
+        if from != to {
+            self.balance_of(to, asset)
+                .checked_add(amount)
+                .ok_or_else(|| AccountingError::AccountOverFunded(to.to_string(), amount))?;
+        }
+
+        self.debit(from, asset, amount);
+        self.credit(to, asset, amount)
+    }
+
+    /// Reserves `amount` of the `signer`'s available balance without moving it.
+    ///
+    /// The returned [`Tx::Hold`] carries the id used to [`Accounts::release`] or
+    /// [`Accounts::capture`] the funds later.
+    ///
+    /// # Errors
+    /// - unknown asset
+    /// - inexistent account
+    /// - the account is frozen or closed
+    /// - insufficient available funds
+    /// - the amount goes over the account's velocity limits, see
+    ///   [`Accounts::set_velocity_rules`]
+    /// - [`AccountingError::ApprovalRequired`] above the asset's approval threshold, as a
+    ///   hold cannot wait for approval
+    pub fn hold(
+        &mut self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Envelope, AccountingError> {
+        self.assets.require(asset)?;
+        self.ensure_can_spend(signer, asset, amount)?;
+        self.check_velocity(signer, asset, amount, 0)?;
+        self.require_no_approval(asset, amount)?;
+        self.last_hold_id += 1;
+        self.insert_hold(self.last_hold_id, signer, asset, amount);
+
+        Ok(self.record(Tx::Hold {
+            id: self.last_hold_id,
+            account: signer.to_string(),
+            asset: asset.to_string(),
+            amount,
+        }))
+    }
+
+    /// Cancels a hold, making its funds available again.
+    ///
+    /// # Errors
+    /// - no open hold with this id
+    pub fn release(&mut self, hold_id: u64) -> Result<Envelope, AccountingError> {
+        let hold = self.remove_hold(hold_id)?;
+
+        Ok(self.record(Tx::Release {
+            id: hold_id,
+            account: hold.account,
+            asset: hold.asset,
+            amount: hold.amount,
+        }))
+    }
+
+    /// Completes a hold by withdrawing its funds from the account.
+    ///
+    /// The funds only leave now, so the account's velocity limits are checked again.
+    ///
+    /// # Errors
+    /// - no open hold with this id
+    /// - the account is frozen
+    /// - the amount goes over the account's velocity limits
+    pub fn capture(&mut self, hold_id: u64) -> Result<Envelope, AccountingError> {
+        if let Some(hold) = self.holds.get(&hold_id) {
+            self.ensure_can_send(&hold.account)?;
+            self.check_velocity(&hold.account, &hold.asset, hold.amount, 0)?;
+        }
+        let hold = self.remove_hold(hold_id)?;
+        self.debit(&hold.account, &hold.asset, hold.amount);
+
+        Ok(self.record(Tx::Capture {
+            id: hold_id,
+            account: hold.account,
+            asset: hold.asset,
+            amount: hold.amount,
+        }))
+    }
+
+    /// Disputes the deposit recorded with sequence id `deposit`, holding its funds until
+    /// the dispute is resolved or charged back.
+    ///
+    /// # Errors
+    /// - no deposit is in the history with this sequence id
+    /// - the deposit was already disputed or reversed
+    /// - the deposited funds are no longer available in the account
+    pub fn dispute(&mut self, deposit: u64) -> Result<Envelope, AccountingError> {
+        let (account, asset, amount) = match self.recorded(deposit).map(|envelope| &envelope.tx) {
+            Some(Tx::Deposit {
+                account,
+                asset,
+                amount,
+                ..
+            }) => (account.clone(), asset.clone(), *amount),
+            _ => return Err(AccountingError::DepositNotFound(deposit)),
+        };
+        self.dispute_deposit(deposit, &account, &asset, amount)
+    }
+
+    /// Disputes the deposit of `amount` of `asset` into `account` recorded with sequence id
+    /// `deposit`, for callers that know the deposit without looking it up in the history.
+    ///
+    /// # Errors
+    /// - the deposit was already disputed or reversed
+    /// - the deposited funds are no longer available in the account
+    pub(crate) fn dispute_deposit(
+        &mut self,
+        deposit: u64,
+        account: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Envelope, AccountingError> {
+        if self.reversed.contains(&deposit) {
+            return Err(AccountingError::TxAlreadyReversed(deposit));
+        }
+        if self.disputes.contains_key(&deposit) {
+            return Err(AccountingError::DepositAlreadyDisputed(deposit));
+        }
+        if amount > self.existing(account)?.available(asset) {
+            return Err(AccountingError::InsufficientAvailableFunds(
+                account.to_string(),
+                amount,
+            ));
+        }
+        let funds = Hold {
+            account: account.to_string(),
+            asset: asset.to_string(),
+            amount,
+        };
+        self.add_held(&funds);
+        self.disputes.insert(
+            deposit,
+            Dispute {
+                funds: funds.clone(),
+                state: DisputeState::Open,
+            },
+        );
+
+        Ok(self.record(Tx::Dispute {
+            deposit,
+            account: funds.account,
+            asset: funds.asset,
+            amount,
+        }))
+    }
+
+    /// Settles the open dispute of a deposit in the account's favour, making its funds
+    /// available again.
+    ///
+    /// # Errors
+    /// - the deposit has no open dispute
+    pub fn resolve(&mut self, deposit: u64) -> Result<Envelope, AccountingError> {
+        let funds = self.settle_dispute(deposit, DisputeState::Resolved)?;
+
+        Ok(self.record(Tx::Resolve {
+            deposit,
+            account: funds.account,
+            asset: funds.asset,
+            amount: funds.amount,
+        }))
+    }
+
+    /// Settles the open dispute of a deposit against the account: its funds are withdrawn
+    /// and the account is locked, whatever its state.
+    ///
+    /// # Errors
+    /// - the deposit has no open dispute
+    pub fn chargeback(&mut self, deposit: u64) -> Result<Envelope, AccountingError> {
+        let funds = self.settle_dispute(deposit, DisputeState::ChargedBack)?;
+        self.debit(&funds.account, &funds.asset, funds.amount);
+        self.set_state(&funds.account, AccountState::Locked);
+
+        Ok(self.record(Tx::Chargeback {
+            deposit,
+            account: funds.account,
+            asset: funds.asset,
+            amount: funds.amount,
+        }))
+    }
+
+    /// Undoes the deposit, withdrawal, transfer or fee recorded with sequence id `tx_id` by
+    /// recording a [`Tx::Reversal`] that moves its funds back; the original stays in the
+    /// history untouched.
+    ///
+    /// # Errors
+    /// - no envelope with this sequence id is in the history
+    /// - the transaction is of another kind or is already reversed
+    /// - a reversed deposit was disputed
+    /// - the funds to take back are no longer available, e.g. they were spent or held
+    /// - the account the funds go back to is closed or locked, or would overflow
+    pub fn reverse(&mut self, tx_id: u64) -> Result<Envelope, AccountingError> {
+        let tx = self
+            .recorded(tx_id)
+            .ok_or(AccountingError::TxNotFound(tx_id))?
+            .tx
+            .clone();
+        self.reverse_tx(tx_id, &tx)
+    }
+
+    /// Makes withdrawals and transfers in `asset` above `threshold` wait for a second
+    /// operator's approval; `None` lifts the requirement. Requests already waiting are kept.
+    ///
+    /// # Errors
+    /// - unknown asset
+    pub fn set_approval_threshold(
+        &mut self,
+        asset: &str,
+        threshold: Option<u64>,
+    ) -> Result<Envelope, AccountingError> {
+        self.assets.require(asset)?;
+        match threshold {
+            Some(threshold) => self
+                .approval_thresholds
+                .insert(asset.to_string(), threshold),
+            None => self.approval_thresholds.remove(asset),
+        };
+
+        Ok(self.record(Tx::SetApprovalThreshold {
+            asset: asset.to_string(),
+            threshold,
+        }))
+    }
+
+    /// The amount in `asset` above which withdrawals and transfers wait for approval
+    pub fn approval_threshold(&self, asset: &str) -> Option<u64> {
+        self.approval_thresholds.get(asset).copied()
+    }
+
+    /// Runs `operation` on behalf of `operator`, who becomes the maker of any request for
+    /// approval it makes.
+    pub fn as_operator<T>(
+        &mut self,
+        operator: &str,
+        operation: impl FnOnce(&mut Accounts) -> Result<T, AccountingError>,
+    ) -> Result<T, AccountingError> {
+        let previous = self.operator.replace(operator.to_string());
+        let result = operation(self);
+        self.operator = previous;
+        result
+    }
+
+    /// Withdrawals and transfers waiting for approval, keyed by request id
+    pub fn pending_approvals(&self) -> &BTreeMap<u64, PendingApproval> {
+        &self.pending_approvals
+    }
+
+    /// Approves a request made by [`Accounts::withdraw`] or [`Accounts::send`] and makes
+    /// the withdrawal or transfer, whose envelope is returned after the
+    /// [`Tx::Approved`] one. A request that cannot be made yet keeps waiting.
+    ///
+    /// Waiting requests do not count towards velocity limits, so they are checked again.
+    ///
+    /// # Errors
+    /// - no request waits with this id
+    /// - `checker` made the request
+    /// - the same errors as [`Accounts::withdraw`] or [`Accounts::send`], checked
+    ///   against the balances once the request's funds are released
+    pub fn approve(&mut self, id: u64, checker: &str) -> Result<Envelope, AccountingError> {
+        let pending = self.pending_approval(id, checker)?.clone();
+        self.check_velocity(&pending.from, &pending.asset, pending.amount, 0)?;
+        // The approval is recorded first so replaying it frees the funds the operation spends
+        let mut scratch = self.scratch();
+        scratch.copy_account(self, &pending.from);
+        if let Some(to) = &pending.to {
+            scratch.copy_account(self, to);
+        }
+        scratch.remove_held(&pending.funds());
+        scratch.make_approved(&pending)?;
+
+        self.settle_approval(id, checker, true)?;
+        self.make_approved(&pending)
+    }
+
+    /// Rejects a request made by [`Accounts::withdraw`] or [`Accounts::send`], releasing
+    /// its funds.
+    ///
+    /// # Errors
+    /// - no request waits with this id
+    /// - `checker` made the request
+    pub fn reject(&mut self, id: u64, checker: &str) -> Result<Envelope, AccountingError> {
+        self.settle_approval(id, checker, false)
+    }
+
+    /// Holds the funds of a withdrawal, or of a transfer if there is a recipient, and
+    /// records a request for its approval with id `id`.
+    fn request_approval(
+        &mut self,
+        id: u64,
+        from: &str,
+        to: Option<&str>,
+        asset: &str,
+        amount: u64,
+        idempotency_key: Option<&str>,
+    ) -> Result<Envelope, AccountingError> {
+        self.assets.require(asset)?;
+        let on = match to {
+            Some(_) => FeeOn::Transfer,
+            None => FeeOn::Withdrawal,
+        };
+        self.fee_for(on, from, asset, amount)?;
+        self.ensure_can_spend(from, asset, amount)?;
+
+        self.last_approval_id = self.last_approval_id.max(id);
+        let pending = PendingApproval {
+            maker: self.operator.clone().unwrap_or_else(|| from.to_string()),
+            from: from.to_string(),
+            to: to.map(str::to_string),
+            asset: asset.to_string(),
+            amount,
+            idempotency_key: idempotency_key.map(str::to_string),
+        };
+        let tx = Tx::ApprovalRequested {
+            id,
+            maker: pending.maker.clone(),
+            from: pending.from.clone(),
+            to: pending.to.clone(),
+            asset: pending.asset.clone(),
+            amount,
+            idempotency_key: pending.idempotency_key.clone(),
+        };
+        self.add_held(&pending.funds());
+        self.pending_approvals.insert(id, pending);
+        let envelope = self.record(tx);
+        // Retries find the request until the withdrawal it approves replaces it
+        if let Some(key) = idempotency_key {
+            self.idempotency_keys
+                .insert(key.to_string(), envelope.clone());
+        }
+        Ok(envelope)
+    }
+
+    /// The request waiting with `id`, if `checker` may decide on it
+    fn pending_approval(
+        &self,
+        id: u64,
+        checker: &str,
+    ) -> Result<&PendingApproval, AccountingError> {
+        let pending = self
+            .pending_approvals
+            .get(&id)
+            .ok_or(AccountingError::ApprovalNotFound(id))?;
+        if pending.maker == checker {
+            return Err(AccountingError::MakerCannotApprove(checker.to_string()));
+        }
+        Ok(pending)
+    }
+
+    /// Releases the funds of a request and records `checker`'s decision on it
+    fn settle_approval(
+        &mut self,
+        id: u64,
+        checker: &str,
+        approved: bool,
+    ) -> Result<Envelope, AccountingError> {
+        self.pending_approval(id, checker)?;
+        let pending = self
+            .pending_approvals
+            .remove(&id)
+            .expect("the request was just found");
+        self.remove_held(&pending.funds());
+
+        let (account, checker) = (pending.from, checker.to_string());
+        Ok(self.record(if approved {
+            Tx::Approved {
+                id,
+                account,
+                checker,
+            }
+        } else {
+            Tx::ApprovalRejected {
+                id,
+                account,
+                checker,
+            }
+        }))
+    }
+
+    /// Makes the withdrawal or transfer of an approved request
+    fn make_approved(&mut self, pending: &PendingApproval) -> Result<Envelope, AccountingError> {
+        match &pending.to {
+            Some(to) => self
+                .send_charged(
+                    &pending.from,
+                    to,
+                    &pending.asset,
+                    pending.amount,
+                    pending.idempotency_key.as_deref(),
+                )
+                .map(|(transfer, _)| transfer),
+            None => self.make_withdrawal(
+                &pending.from,
+                &pending.asset,
+                pending.amount,
+                pending.idempotency_key.as_deref(),
+            ),
+        }
+    }
+
+    /// Fails for a withdrawal or transfer of `amount` that would need approval but cannot
+    /// wait for it
+    fn require_no_approval(&self, asset: &str, amount: u64) -> Result<(), AccountingError> {
+        match self.approval_thresholds.get(asset) {
+            Some(threshold) if self.needs_approval(asset, amount) => Err(
+                AccountingError::ApprovalRequired(asset.to_string(), *threshold),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether a withdrawal or transfer of `amount` must wait for approval; never while
+    /// replaying, where the approval is replayed separately
+    fn needs_approval(&self, asset: &str, amount: u64) -> bool {
+        !self.is_replaying()
+            && self
+                .approval_thresholds
+                .get(asset)
+                .is_some_and(|threshold| amount > *threshold)
+    }
+
+    /// Where the deposit recorded with sequence id `deposit` is in the dispute workflow;
+    /// `None` if it was never disputed
+    pub fn dispute_state(&self, deposit: u64) -> Option<DisputeState> {
+        self.disputes.get(&deposit).map(|dispute| dispute.state)
+    }
+
+    /// The `signer`'s total balance in `asset`, including held funds
+    pub fn total_balance(&self, signer: &str, asset: &str) -> u64 {
+        self.balance_of(signer, asset)
+    }
+
+    /// The part of the `signer`'s balance in `asset` that is not reserved by a hold
+    pub fn available_balance(&self, signer: &str, asset: &str) -> u64 {
+        self.accounts
+            .get(signer)
+            .map_or(0, |account| account.available(asset))
+    }
+
+    /// Explicitly opens an account with no balance.
+    ///
+    /// # Errors
+    /// - the account already exists, whatever its state
+    pub fn open(&mut self, signer: &str) -> Result<Envelope, AccountingError> {
+        if self.accounts.contains_key(signer) {
+            return Err(AccountingError::AccountAlreadyExists(signer.to_string()));
+        }
+        self.accounts.insert(signer.to_string(), Account::default());
+
+        Ok(self.record(Tx::OpenAccount {
+            account: signer.to_string(),
+        }))
+    }
+
+    /// Blocks the account from sending funds; it can still receive them.
+    ///
+    /// # Errors
+    /// - inexistent account
+    /// - the account is already frozen or closed
+    pub fn freeze(&mut self, signer: &str) -> Result<Envelope, AccountingError> {
+        self.ensure_can_send(signer)?;
+        self.set_state(signer, AccountState::Frozen);
+
+        Ok(self.record(Tx::FreezeAccount {
+            account: signer.to_string(),
+        }))
+    }
+
+    /// Lifts a freeze so the account can send funds again.
+    ///
+    /// # Errors
+    /// - inexistent account
+    /// - the account is not frozen
+    pub fn unfreeze(&mut self, signer: &str) -> Result<Envelope, AccountingError> {
+        if self.account_state(signer) != Some(AccountState::Frozen) {
+            self.ensure_can_send(signer)?;
+            return Err(AccountingError::AccountNotFrozen(signer.to_string()));
+        }
+        self.set_state(signer, AccountState::Open);
+
+        Ok(self.record(Tx::UnfreezeAccount {
+            account: signer.to_string(),
+        }))
+    }
+
+    /// Permanently closes an empty account.
+    ///
+    /// # Errors
+    /// - inexistent account
+    /// - the account is already closed
+    /// - the account still has a balance in any asset
+    pub fn close(&mut self, signer: &str) -> Result<Envelope, AccountingError> {
+        let account = self.existing(signer)?;
+        match account.state {
+            AccountState::Closed => return Err(AccountingError::AccountClosed(signer.to_string())),
+            AccountState::Locked => return Err(AccountingError::AccountLocked(signer.to_string())),
+            AccountState::Open | AccountState::Frozen => {}
+        }
+        if account.balances.values().any(|balance| *balance > 0) || !account.credit_used.is_empty()
+        {
+            return Err(AccountingError::AccountHasBalance(signer.to_string()));
+        }
+        self.set_state(signer, AccountState::Closed);
+
+        Ok(self.record(Tx::CloseAccount {
+            account: signer.to_string(),
+        }))
+    }
+
+    /// Lets the `signer`'s balance in `asset` go `limit` below zero; `0` removes the credit line.
+    ///
+    /// Lowering the limit below the credit already drawn only blocks further spending.
+    ///
+    /// # Errors
+    /// - unknown asset
+    /// - inexistent account
+    /// - the account is closed
+    pub fn set_credit_limit(
+        &mut self,
+        signer: &str,
+        asset: &str,
+        limit: u64,
+    ) -> Result<Envelope, AccountingError> {
+        self.assets.require(asset)?;
+        if self.existing(signer)?.state == AccountState::Closed {
+            return Err(AccountingError::AccountClosed(signer.to_string()));
+        }
+        if let Some(account) = self.accounts.get_mut(signer) {
+            set_or_remove(&mut account.credit_limits, asset, limit);
+        }
+
+        Ok(self.record(Tx::SetCreditLimit {
+            account: signer.to_string(),
+            asset: asset.to_string(),
+            limit,
+        }))
+    }
+
+    /// How far the `signer`'s balance in `asset` may go below zero
+    pub fn credit_limit(&self, signer: &str, asset: &str) -> u64 {
+        self.accounts
+            .get(signer)
+            .map_or(0, |account| account.credit_limit(asset))
+    }
+
+    /// How much of the `signer`'s credit line in `asset` is drawn, i.e. how far the
+    /// balance is below zero
+    pub fn credit_used(&self, signer: &str, asset: &str) -> u64 {
+        self.accounts
+            .get(signer)
+            .map_or(0, |account| account.credit_used(asset))
+    }
+
+    /// Credits interest paid on the `signer`'s positive balance in `asset`.
+    ///
+    /// # Errors
+    /// - unknown asset
+    /// - inexistent account
+    /// - the account is closed or locked
+    /// - attempted overflow
+    pub fn pay_interest(
+        &mut self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Envelope, AccountingError> {
+        self.pay_interest_with_key(signer, asset, amount, None)
+    }
+
+    /// Like [`Accounts::pay_interest`], but paying again with the same `idempotency_key`
+    /// returns the original [`Envelope`] without crediting again, e.g. when a period is
+    /// posted again after a crash.
+    ///
+    /// # Errors
+    /// - the key was already used with different parameters
+    /// - the same errors as [`Accounts::pay_interest`]
+    pub fn pay_interest_idempotent(
+        &mut self,
+        idempotency_key: &str,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Envelope, AccountingError> {
+        self.pay_interest_with_key(signer, asset, amount, Some(idempotency_key))
+    }
+
+    fn pay_interest_with_key(
+        &mut self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+        idempotency_key: Option<&str>,
+    ) -> Result<Envelope, AccountingError> {
+        let tx = Tx::InterestPaid {
+            account: signer.to_string(),
+            asset: asset.to_string(),
+            amount,
+            idempotency_key: idempotency_key.map(str::to_string),
+        };
+        if let Some(original) = self.previously_applied(&tx)? {
+            return Ok(original);
+        }
+
+        self.assets.require(asset)?;
+        self.existing(signer)?;
+        self.ensure_can_receive(signer)?;
+        self.credit(signer, asset, amount)?;
+
+        Ok(self.record(tx))
+    }
+
+    /// Debits interest charged on the `signer`'s drawn credit in `asset`.
+    ///
+    /// The charge is taken even if it goes over the credit limit or the account is frozen.
+    ///
+    /// # Errors
+    /// - unknown asset
+    /// - inexistent account
+    /// - the charge does not fit in the credit drawn
+    pub fn charge_interest(
+        &mut self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Envelope, AccountingError> {
+        self.charge_interest_with_key(signer, asset, amount, None)
+    }
+
+    /// Like [`Accounts::charge_interest`], but charging again with the same
+    /// `idempotency_key` returns the original [`Envelope`] without debiting again.
+    ///
+    /// # Errors
+    /// - the key was already used with different parameters
+    /// - the same errors as [`Accounts::charge_interest`]
+    pub fn charge_interest_idempotent(
+        &mut self,
+        idempotency_key: &str,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<Envelope, AccountingError> {
+        self.charge_interest_with_key(signer, asset, amount, Some(idempotency_key))
+    }
+
+    fn charge_interest_with_key(
+        &mut self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+        idempotency_key: Option<&str>,
+    ) -> Result<Envelope, AccountingError> {
+        let tx = Tx::InterestCharged {
+            account: signer.to_string(),
+            asset: asset.to_string(),
+            amount,
+            idempotency_key: idempotency_key.map(str::to_string),
+        };
+        if let Some(original) = self.previously_applied(&tx)? {
+            return Ok(original);
+        }
+
+        self.assets.require(asset)?;
+        let account = self.existing(signer)?;
+        let shortfall = amount.saturating_sub(account.balance(asset));
+        account
+            .credit_used(asset)
+            .checked_add(shortfall)
+            .ok_or_else(|| AccountingError::AccountUnderFunded(signer.to_string(), amount))?;
+        self.debit(signer, asset, amount);
+
+        Ok(self.record(tx))
+    }
+
+    /// The `signer`'s balance in every asset it has used; empty if the account does not exist
+    pub fn balance(&self, signer: &str) -> BTreeMap<&str, Balance> {
+        self.account(signer)
+            .map(|summary| summary.balances)
+            .unwrap_or_default()
+    }
+
+    /// A view of the `signer`'s account; `None` if it does not exist
+    pub fn account(&self, signer: &str) -> Option<AccountSummary<'_>> {
+        self.accounts
+            .get_key_value(signer)
+            .map(|(signer, account)| summarize(signer, account))
+    }
+
+    /// Every account, in the given `order`
+    pub fn list_accounts(&self, order: AccountOrder) -> Vec<AccountSummary<'_>> {
+        let mut summaries = self
+            .accounts
+            .iter()
+            .map(|(signer, account)| summarize(signer, account))
+            .collect::<Vec<_>>();
+        match order {
+            AccountOrder::Name => summaries.sort_by_key(|summary| summary.signer),
+            AccountOrder::Balance(asset) => summaries.sort_by_key(|summary| {
+                let net = summary.balances.get(asset).map_or(0, Balance::net);
+                (Reverse(net), summary.signer)
+            }),
+        }
+        summaries
+    }
+
+    /// The net balance of every account in `asset`, system accounts included
+    pub fn total_supply(&self, asset: &str) -> i128 {
+        self.accounts
+            .values()
+            .map(|account| {
+                i128::from(account.balance(asset)) - i128::from(account.credit_used(asset))
+            })
+            .sum()
+    }
+
+    /// Up to `limit` envelopes touching the `signer`'s account with a sequence id after
+    /// `after`, oldest first; pass `0` for the first page.
+    pub fn account_history(&self, signer: &str, after: u64, limit: usize) -> HistoryPage<'_> {
+        let positions = self
+            .history_index
+            .get(signer)
+            .map_or(&[][..], Vec::as_slice);
+        let start = positions.partition_point(|position| self.history[*position].seq <= after);
+        let envelopes = positions[start..]
+            .iter()
+            .take(limit)
+            .map(|position| &self.history[*position])
+            .collect::<Vec<_>>();
+        let next_after = match envelopes.last() {
+            Some(last) if start + envelopes.len() < positions.len() => Some(last.seq),
+            _ => None,
+        };
+        HistoryPage {
+            envelopes,
+            next_after,
+        }
+    }
+
+    /// The ledger as it was at `point`, rebuilt by replaying the history from the
+    /// closest earlier checkpoint. The returned ledger has no history of its own.
+    ///
+    /// # Errors
+    /// - [`AccountingError::HistoryUnavailable`] for a ledger decoded from a snapshot whose
+    ///   history was not restored
+    /// - the first error returned by [`Accounts::apply`], which only happens if the
+    ///   history does not replay onto an empty ledger
+    pub fn as_of(&self, point: AsOf) -> Result<Accounts, AccountingError> {
+        if self.history_missing_until > 0 {
+            return Err(AccountingError::HistoryUnavailable(
+                self.history_missing_until,
+            ));
+        }
+        let end = match point {
+            AsOf::Seq(seq) => self.history.partition_point(|envelope| envelope.seq <= seq),
+            AsOf::Timestamp(timestamp) => self
+                .history
+                .partition_point(|envelope| envelope.timestamp <= timestamp),
+        };
+
+        let mut checkpoints = self.checkpoints.states();
+        let (start, mut past) = match checkpoints.range(..=end).next_back() {
+            Some((&start, state)) => (start, Accounts::clone(state)),
+            None => (0, self.empty_like()),
+        };
+        past.discard_history();
+        for (position, envelope) in (start..end).zip(&self.history[start..end]) {
+            past.apply(envelope)?;
+            if (position + 1) % CHECKPOINT_EVERY == 0 {
+                checkpoints.insert(position + 1, Arc::new(past.clone()));
+            }
+        }
+        Ok(past)
+    }
+
+    /// The `signer`'s balance in every asset at `point`; empty if the account did not
+    /// exist yet.
+    ///
+    /// # Errors
+    /// - the same errors as [`Accounts::as_of`]
+    pub fn balance_as_of(
+        &self,
+        signer: &str,
+        point: AsOf,
+    ) -> Result<BTreeMap<String, Balance>, AccountingError> {
+        Ok(self
+            .as_of(point)?
+            .balance(signer)
+            .into_iter()
+            .map(|(asset, balance)| (asset.to_string(), balance))
+            .collect())
+    }
+
+    /// The lifecycle state of the `signer`'s account; `None` if it does not exist
+    pub fn account_state(&self, signer: &str) -> Option<AccountState> {
+        self.accounts.get(signer).map(|account| account.state)
+    }
+
+    fn existing(&self, signer: &str) -> Result<&Account, AccountingError> {
+        self.accounts
+            .get(signer)
+            .ok_or_else(|| AccountingError::AccountNotFound(signer.to_string()))
+    }
+
+    fn set_state(&mut self, signer: &str, state: AccountState) {
+        if let Some(account) = self.accounts.get_mut(signer) {
+            account.state = state;
+        }
+    }
+
+    /// Checks that `signer` exists and may send funds
+    fn ensure_can_send(&self, signer: &str) -> Result<&Account, AccountingError> {
+        let account = self.existing(signer)?;
+        match account.state {
+            AccountState::Open => Ok(account),
+            AccountState::Frozen => Err(AccountingError::AccountFrozen(signer.to_string())),
+            AccountState::Closed => Err(AccountingError::AccountClosed(signer.to_string())),
+            AccountState::Locked => Err(AccountingError::AccountLocked(signer.to_string())),
+        }
+    }
+
+    /// Checks that `signer` may receive funds; accounts that do not exist yet can
+    fn ensure_can_receive(&self, signer: &str) -> Result<(), AccountingError> {
+        match self.account_state(signer) {
+            Some(AccountState::Closed) => Err(AccountingError::AccountClosed(signer.to_string())),
+            Some(AccountState::Locked) => Err(AccountingError::AccountLocked(signer.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that `signer` can spend `amount` of `asset`, drawing on its credit line if it has one.
+    fn ensure_can_spend(
+        &self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<(), AccountingError> {
+        let account = self.ensure_can_send(signer)?;
+        if account.credit_limit(asset) > 0 {
+            let spendable = account.spendable(asset);
+            if amount > spendable {
+                return Err(AccountingError::CreditLimitExceeded(
+                    signer.to_string(),
+                    amount - spendable,
+                ));
+            }
+            return Ok(());
+        }
+
+        if amount > account.balance(asset) {
+            return Err(AccountingError::AccountUnderFunded(
+                signer.to_string(),
+                amount,
+            ));
+        }
+        if amount > account.available(asset) {
+            return Err(AccountingError::InsufficientAvailableFunds(
+                signer.to_string(),
+                amount,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that crediting `amount` to `signer` cannot overflow its balance
+    fn ensure_can_credit(
+        &self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+    ) -> Result<(), AccountingError> {
+        let Some(account) = self.accounts.get(signer) else {
+            return Ok(());
+        };
+        let repaid = amount.min(account.credit_used(asset));
+        account
+            .balance(asset)
+            .checked_add(amount - repaid)
+            .map(|_| ())
+            .ok_or_else(|| AccountingError::AccountOverFunded(signer.to_string(), amount))
+    }
+
+    fn insert_hold(&mut self, id: u64, signer: &str, asset: &str, amount: u64) {
+        let hold = Hold {
+            account: signer.to_string(),
+            asset: asset.to_string(),
+            amount,
+        };
+        self.add_held(&hold);
+        self.holds.insert(id, hold);
+    }
+
+    fn remove_hold(&mut self, id: u64) -> Result<Hold, AccountingError> {
+        let hold = self
+            .holds
+            .remove(&id)
+            .ok_or(AccountingError::HoldNotFound(id))?;
+        self.remove_held(&hold);
+        Ok(hold)
+    }
+
+    /// Adds `funds` to the held total of their account
+    fn add_held(&mut self, funds: &Hold) {
+        *self
+            .accounts
+            .entry(funds.account.clone())
+            .or_default()
+            .held
+            .entry(funds.asset.clone())
+            .or_default() += funds.amount;
+    }
+
+    /// Takes `funds` off the held total of their account
+    fn remove_held(&mut self, funds: &Hold) {
+        if let Some(account) = self.accounts.get_mut(&funds.account) {
+            if let Some(held) = account.held.get_mut(&funds.asset) {
+                *held -= funds.amount;
+                if *held == 0 {
+                    account.held.remove(&funds.asset);
+                }
+            }
+        }
+    }
+
+    /// The envelope with sequence id `seq`, if it is in the history
+    fn recorded(&self, seq: u64) -> Option<&Envelope> {
+        self.history
+            .binary_search_by_key(&seq, |envelope| envelope.seq)
+            .ok()
+            .map(|position| &self.history[position])
+    }
+
+    /// Moves the funds of `tx`, recorded with sequence id `original`, back where they came
+    /// from and records the [`Tx::Reversal`]
+    fn reverse_tx(&mut self, original: u64, tx: &Tx) -> Result<Envelope, AccountingError> {
+        if self.reversed.contains(&original) {
+            return Err(AccountingError::TxAlreadyReversed(original));
+        }
+        // The accounts the funds are taken back from and returned to; `None` outside the ledger
+        let (debited, credited, asset, amount) = match tx {
+            Tx::Deposit {
+                account,
+                asset,
+                amount,
+                ..
+            } => {
+                if self.disputes.contains_key(&original) {
+                    return Err(AccountingError::DepositAlreadyDisputed(original));
+                }
+                (Some(account.as_str()), None, asset, *amount)
+            }
+            Tx::Withdraw {
+                account,
+                asset,
+                amount,
+                ..
+            } => (None, Some(account.as_str()), asset, *amount),
+            Tx::Transfer {
+                from,
+                to,
+                asset,
+                amount,
+                ..
+            } => (Some(to.as_str()), Some(from.as_str()), asset, *amount),
+            Tx::Fee {
+                account,
+                asset,
+                amount,
+            } => (Some(FEE_ACCOUNT), Some(account.as_str()), asset, *amount),
+            _ => return Err(AccountingError::TxNotReversible(original)),
+        };
+
+        if let Some(debited) = debited {
+            let available = self
+                .accounts
+                .get(debited)
+                .map_or(0, |account| account.available(asset));
+            if amount > available {
+                return Err(AccountingError::TxFundsSpent(original));
+            }
+        }
+        if let Some(credited) = credited {
+            self.ensure_can_receive(credited)?;
+            self.ensure_can_credit(credited, asset, amount)?;
+        }
+        if let Some(debited) = debited {
+            self.debit(debited, asset, amount);
+        }
+        if let Some(credited) = credited {
+            self.credit(credited, asset, amount)?;
+        }
+        self.reversed.insert(original);
+
+        Ok(self.record(Tx::Reversal {
+            original,
+            tx: Box::new(tx.clone()),
+        }))
+    }
+
+    /// Marks the open dispute of a deposit as settled with `outcome` and releases its
+    /// funds from the held total
+    fn settle_dispute(
+        &mut self,
+        deposit: u64,
+        outcome: DisputeState,
+    ) -> Result<Hold, AccountingError> {
+        let dispute = self
+            .disputes
+            .get_mut(&deposit)
+            .filter(|dispute| dispute.state == DisputeState::Open)
+            .ok_or(AccountingError::DepositNotDisputed(deposit))?;
+        dispute.state = outcome;
+        let funds = dispute.funds.clone();
+        self.remove_held(&funds);
+        Ok(funds)
+    }
+
+    /// Wraps an applied `tx` in the next [`Envelope`], remembers its idempotency key
+    /// and posts it to the double-entry books, if enabled, before returning it
+    fn record(&mut self, tx: Tx) -> Envelope {
+        let stamp = self.stamp.clone().unwrap_or_default();
+        let (seq, timestamp) = stamp
+            .recorded
+            .unwrap_or_else(|| (self.last_seq + 1, self.clock.now().max(self.last_timestamp)));
+        self.last_seq = seq;
+        self.last_timestamp = timestamp;
+        let envelope = Envelope {
+            seq,
+            timestamp,
+            details: stamp.details,
+            tx,
+        };
+        self.push_history(envelope.clone());
+
+        if let Some(key) = envelope.tx.idempotency_key() {
+            self.idempotency_keys
+                .insert(key.to_string(), envelope.clone());
+        }
+        if let Some(general_ledger) = &mut self.general_ledger {
+            general_ledger.post(&envelope.tx);
+        }
+        envelope
+    }
+
+    /// Appends to the history and indexes the envelope under every account it touches
+    fn push_history(&mut self, envelope: Envelope) {
+        if self.history_discarded {
+            return;
+        }
+        let position = self.history.len();
+        for signer in envelope.tx.accounts() {
+            let positions = self.history_index.entry(signer.to_string()).or_default();
+            if positions.last() != Some(&position) {
+                positions.push(position);
+            }
+        }
+        self.history.push(envelope);
+    }
+
+    /// The `signer`'s balance in `asset`; `0` if either does not exist
+    fn balance_of(&self, signer: &str, asset: &str) -> u64 {
+        self.accounts
+            .get(signer)
+            .map_or(0, |account| account.balance(asset))
+    }
+
+    /// Adds `amount` to a balance, repaying any drawn credit first and opening the
+    /// account if needed.
+    ///
+    /// # Errors
+    /// - the balance would overflow; nothing is changed
+    fn credit(&mut self, signer: &str, asset: &str, amount: u64) -> Result<(), AccountingError> {
+        self.ensure_can_credit(signer, asset, amount)?;
+        let account = self.accounts.entry(signer.to_string()).or_default();
+        let repaid = amount.min(account.credit_used(asset));
+        let used = account.credit_used(asset) - repaid;
+        set_or_remove(&mut account.credit_used, asset, used);
+        let balance = account.balance(asset) + (amount - repaid);
+        account.balances.insert(asset.to_string(), balance);
+        Ok(())
+    }
+
+    /// Takes `amount`, checked by [`Accounts::ensure_can_spend`], out of a balance and
+    /// draws any shortfall from the credit line
+    fn debit(&mut self, signer: &str, asset: &str, amount: u64) {
+        let account = self.accounts.entry(signer.to_string()).or_default();
+        let balance = account.balance(asset);
+        let drawn = amount.saturating_sub(balance);
+        account
+            .balances
+            .insert(asset.to_string(), balance - (amount - drawn));
+        let used = account.credit_used(asset) + drawn;
+        set_or_remove(&mut account.credit_used, asset, used);
+    }
+
+    /// Applies a previously recorded [`Envelope`] to the accounts, keeping its
+    /// sequence id, timestamp and details.
+    ///
+    /// # Errors
+    /// - the envelope's sequence id is not after [`Accounts::last_seq`]
+    /// - the same errors as the operation that produced the transaction
+    pub fn apply(&mut self, envelope: &Envelope) -> Result<(), AccountingError> {
+        if envelope.seq <= self.last_seq {
+            return Err(AccountingError::EnvelopeOutOfOrder(envelope.seq));
+        }
+        let stamp = Stamp {
+            recorded: Some((envelope.seq, envelope.timestamp)),
+            details: envelope.details.clone(),
+        };
+        self.stamped(stamp, |accounts| accounts.apply_tx(&envelope.tx))
+    }
+
+    fn apply_tx(&mut self, tx: &Tx) -> Result<(), AccountingError> {
+        match tx {
+            Tx::Deposit {
+                account,
+                asset,
+                amount,
+                idempotency_key,
+            } => self
+                .deposit_with_key(account, asset, *amount, idempotency_key.as_deref())
+                .map(|_| ()),
+            Tx::Withdraw {
+                account,
+                asset,
+                amount,
+                idempotency_key,
+            } => self
+                .withdraw_with_key(account, asset, *amount, idempotency_key.as_deref())
+                .map(|_| ()),
+            Tx::Transfer {
+                id,
+                from,
+                to,
+                asset,
+                amount,
+                ..
+            } => {
+                self.transfer(from, to, asset, *amount)?;
+                self.last_transfer_id = self.last_transfer_id.max(*id);
+                self.record(tx.clone());
+                Ok(())
+            }
+            Tx::RegisterAsset { asset, decimals } => {
+                self.register_asset(asset, *decimals).map(|_| ())
+            }
+            Tx::Hold {
+                id,
+                account,
+                asset,
+                amount,
+            } => {
+                self.assets.require(asset)?;
+                self.ensure_can_spend(account, asset, *amount)?;
+                self.insert_hold(*id, account, asset, *amount);
+                self.last_hold_id = self.last_hold_id.max(*id);
+                self.record(tx.clone());
+                Ok(())
+            }
+            Tx::Release { id, .. } => self.release(*id).map(|_| ()),
+            Tx::Capture { id, .. } => self.capture(*id).map(|_| ()),
+            Tx::OpenAccount { account } => self.open(account).map(|_| ()),
+            Tx::FreezeAccount { account } => self.freeze(account).map(|_| ()),
+            Tx::UnfreezeAccount { account } => self.unfreeze(account).map(|_| ()),
+            Tx::CloseAccount { account } => self.close(account).map(|_| ()),
+            Tx::Fee {
+                account,
+                asset,
+                amount,
+            } => self.charge_fee(account, asset, *amount).map(|_| ()),
+            Tx::SetFee { on, asset, fee } => self.set_fee(*on, asset, fee.clone()).map(|_| ()),
+            Tx::SetCreditLimit {
+                account,
+                asset,
+                limit,
+            } => self.set_credit_limit(account, asset, *limit).map(|_| ()),
+            Tx::InterestPaid {
+                account,
+                asset,
+                amount,
+                idempotency_key,
+            } => self
+                .pay_interest_with_key(account, asset, *amount, idempotency_key.as_deref())
+                .map(|_| ()),
+            Tx::InterestCharged {
+                account,
+                asset,
+                amount,
+                idempotency_key,
+            } => self
+                .charge_interest_with_key(account, asset, *amount, idempotency_key.as_deref())
+                .map(|_| ()),
+            // The deposit may be older than the history this ledger holds
+            Tx::Dispute {
+                deposit,
+                account,
+                asset,
+                amount,
+            } => self
+                .dispute_deposit(*deposit, account, asset, *amount)
+                .map(|_| ()),
+            Tx::Resolve { deposit, .. } => self.resolve(*deposit).map(|_| ()),
+            Tx::Chargeback { deposit, .. } => self.chargeback(*deposit).map(|_| ()),
+            // The original may be missing from a ledger without history, so its copy is used
+            Tx::Reversal { original, tx } => self.reverse_tx(*original, tx).map(|_| ()),
+            Tx::SetApprovalThreshold { asset, threshold } => {
+                self.set_approval_threshold(asset, *threshold).map(|_| ())
+            }
+            Tx::ApprovalRequested {
+                id,
+                maker,
+                from,
+                to,
+                asset,
+                amount,
+                idempotency_key,
+            } => self
+                .as_operator(maker, |ledger| {
+                    ledger.request_approval(
+                        *id,
+                        from,
+                        to.as_deref(),
+                        asset,
+                        *amount,
+                        idempotency_key.as_deref(),
+                    )
+                })
+                .map(|_| ()),
+            // The operation approved follows as its own envelope
+            Tx::Approved { id, checker, .. } => {
+                self.settle_approval(*id, checker, true).map(|_| ())
+            }
+            Tx::ApprovalRejected { id, checker, .. } => {
+                self.settle_approval(*id, checker, false).map(|_| ())
+            }
+        }
+    }
+
+    /// Rebuilds an [`Accounts`] instance by applying `envelopes` in sequence to an empty state.
+    ///
+    /// # Errors
+    /// - the first error returned by [`Accounts::apply`]
+    pub fn replay(envelopes: impl IntoIterator<Item = Envelope>) -> Result<Self, AccountingError> {
+        Accounts::replay_from(Accounts::new(), envelopes)
+    }
+
+    /// An empty ledger with the same bookkeeping mode and clock as this one
+    fn empty_like(&self) -> Accounts {
+        let empty = match self.general_ledger {
+            Some(_) => Accounts::with_double_entry(),
+            None => Accounts::new(),
+        };
+        Accounts {
+            clock: self.clock.clone(),
+            velocity: self.velocity.clone(),
+            ..empty
+        }
+    }
+
+    /// Applies `envelopes` in sequence on top of `base`, e.g. a state loaded from a snapshot.
+    ///
+    /// # Errors
+    /// - the first error returned by [`Accounts::apply`]
+    pub fn replay_from(
+        mut base: Accounts,
+        envelopes: impl IntoIterator<Item = Envelope>,
+    ) -> Result<Self, AccountingError> {
+        for envelope in envelopes {
+            base.apply(&envelope)?;
+        }
+        Ok(base)
+    }
+
+    /// Replays `envelopes` onto an empty ledger and returns whether the result matches the
+    /// current state.
+    ///
+    /// # Errors
+    /// - the log cannot be replayed (see [`Accounts::replay`])
+    pub fn verify_against(
+        &self,
+        envelopes: impl IntoIterator<Item = Envelope>,
+    ) -> Result<bool, AccountingError> {
+        Ok(Accounts::replay_from(self.empty_like(), envelopes)? == *self)
+    }
+}
+
+impl Encode for Accounts {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.assets.encode(encoder);
+
+        // Sorting keeps the encoding of equal ledgers byte-for-byte identical
+        let mut accounts = self.accounts.iter().collect::<Vec<_>>();
+        accounts.sort_by_key(|(signer, _)| *signer);
+
+        encoder.put_u64(accounts.len() as u64);
+        for (signer, account) in accounts {
+            encoder.put_str(signer);
+            account.state.encode(encoder);
+            for figures in [
+                &account.balances,
+                &account.credit_limits,
+                &account.credit_used,
+            ] {
+                encoder.put_u64(figures.len() as u64);
+                for (asset, value) in figures {
+                    encoder.put_str(asset);
+                    encoder.put_u64(*value);
+                }
+            }
+        }
+        encoder.put_u64(self.last_transfer_id);
+
+        match &self.general_ledger {
+            Some(general_ledger) => {
+                encoder.put_u8(1);
+                general_ledger.encode(encoder);
+            }
+            None => encoder.put_u8(0),
+        }
+
+        // Per-account held totals are rebuilt from the holds when decoding
+        encoder.put_u64(self.holds.len() as u64);
+        for (id, hold) in &self.holds {
+            encoder.put_u64(*id);
+            encoder.put_str(&hold.account);
+            encoder.put_str(&hold.asset);
+            encoder.put_u64(hold.amount);
+        }
+        encoder.put_u64(self.last_hold_id);
+
+        let mut idempotency_keys = self.idempotency_keys.iter().collect::<Vec<_>>();
+        idempotency_keys.sort_by_key(|(key, _)| *key);
+        encoder.put_u64(idempotency_keys.len() as u64);
+        for (key, envelope) in idempotency_keys {
+            encoder.put_str(key);
+            envelope.encode(encoder);
+        }
+        encoder.put_u64(self.last_seq);
+        encoder.put_u64(self.last_timestamp);
+        self.fees.encode(encoder);
+
+        // Held totals of open disputes are rebuilt when decoding, like those of holds
+        encoder.put_u64(self.disputes.len() as u64);
+        for (deposit, dispute) in &self.disputes {
+            encoder.put_u64(*deposit);
+            encoder.put_str(&dispute.funds.account);
+            encoder.put_str(&dispute.funds.asset);
+            encoder.put_u64(dispute.funds.amount);
+            dispute.state.encode(encoder);
+        }
+
+        encoder.put_u64(self.reversed.len() as u64);
+        for original in &self.reversed {
+            encoder.put_u64(*original);
+        }
+
+        encoder.put_u64(self.approval_thresholds.len() as u64);
+        for (asset, threshold) in &self.approval_thresholds {
+            encoder.put_str(asset);
+            encoder.put_u64(*threshold);
+        }
+        // Held totals of waiting requests are rebuilt when decoding, like those of holds
+        encoder.put_u64(self.pending_approvals.len() as u64);
+        for (id, pending) in &self.pending_approvals {
+            encoder.put_u64(*id);
+            encoder.put_str(&pending.maker);
+            encoder.put_str(&pending.from);
+            encoder.put_option_str(pending.to.as_deref());
+            encoder.put_str(&pending.asset);
+            encoder.put_u64(pending.amount);
+            encoder.put_option_str(pending.idempotency_key.as_deref());
+        }
+        encoder.put_u64(self.last_approval_id);
+    }
+}
+
+impl Encode for AccountState {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_u8(match self {
+            AccountState::Open => 0,
+            AccountState::Frozen => 1,
+            AccountState::Closed => 2,
+            AccountState::Locked => 3,
+        });
+    }
+}
+
+impl Decode for AccountState {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        match decoder.get_u8()? {
+            0 => Ok(AccountState::Open),
+            1 => Ok(AccountState::Frozen),
+            2 => Ok(AccountState::Closed),
+            3 => Ok(AccountState::Locked),
+            _ => Err(invalid_data("unknown account state")),
+        }
+    }
+}
+
+impl Encode for DisputeState {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_u8(match self {
+            DisputeState::Open => 0,
+            DisputeState::Resolved => 1,
+            DisputeState::ChargedBack => 2,
+        });
+    }
+}
+
+impl Decode for DisputeState {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        match decoder.get_u8()? {
+            0 => Ok(DisputeState::Open),
+            1 => Ok(DisputeState::Resolved),
+            2 => Ok(DisputeState::ChargedBack),
+            _ => Err(invalid_data("unknown dispute state")),
+        }
+    }
+}
+
+impl Decode for Accounts {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        let assets = AssetRegistry::decode(decoder)?;
+
+        let mut balances = HashMap::new();
+        for _ in 0..decoder.get_u64()? {
+            let signer = decoder.get_str()?;
+            let mut account = Account {
+                state: AccountState::decode(decoder)?,
+                ..Account::default()
+            };
+            for figures in [
+                &mut account.balances,
+                &mut account.credit_limits,
+                &mut account.credit_used,
+            ] {
+                for _ in 0..decoder.get_u64()? {
+                    let asset = decoder.get_str()?;
+                    figures.insert(asset, decoder.get_u64()?);
+                }
+            }
+            balances.insert(signer, account);
+        }
+
+        let last_transfer_id = decoder.get_u64()?;
+        let general_ledger = match decoder.get_u8()? {
+            0 => None,
+            _ => Some(GeneralLedger::decode(decoder)?),
+        };
+
+        let mut accounts = Accounts {
+            accounts: balances,
+            assets,
+            last_transfer_id,
+            general_ledger,
+            ..Accounts::new()
+        };
+        for _ in 0..decoder.get_u64()? {
+            let id = decoder.get_u64()?;
+            let signer = decoder.get_str()?;
+            let asset = decoder.get_str()?;
+            let amount = decoder.get_u64()?;
+            accounts.insert_hold(id, &signer, &asset, amount);
+        }
+        accounts.last_hold_id = decoder.get_u64()?;
+
+        for _ in 0..decoder.get_u64()? {
+            let key = decoder.get_str()?;
+            accounts
+                .idempotency_keys
+                .insert(key, Envelope::decode(decoder)?);
+        }
+        accounts.last_seq = decoder.get_u64()?;
+        accounts.history_missing_until = accounts.last_seq;
+        accounts.last_timestamp = decoder.get_u64()?;
+        accounts.fees = FeeSchedule::decode(decoder)?;
+
+        for _ in 0..decoder.get_u64()? {
+            let deposit = decoder.get_u64()?;
+            let dispute = Dispute {
+                funds: Hold {
+                    account: decoder.get_str()?,
+                    asset: decoder.get_str()?,
+                    amount: decoder.get_u64()?,
+                },
+                state: DisputeState::decode(decoder)?,
+            };
+            if dispute.state == DisputeState::Open {
+                accounts.add_held(&dispute.funds);
+            }
+            accounts.disputes.insert(deposit, dispute);
+        }
+
+        for _ in 0..decoder.get_u64()? {
+            accounts.reversed.insert(decoder.get_u64()?);
+        }
+
+        for _ in 0..decoder.get_u64()? {
+            let asset = decoder.get_str()?;
+            accounts
+                .approval_thresholds
+                .insert(asset, decoder.get_u64()?);
+        }
+        for _ in 0..decoder.get_u64()? {
+            let id = decoder.get_u64()?;
+            let pending = PendingApproval {
+                maker: decoder.get_str()?,
+                from: decoder.get_str()?,
+                to: decoder.get_option_str()?,
+                asset: decoder.get_str()?,
+                amount: decoder.get_u64()?,
+                idempotency_key: decoder.get_option_str()?,
+            };
+            accounts.add_held(&pending.funds());
+            accounts.pending_approvals.insert(id, pending);
+        }
+        accounts.last_approval_id = decoder.get_u64()?;
+        Ok(accounts)
+    }
+}
+
+/// Whether `tx` is a retry of `original`: the same transaction, though a transfer gets a new
+/// id, or the withdrawal or transfer a request for approval was recorded for
+fn is_retry_of(original: &Tx, tx: &Tx) -> bool {
+    match (original, tx) {
+        (
+            Tx::Transfer {
+                from,
+                to,
+                asset,
+                amount,
+                idempotency_key,
+                ..
+            }
+            | Tx::ApprovalRequested {
+                from,
+                to: Some(to),
+                asset,
+                amount,
+                idempotency_key,
+                ..
+            },
+            Tx::Transfer {
+                from: sender,
+                to: recipient,
+                asset: sent,
+                amount: requested,
+                idempotency_key: key,
+                ..
+            },
+        ) => {
+            from == sender
+                && to == recipient
+                && asset == sent
+                && amount == requested
+                && idempotency_key == key
+        }
+        (
+            Tx::ApprovalRequested {
+                from,
+                to: None,
+                asset,
+                amount,
+                idempotency_key,
+                ..
+            },
+            Tx::Withdraw {
+                account,
+                asset: withdrawn,
+                amount: requested,
+                idempotency_key: key,
+            },
+        ) => from == account && asset == withdrawn && amount == requested && idempotency_key == key,
+        _ => original == tx,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        clock::ManualClock,
+        codec::{Decode, Decoder, Encode, Encoder},
+        errors::AccountingError,
+        fees::{Fee, FeeOn, FeeRule, FEE_ACCOUNT},
+        test_utils::{accounts_at, accounts_with_assets, BTC, USD},
+        tx::{Details, Envelope, Tx},
+        velocity::{VelocityRules, WINDOW},
+    };
+
+    use super::{
+        AccountOrder, AccountState, Accounts, AsOf, Balance, DisputeState, Leg, CHECKPOINT_EVERY,
+    };
+
+    /// An envelope for a hand-written log entry
+    fn envelope(seq: u64, tx: Tx) -> Envelope {
+        Envelope {
+            seq,
+            timestamp: 0,
+            details: Details::default(),
+            tx,
+        }
+    }
+
+    #[test]
+    fn when_a_new_user_makes_a_deposit_it_is_added_in_accounts() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        let deposit = 100;
+
+        // Act
+        let sut = accounts.deposit(signer, USD, deposit);
+
+        // Assert
+        assert_eq!(
+            Tx::Deposit {
+                account: signer.to_string(),
+                asset: USD.to_string(),
+                amount: deposit,
+                idempotency_key: None,
+            },
+            sut.unwrap().tx
+        );
+        assert_eq!(accounts.balance_of(signer, USD), deposit);
+    }
+
+    #[test]
+    fn when_an_existent_user_makes_a_transaction_the_amount_is_correctly_updated() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        let first_deposit = 100;
+        let second_deposit = 150;
+
+        accounts
+            .deposit(signer, USD, first_deposit)
+            .expect("first deposit failed");
+
+        // Act
+        let sut = accounts.deposit(signer, USD, second_deposit);
+
+        // Assert
+        assert_eq!(
+            Tx::Deposit {
+                account: signer.to_string(),
+                asset: USD.to_string(),
+                amount: second_deposit,
+                idempotency_key: None,
+            },
+            sut.unwrap().tx
+        );
+        assert_eq!(
+            accounts.balance_of(signer, USD),
+            first_deposit + second_deposit
+        );
+    }
+
+    #[test]
+    fn errors_when_a_deposit_causes_an_overflow() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        let first_deposit = 100;
+        let second_deposit = u64::MAX;
+
+        accounts
+            .deposit(signer, USD, first_deposit)
+            .expect("deposit failed");
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let sut = accounts.deposit(signer, USD, second_deposit);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::AccountOverFunded(
+                signer.to_string(),
+                second_deposit
+            )),
+            sut
+        );
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn balances_in_different_assets_are_kept_apart() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+
+        // Act
+        accounts.deposit(signer, USD, 100).expect("deposit failed");
+        accounts.deposit(signer, BTC, 7).expect("deposit failed");
+        accounts.withdraw(signer, USD, 40).expect("withdraw failed");
+
+        // Assert
+        assert_eq!(accounts.balance_of(signer, USD), 60);
+        assert_eq!(accounts.balance_of(signer, BTC), 7);
+    }
+
+    #[test]
+    fn errors_when_using_an_unknown_asset() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        accounts
+            .deposit("client_1", USD, 100)
+            .expect("deposit failed");
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let deposit = accounts.deposit("client_1", "EUR", 100);
+        let withdraw = accounts.withdraw("client_1", "EUR", 100);
+        let send = accounts.send("client_1", "client_2", "EUR", 100);
+
+        // Assert
+        let unknown = Err(AccountingError::UnknownAsset("EUR".to_string()));
+        assert_eq!(unknown, deposit);
+        assert_eq!(unknown, withdraw);
+        assert_eq!(unknown, send);
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn withdrawing_correctly_updates_the_account_on_the_happy_path() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        let deposit = 100;
+        let withdraw = 50;
+
+        accounts
+            .deposit(signer, USD, deposit)
+            .expect("deposit failed");
+
+        // Act
+        let sut = accounts.withdraw(signer, USD, withdraw);
+
+        // Assert
+        assert_eq!(
+            Tx::Withdraw {
+                account: signer.to_string(),
+                asset: USD.to_string(),
+                amount: withdraw,
+                idempotency_key: None,
+            },
+            sut.unwrap().tx
+        );
+        assert_eq!(accounts.balance_of(signer, USD), deposit - withdraw);
+    }
+
+    #[test]
+    fn errors_when_withdrawing_from_a_nonexistent_account() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        let withdraw = 100;
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let sut = accounts.withdraw(signer, USD, withdraw);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::AccountNotFound(signer.to_string())),
+            sut
+        );
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn errors_when_withdrawing_more_than_is_available() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        let deposit = 100;
+        let withdraw = 200;
+
+        accounts
+            .deposit(signer, USD, deposit)
+            .expect("deposit failed");
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let sut = accounts.withdraw(signer, USD, withdraw);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::AccountUnderFunded(
+                signer.to_string(),
+                withdraw
+            )),
+            sut
+        );
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn sending_money_correctly_updates_both_accounts_on_the_happy_path() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+
+        let sender = "client_1";
+        let sender_deposit = 100;
+        accounts
+            .deposit(sender, USD, sender_deposit)
+            .expect("deposit failed");
+
+        let recipient = "client_2";
+
+        let transferred_amount = 50;
+
+        // Act
+        let sut = accounts.send(sender, recipient, USD, transferred_amount);
+
+        // Assert
+        assert_eq!(
+            Tx::Transfer {
+                id: 1,
+                from: sender.to_string(),
+                to: recipient.to_string(),
+                asset: USD.to_string(),
+                amount: transferred_amount,
+                idempotency_key: None,
+            },
+            sut.unwrap().tx
+        );
+        assert_eq!(
+            accounts.balance_of(sender, USD),
+            sender_deposit - transferred_amount
+        );
+        assert_eq!(accounts.balance_of(recipient, USD), transferred_amount);
+    }
+
+    #[test]
+    fn errors_when_sending_from_a_nonexistent_account() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let sender = "client_1";
+        let recipient = "client_2";
+        accounts
+            .deposit(recipient, USD, 100)
+            .expect("deposit failed");
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let sut = accounts.send(sender, recipient, USD, 50);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::AccountNotFound(sender.to_string())),
+            sut
+        );
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn errors_when_sending_more_than_is_available() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let sender = "client_1";
+        let recipient = "client_2";
+        let transferred_amount = 200;
+        accounts.deposit(sender, USD, 100).expect("deposit failed");
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let sut = accounts.send(sender, recipient, USD, transferred_amount);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::AccountUnderFunded(
+                sender.to_string(),
+                transferred_amount
+            )),
+            sut
+        );
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn errors_and_rolls_back_when_sending_overflows_the_recipient() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let sender = "client_1";
+        let recipient = "client_2";
+        let transferred_amount = 100;
+        accounts.deposit(sender, USD, 100).expect("deposit failed");
+        accounts
+            .deposit(recipient, USD, u64::MAX)
+            .expect("deposit failed");
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let sut = accounts.send(sender, recipient, USD, transferred_amount);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::AccountOverFunded(
+                recipient.to_string(),
+                transferred_amount
+            )),
+            sut
+        );
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn sending_to_yourself_leaves_the_balance_unchanged() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        accounts
+            .deposit(signer, USD, u64::MAX)
+            .expect("deposit failed");
+
+        // Act
+        let sut = accounts.send(signer, signer, USD, 100);
+
+        // Assert
+        assert!(sut.is_ok());
+        assert_eq!(accounts.balance_of(signer, USD), u64::MAX);
+    }
+
+    #[test]
+    fn replaying_the_tx_log_rebuilds_the_same_accounts() {
+        // Arrange
+        let mut accounts = Accounts::new();
+        let tx_log = vec![
+            accounts.register_asset(USD, 2).unwrap(),
+            accounts.register_asset(BTC, 8).unwrap(),
+            accounts.deposit("client_1", USD, 100).unwrap(),
+            accounts.deposit("client_2", BTC, 20).unwrap(),
+            accounts.withdraw("client_1", USD, 30).unwrap(),
+            accounts.send("client_1", "client_2", USD, 50).unwrap(),
+        ];
+
+        // Act
+        let sut = Accounts::replay(tx_log.clone());
+
+        // Assert
+        assert_eq!(accounts, sut.unwrap());
+        assert_eq!(Ok(true), accounts.verify_against(tx_log));
+    }
+
+    #[test]
+    fn verifying_against_a_diverging_log_returns_false() {
+        // Arrange
+        let mut accounts = Accounts::new();
+        let mut tx_log = vec![accounts.register_asset(USD, 2).unwrap()];
+        accounts.deposit("client_1", USD, 100).unwrap();
+        tx_log.push(envelope(
+            2,
+            Tx::Deposit {
+                account: "client_1".to_string(),
+                asset: USD.to_string(),
+                amount: 99,
+                idempotency_key: None,
+            },
+        ));
+
+        // Act
+        let sut = accounts.verify_against(tx_log);
+
+        // Assert
+        assert_eq!(Ok(false), sut);
+    }
+
+    #[test]
+    fn errors_when_replaying_an_invalid_log() {
+        // Arrange
+        let tx_log = vec![
+            envelope(
+                1,
+                Tx::RegisterAsset {
+                    asset: USD.to_string(),
+                    decimals: 2,
+                },
+            ),
+            envelope(
+                2,
+                Tx::Withdraw {
+                    account: "client_1".to_string(),
+                    asset: USD.to_string(),
+                    amount: 10,
+                    idempotency_key: None,
+                },
+            ),
+        ];
+
+        // Act
+        let sut = Accounts::replay(tx_log);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::AccountNotFound("client_1".to_string())),
+            sut
+        );
+    }
+
+    #[test]
+    fn transfers_are_issued_increasing_ids() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        accounts
+            .deposit("client_1", USD, 100)
+            .expect("deposit failed");
+
+        // Act
+        let first = accounts.send("client_1", "client_2", USD, 10).unwrap();
+        let second = accounts.send("client_2", "client_1", USD, 5).unwrap();
+
+        // Assert
+        assert!(matches!(first.tx, Tx::Transfer { id: 1, .. }));
+        assert!(matches!(second.tx, Tx::Transfer { id: 2, .. }));
+    }
+
+    #[test]
+    fn replaying_a_failing_transfer_applies_neither_leg() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        accounts
+            .deposit("client_1", USD, 10)
+            .expect("deposit failed");
+        let transfer = envelope(
+            4,
+            Tx::Transfer {
+                id: 1,
+                from: "client_1".to_string(),
+                to: "client_2".to_string(),
+                asset: USD.to_string(),
+                amount: 50,
+                idempotency_key: None,
+            },
+        );
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let sut = accounts.apply(&transfer);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::AccountUnderFunded(
+                "client_1".to_string(),
+                50
+            )),
+            sut
+        );
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn double_entry_books_balance_and_mirror_customer_balances() {
+        // Arrange
+        let mut accounts = Accounts::with_double_entry();
+        accounts.register_asset(USD, 2).unwrap();
+        accounts.deposit("client_1", USD, 100).unwrap();
+        accounts.send("client_1", "client_2", USD, 30).unwrap();
+        accounts.withdraw("client_2", USD, 10).unwrap();
+
+        // Act
+        let sut = accounts.trial_balance().unwrap();
+
+        // Assert
+        assert_eq!(Ok(()), sut.assert_balanced());
+        assert_eq!(Ok(()), sut.reconcile(&accounts));
+    }
+
+    #[test]
+    fn failed_operations_are_not_posted_to_the_books() {
+        // Arrange
+        let mut accounts = Accounts::with_double_entry();
+        accounts.register_asset(USD, 2).unwrap();
+        accounts.deposit("client_1", USD, 100).unwrap();
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let sut = accounts.send("client_1", "client_2", USD, 300);
+
+        // Assert
+        assert!(sut.is_err());
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn accounts_without_double_entry_have_no_trial_balance() {
+        assert_eq!(None, accounts_with_assets().trial_balance());
+    }
+
+    #[test]
+    fn holding_funds_reduces_the_available_but_not_the_total_balance() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        accounts.deposit(signer, USD, 100).expect("deposit failed");
+
+        // Act
+        let sut = accounts.hold(signer, USD, 70);
+
+        // Assert
+        assert_eq!(
+            Tx::Hold {
+                id: 1,
+                account: signer.to_string(),
+                asset: USD.to_string(),
+                amount: 70
+            },
+            sut.unwrap().tx
+        );
+        assert_eq!(100, accounts.total_balance(signer, USD));
+        assert_eq!(30, accounts.available_balance(signer, USD));
+    }
+
+    #[test]
+    fn errors_when_spending_held_funds() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        accounts.deposit(signer, USD, 100).expect("deposit failed");
+        accounts.hold(signer, USD, 70).expect("hold failed");
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let withdraw = accounts.withdraw(signer, USD, 50);
+        let send = accounts.send(signer, "client_2", USD, 50);
+        let hold = accounts.hold(signer, USD, 50);
+
+        // Assert
+        let insufficient = Err(AccountingError::InsufficientAvailableFunds(
+            signer.to_string(),
+            50,
+        ));
+        assert_eq!(insufficient, withdraw);
+        assert_eq!(insufficient, send);
+        assert_eq!(insufficient, hold);
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn releasing_a_hold_makes_the_funds_available_again() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        accounts.deposit(signer, USD, 100).expect("deposit failed");
+        accounts.hold(signer, USD, 70).expect("hold failed");
+
+        // Act
+        let sut = accounts.release(1);
+
+        // Assert
+        assert!(matches!(
+            sut.map(|envelope| envelope.tx),
+            Ok(Tx::Release {
+                id: 1,
+                amount: 70,
+                ..
+            })
+        ));
+        assert_eq!(100, accounts.available_balance(signer, USD));
+        assert_eq!(Err(AccountingError::HoldNotFound(1)), accounts.release(1));
+    }
+
+    #[test]
+    fn capturing_a_hold_withdraws_its_funds() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        accounts.deposit(signer, USD, 100).expect("deposit failed");
+        accounts.hold(signer, USD, 70).expect("hold failed");
+
+        // Act
+        let sut = accounts.capture(1);
+
+        // Assert
+        assert!(matches!(
+            sut.map(|envelope| envelope.tx),
+            Ok(Tx::Capture {
+                id: 1,
+                amount: 70,
+                ..
+            })
+        ));
+        assert_eq!(30, accounts.total_balance(signer, USD));
+        assert_eq!(30, accounts.available_balance(signer, USD));
+        assert_eq!(Err(AccountingError::HoldNotFound(1)), accounts.capture(1));
+    }
+
+    #[test]
+    fn replaying_the_hold_lifecycle_reproduces_the_held_amounts() {
+        // Arrange
+        let mut accounts = Accounts::with_double_entry();
+        let tx_log = vec![
+            accounts.register_asset(USD, 2).unwrap(),
+            accounts.deposit("client_1", USD, 100).unwrap(),
+            accounts.hold("client_1", USD, 10).unwrap(),
+            accounts.hold("client_1", USD, 20).unwrap(),
+            accounts.hold("client_1", USD, 30).unwrap(),
+            accounts.release(1).unwrap(),
+            accounts.capture(2).unwrap(),
+        ];
+
+        // Act
+        let sut = Accounts::replay_from(Accounts::with_double_entry(), tx_log.clone()).unwrap();
+
+        // Assert
+        assert_eq!(accounts, sut);
+        assert_eq!(80, sut.total_balance("client_1", USD));
+        assert_eq!(50, sut.available_balance("client_1", USD));
+        assert_eq!(Ok(true), accounts.verify_against(tx_log));
+        assert_eq!(Ok(()), sut.trial_balance().unwrap().assert_balanced());
+    }
+
+    #[test]
+    fn opening_an_account_twice_errors() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+
+        // Act
+        let first = accounts.open(signer);
+        let second = accounts.open(signer);
+
+        // Assert
+        assert_eq!(
+            Ok(Tx::OpenAccount {
+                account: signer.to_string()
+            }),
+            first.map(|envelope| envelope.tx)
+        );
+        assert_eq!(
+            Err(AccountingError::AccountAlreadyExists(signer.to_string())),
+            second
+        );
+        assert_eq!(Some(AccountState::Open), accounts.account_state(signer));
+    }
+
+    #[test]
+    fn frozen_accounts_can_receive_but_not_send() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        accounts.deposit(signer, USD, 100).expect("deposit failed");
+        accounts
+            .deposit("client_2", USD, 100)
+            .expect("deposit failed");
+        accounts.freeze(signer).expect("freeze failed");
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let withdraw = accounts.withdraw(signer, USD, 10);
+        let send = accounts.send(signer, "client_2", USD, 10);
+        let hold = accounts.hold(signer, USD, 10);
+        let freeze = accounts.freeze(signer);
+
+        // Assert
+        let frozen = Err(AccountingError::AccountFrozen(signer.to_string()));
+        assert_eq!(frozen, withdraw);
+        assert_eq!(frozen, send);
+        assert_eq!(frozen, hold);
+        assert_eq!(frozen, freeze);
+        assert_eq!(previous_accounts, accounts);
+
+        accounts.deposit(signer, USD, 5).expect("deposit failed");
+        accounts
+            .send("client_2", signer, USD, 5)
+            .expect("send failed");
+        assert_eq!(110, accounts.total_balance(signer, USD));
+    }
+
+    #[test]
+    fn unfreezing_allows_sending_again() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        accounts.deposit(signer, USD, 100).expect("deposit failed");
+        accounts.freeze(signer).expect("freeze failed");
+
+        // Act
+        let sut = accounts.unfreeze(signer);
+
+        // Assert
+        assert!(sut.is_ok());
+        assert!(accounts.withdraw(signer, USD, 10).is_ok());
+        assert_eq!(
+            Err(AccountingError::AccountNotFrozen(signer.to_string())),
+            accounts.unfreeze(signer)
+        );
+    }
+
+    #[test]
+    fn errors_when_closing_an_account_with_a_balance() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        accounts.deposit(signer, USD, 100).expect("deposit failed");
+        accounts.deposit(signer, BTC, 0).expect("deposit failed");
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let sut = accounts.close(signer);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::AccountHasBalance(signer.to_string())),
+            sut
+        );
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn closed_accounts_reject_every_operation() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        accounts.deposit(signer, USD, 100).expect("deposit failed");
+        accounts
+            .deposit("client_2", USD, 100)
+            .expect("deposit failed");
+        accounts
+            .withdraw(signer, USD, 100)
+            .expect("withdraw failed");
+
+        // Act
+        let sut = accounts.close(signer);
+
+        // Assert
+        assert!(sut.is_ok());
+        assert_eq!(Some(AccountState::Closed), accounts.account_state(signer));
+        let closed = Err(AccountingError::AccountClosed(signer.to_string()));
+        assert_eq!(closed, accounts.deposit(signer, USD, 1));
+        assert_eq!(closed, accounts.withdraw(signer, USD, 1));
+        assert_eq!(closed, accounts.send("client_2", signer, USD, 1));
+        assert_eq!(closed, accounts.freeze(signer));
+        assert_eq!(closed, accounts.close(signer));
+        assert_eq!(
+            Err(AccountingError::AccountAlreadyExists(signer.to_string())),
+            accounts.open(signer)
+        );
+    }
+
+    #[test]
+    fn replaying_lifecycle_transactions_reproduces_account_states() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let tx_log = vec![
+            accounts.open("client_1").unwrap(),
+            accounts.open("client_2").unwrap(),
+            accounts.deposit("client_2", USD, 10).unwrap(),
+            accounts.freeze("client_2").unwrap(),
+            accounts.close("client_1").unwrap(),
+        ];
+
+        // Act
+        let sut = Accounts::replay_from(accounts_with_assets(), tx_log);
+
+        // Assert
+        let sut = sut.unwrap();
+        assert_eq!(accounts, sut);
+        assert_eq!(Some(AccountState::Closed), sut.account_state("client_1"));
+        assert_eq!(Some(AccountState::Frozen), sut.account_state("client_2"));
+    }
+
+    #[test]
+    fn retrying_an_idempotent_deposit_returns_the_original_tx_without_reapplying() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        let original = accounts
+            .deposit_idempotent("payment-1", signer, USD, 100)
+            .unwrap();
+
+        // Act
+        let sut = accounts.deposit_idempotent("payment-1", signer, USD, 100);
+
+        // Assert
+        assert_eq!(Ok(original), sut);
+        assert_eq!(100, accounts.total_balance(signer, USD));
+    }
+
+    #[test]
+    fn retrying_an_idempotent_withdrawal_returns_the_original_tx_without_reapplying() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        accounts.deposit(signer, USD, 100).expect("deposit failed");
+        let original = accounts
+            .withdraw_idempotent("payout-1", signer, USD, 30)
+            .unwrap();
+
+        // Act
+        let sut = accounts.withdraw_idempotent("payout-1", signer, USD, 30);
+
+        // Assert
+        assert_eq!(Ok(original), sut);
+        assert_eq!(70, accounts.total_balance(signer, USD));
+    }
+
+    #[test]
+    fn errors_when_an_idempotency_key_is_reused_with_different_parameters() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        accounts
+            .deposit_idempotent("payment-1", signer, USD, 100)
+            .unwrap();
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let amount = accounts.deposit_idempotent("payment-1", signer, USD, 200);
+        let kind = accounts.withdraw_idempotent("payment-1", signer, USD, 100);
+
+        // Assert
+        let reused = Err(AccountingError::IdempotencyKeyReused(
+            "payment-1".to_string(),
+        ));
+        assert_eq!(reused, amount);
+        assert_eq!(reused, kind);
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn a_failed_idempotent_operation_does_not_consume_its_key() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "client_1";
+        accounts
+            .withdraw_idempotent("payout-1", signer, USD, 30)
+            .expect_err("withdraw should fail");
+        accounts.deposit(signer, USD, 100).expect("deposit failed");
+
+        // Act
+        let sut = accounts.withdraw_idempotent("payout-1", signer, USD, 30);
+
+        // Assert
+        assert!(sut.is_ok());
+        assert_eq!(70, accounts.total_balance(signer, USD));
+    }
+
+    #[test]
+    fn seen_idempotency_keys_survive_replay() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let tx_log = vec![
+            accounts
+                .deposit_idempotent("payment-1", "client_1", USD, 100)
+                .unwrap(),
+            accounts
+                .withdraw_idempotent("payout-1", "client_1", USD, 30)
+                .unwrap(),
+        ];
+
+        // Act
+        let mut sut = Accounts::replay_from(accounts_with_assets(), tx_log.clone()).unwrap();
+
+        // Assert
+        assert_eq!(accounts, sut);
+        assert_eq!(
+            Ok(tx_log[0].clone()),
+            sut.deposit_idempotent("payment-1", "client_1", USD, 100)
+        );
+        assert_eq!(70, sut.total_balance("client_1", USD));
+        assert_eq!(Some(&tx_log[1]), sut.idempotent_tx("payout-1"));
+    }
+
+    #[test]
+    fn envelopes_carry_increasing_seq_ids_and_clock_timestamps() {
+        // Arrange
+        let clock = ManualClock::new(1_700_000_000);
+        let mut accounts = accounts_at(&clock);
+
+        // Act
+        let deposit = accounts.deposit("client_1", USD, 100).unwrap();
+        clock.advance(90);
+        let withdraw = accounts.withdraw("client_1", USD, 40).unwrap();
+
+        // Assert
+        assert_eq!((2, 1_700_000_000), (deposit.seq, deposit.timestamp));
+        assert_eq!((3, 1_700_000_090), (withdraw.seq, withdraw.timestamp));
+        assert_eq!(Details::default(), withdraw.details);
+        assert_eq!(3, accounts.last_seq());
+    }
+
+    #[test]
+    fn annotated_details_are_recorded_and_survive_replay() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let details = Details {
+            memo: Some("refund for order 17".to_string()),
+            reference: Some("PSP-8812".to_string()),
+        };
+
+        // Act
+        let sut = accounts
+            .annotated(details.clone(), |accounts| {
+                accounts.deposit("client_1", USD, 100)
+            })
+            .unwrap();
+        let unannotated = accounts.deposit("client_1", USD, 5).unwrap();
+
+        // Assert
+        assert_eq!(details, sut.details);
+        assert_eq!(Details::default(), unannotated.details);
+        let replayed =
+            Accounts::replay_from(accounts_with_assets(), vec![sut, unannotated]).unwrap();
+        assert_eq!(accounts, replayed);
+    }
+
+    #[test]
+    fn errors_when_replaying_envelopes_out_of_order() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let first = accounts.deposit("client_1", USD, 100).unwrap();
+        let second = accounts.deposit("client_1", USD, 50).unwrap();
+
+        // Act
+        let sut = Accounts::replay_from(accounts_with_assets(), vec![second.clone(), first]);
+
+        // Assert
+        assert_eq!(Err(AccountingError::EnvelopeOutOfOrder(3)), sut);
+    }
+
+    /// A ledger charging `fee` on `on` operations in [`USD`]
+    fn accounts_with_fee(on: FeeOn, fee: Fee) -> Accounts {
+        let mut accounts = Accounts::with_double_entry();
+        accounts.register_asset(USD, 2).expect("register failed");
+        accounts
+            .set_fee(on, USD, Some(fee))
+            .expect("set fee failed");
+        accounts
+    }
+
+    #[test]
+    fn withdrawal_fees_are_credited_to_the_house_account_as_their_own_tx() {
+        // Arrange
+        let fee = Fee {
+            rule: FeeRule::BasisPoints(100),
+            min: 5,
+            max: None,
+        };
+        let mut accounts = accounts_with_fee(FeeOn::Withdrawal, fee);
+        accounts.deposit("client_1", USD, 10_000).unwrap();
+
+        // Act
+        let sut = accounts.withdraw("client_1", USD, 2_000).unwrap();
+
+        // Assert
+        assert_eq!(7_980, accounts.total_balance("client_1", USD));
+        assert_eq!(20, accounts.total_balance(FEE_ACCOUNT, USD));
+        let recorded = accounts.history_since(sut.seq - 1);
+        assert_eq!(2, recorded.len());
+        assert_eq!(sut, recorded[0]);
+        assert_eq!(
+            Tx::Fee {
+                account: "client_1".to_string(),
+                asset: USD.to_string(),
+                amount: 20,
+            },
+            recorded[1].tx
+        );
+        assert_eq!(Ok(()), accounts.trial_balance().unwrap().assert_balanced());
+    }
+
+    #[test]
+    fn errors_and_charges_nothing_when_the_fee_cannot_be_covered() {
+        // Arrange
+        let fee = Fee {
+            rule: FeeRule::Flat(50),
+            min: 0,
+            max: None,
+        };
+        let mut accounts = accounts_with_fee(FeeOn::Transfer, fee);
+        accounts.deposit("client_1", USD, 100).unwrap();
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let sut = accounts.send("client_1", "client_2", USD, 60);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::AccountUnderFunded(
+                "client_1".to_string(),
+                110
+            )),
+            sut
+        );
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn replaying_charged_fees_does_not_charge_them_twice() {
+        // Arrange
+        let fee = Fee {
+            rule: FeeRule::Flat(10),
+            min: 0,
+            max: None,
+        };
+        let mut accounts = accounts_with_fee(FeeOn::Transfer, fee);
+        accounts.deposit("client_1", USD, 100).unwrap();
+        accounts.send("client_1", "client_2", USD, 50).unwrap();
+
+        // Act
+        let sut = Accounts::replay_from(
+            Accounts::with_double_entry(),
+            accounts.history_since(0).to_vec(),
+        );
+
+        // Assert
+        let sut = sut.unwrap();
+        assert_eq!(accounts, sut);
+        assert_eq!(40, sut.total_balance("client_1", USD));
+        assert_eq!(10, sut.total_balance(FEE_ACCOUNT, USD));
+    }
+
+    #[test]
+    fn spending_beyond_the_balance_draws_on_the_credit_line_until_repaid() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "business_1";
+        accounts.deposit(signer, USD, 100).unwrap();
+        accounts.set_credit_limit(signer, USD, 100).unwrap();
+
+        // Act
+        accounts.withdraw(signer, USD, 150).unwrap();
+        let overdrawn = (
+            accounts.total_balance(signer, USD),
+            accounts.credit_used(signer, USD),
+        );
+        accounts.deposit(signer, USD, 80).unwrap();
+
+        // Assert
+        assert_eq!((0, 50), overdrawn);
+        assert_eq!(30, accounts.total_balance(signer, USD));
+        assert_eq!(0, accounts.credit_used(signer, USD));
+        let replayed =
+            Accounts::replay_from(accounts_with_assets(), accounts.history_since(2).to_vec());
+        assert_eq!(Ok(accounts), replayed);
+    }
+
+    #[test]
+    fn errors_with_the_excess_when_a_request_goes_over_the_credit_limit() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let signer = "business_1";
+        accounts.deposit(signer, USD, 100).unwrap();
+        accounts.set_credit_limit(signer, USD, 500).unwrap();
+        accounts.withdraw(signer, USD, 300).unwrap();
+
+        // Act
+        let previous_accounts = accounts.clone();
+        let sut = accounts.send(signer, "client_2", USD, 320);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::CreditLimitExceeded(signer.to_string(), 20)),
+            sut
+        );
+        assert_eq!(previous_accounts, accounts);
+        assert_eq!(
+            Err(AccountingError::AccountHasBalance(signer.to_string())),
+            accounts.close(signer)
+        );
+    }
+
+    #[test]
+    fn accounts_can_be_listed_by_name_or_by_balance() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        accounts.deposit("carol", USD, 300).unwrap();
+        accounts.deposit("alice", USD, 100).unwrap();
+        accounts.deposit("bob", BTC, 5).unwrap();
+        accounts.hold("alice", USD, 40).unwrap();
+
+        // Act
+        let by_name = accounts.list_accounts(AccountOrder::Name);
+        let by_balance = accounts.list_accounts(AccountOrder::Balance(USD));
+
+        // Assert
+        let signers = |summaries: Vec<super::AccountSummary>| {
+            summaries
+                .into_iter()
+                .map(|summary| summary.signer.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["alice", "bob", "carol"], signers(by_name));
+        assert_eq!(vec!["carol", "alice", "bob"], signers(by_balance));
+        assert_eq!(
+            Some(&Balance {
+                total: 100,
+                held: 40,
+                credit_used: 0
+            }),
+            accounts.balance("alice").get(USD)
+        );
+        assert!(accounts.balance("dave").is_empty());
+    }
+
+    #[test]
+    fn total_supply_nets_drawn_credit_against_balances() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        accounts.deposit("client_1", USD, 500).unwrap();
+        accounts.open("business_1").unwrap();
+        accounts.set_credit_limit("business_1", USD, 1_000).unwrap();
+        accounts.send("business_1", "client_1", USD, 200).unwrap();
+
+        // Act
+        let sut = accounts.total_supply(USD);
+
+        // Assert
+        assert_eq!(500, sut);
+        assert_eq!(0, accounts.total_supply(BTC));
+    }
+
+    #[test]
+    fn account_history_is_paginated_and_indexed_when_restored_after_decode() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        for amount in 1..=5 {
+            accounts.deposit("client_1", USD, amount).unwrap();
+            accounts.deposit("client_2", USD, amount).unwrap();
+        }
+        let mut encoder = Encoder::new();
+        accounts.encode(&mut encoder);
+        let bytes = encoder.into_bytes();
+        let mut decoded = Accounts::decode(&mut Decoder::new(&bytes)).unwrap();
+        let without_history = decoded.account_history("client_1", 0, 10).envelopes.len();
+        decoded
+            .restore_history(accounts.history_since(0).to_vec())
+            .unwrap();
+
+        // Act
+        let first = accounts.account_history("client_1", 0, 2);
+        let last = decoded.account_history("client_1", first.next_after.unwrap(), 3);
+
+        // Assert
+        let amounts = |page: &super::HistoryPage| {
+            page.envelopes
+                .iter()
+                .map(|envelope| match envelope.tx {
+                    Tx::Deposit { amount, .. } => amount,
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![1, 2], amounts(&first));
+        assert_eq!(Some(5), first.next_after);
+        assert_eq!(vec![3, 4, 5], amounts(&last));
+        assert_eq!(None, last.next_after);
+        assert_eq!(0, without_history);
+        assert_eq!(
+            Err(AccountingError::EnvelopeOutOfOrder(1)),
+            decoded.restore_history(accounts.history_since(0)[..1].to_vec())
+        );
+    }
+
+    #[test]
+    fn balances_as_of_a_past_seq_or_timestamp_ignore_later_transactions() {
+        // Arrange
+        let clock = ManualClock::new(1_000);
+        let mut accounts = accounts_at(&clock);
+        accounts.deposit("client_1", USD, 100).unwrap();
+        clock.advance(60);
+        let sent = accounts.send("client_1", "client_2", USD, 30).unwrap();
+        clock.advance(60);
+        accounts.withdraw("client_1", USD, 50).unwrap();
+
+        // Act
+        let after_send = accounts
+            .balance_as_of("client_1", AsOf::Seq(sent.seq))
+            .unwrap();
+        let after_deposit = accounts
+            .balance_as_of("client_1", AsOf::Timestamp(1_059))
+            .unwrap();
+        let before_anything = accounts
+            .balance_as_of("client_1", AsOf::Timestamp(999))
+            .unwrap();
+
+        // Assert
+        assert_eq!(Some(70), after_send.get(USD).map(|balance| balance.total));
+        assert_eq!(
+            Some(100),
+            after_deposit.get(USD).map(|balance| balance.total)
+        );
+        assert!(before_anything.is_empty());
+        assert_eq!(20, accounts.total_balance("client_1", USD));
     }
 
-    /// Either deposits the `amount` provided into the `signer` account or adds the amount to the existing account.
-    ///
-    /// # Errors
-    /// - attempted overflow
-    pub fn deposit(&mut self, signer: &str, amount: u64) -> Result<Tx, AccountingError> {
-        if let Some(account) = self.accounts.get_mut(signer) {
-            (*account)
-                .checked_add(amount)
-                .map(|new_amount| *account = new_amount)
-                .ok_or_else(|| AccountingError::AccountOverFunded(signer.to_string(), amount))
-                // Using map() here is an easy way to only manipulate the non-error result
-                .map(|_| Tx::Deposit {
-                    account: signer.to_string(),
-                    amount,
-                })
-        } else {
-            self.accounts.insert(signer.to_string(), amount);
-            Ok(Tx::Deposit {
-                account: signer.to_string(),
-                amount,
-            })
-        }
+    #[test]
+    fn envelopes_recorded_after_the_clock_goes_back_keep_the_last_timestamp() {
+        // Arrange
+        let clock = ManualClock::new(1_000);
+        let mut accounts = accounts_at(&clock);
+        accounts.deposit("client_1", USD, 100).unwrap();
+        clock.set(900);
+        let withdrawal = accounts.withdraw("client_1", USD, 40).unwrap();
+        clock.set(950);
+        accounts.deposit("client_1", USD, 5).unwrap();
+
+        // Act
+        let before_anything = accounts
+            .balance_as_of("client_1", AsOf::Timestamp(999))
+            .unwrap();
+        let after_withdrawal = accounts
+            .balance_as_of("client_1", AsOf::Timestamp(1_000))
+            .unwrap();
+
+        // Assert
+        assert_eq!(1_000, withdrawal.timestamp);
+        assert!(before_anything.is_empty());
+        assert_eq!(
+            Some(65),
+            after_withdrawal.get(USD).map(|balance| balance.total)
+        );
     }
 
-    /// Withdraws the `amount` from the `signer` account.
-    ///
-    /// # Errors
-    /// - insufficient funds
-    /// - inexistent account
-    pub fn withdraw(&mut self, signer: &str, amount: u64) -> Result<Tx, AccountingError> {
-        if let Some(account) = self.accounts.get_mut(signer) {
-            (*account)
-                .checked_sub(amount)
-                .map(|new_amount| *account = new_amount)
-                .ok_or_else(|| AccountingError::AccountUnderFunded(signer.to_string(), amount))
-                .map(|_| Tx::Withdraw {
-                    account: signer.to_string(),
-                    amount,
-                })
-        } else {
-            Err(AccountingError::AccountNotFound(signer.to_string()))
-        }
+    #[test]
+    fn past_balances_need_the_history_a_snapshot_left_out_restored_first() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        accounts.deposit("client_1", USD, 100).unwrap();
+        let mut encoder = Encoder::new();
+        accounts.encode(&mut encoder);
+        let bytes = encoder.into_bytes();
+        let snapshotted = accounts.history_since(0).to_vec();
+        let tail = vec![accounts.withdraw("client_1", USD, 40).unwrap()];
+        let mut sut =
+            Accounts::replay_from(Accounts::decode(&mut Decoder::new(&bytes)).unwrap(), tail)
+                .unwrap();
+        let without_history = sut.balance_as_of("client_1", AsOf::Seq(3));
+
+        // Act
+        sut.restore_history(snapshotted.clone()).unwrap();
+
+        // Assert
+        assert_eq!(Err(AccountingError::HistoryUnavailable(3)), without_history);
+        assert_eq!(accounts, sut);
+        let after_deposit = sut.balance_as_of("client_1", AsOf::Seq(3)).unwrap();
+        assert_eq!(
+            Some(100),
+            after_deposit.get(USD).map(|balance| balance.total)
+        );
+        assert_eq!(
+            Err(AccountingError::EnvelopeOutOfOrder(1)),
+            sut.restore_history(snapshotted)
+        );
     }
 
-    /// Withdraws the amount from the sender account and deposits it in the recipient account.
-    ///
-    /// # Errors
-    /// - inexistent `sender` account
-    /// - `sender` has insufficient funds
-    /// - deposit can cause overflow for `recipient`
-    pub fn send(
-        &mut self,
-        sender: &str,
-        recipient: &str,
-        amount: u64,
-    ) -> Result<(Tx, Tx), AccountingError> {
-        Ok((
-            self.withdraw(sender, amount)?,
-            self.deposit(recipient, amount)?,
-        ))
+    #[test]
+    fn ledgers_as_of_a_point_match_the_ledger_at_that_point_across_checkpoints() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let mut totals = vec![];
+        for amount in 1..=(2 * CHECKPOINT_EVERY as u64 + 100) {
+            let signer = if amount % 2 == 0 {
+                "client_1"
+            } else {
+                "client_2"
+            };
+            let envelope = accounts.deposit(signer, USD, amount).unwrap();
+            totals.push((envelope.seq, accounts.total_supply(USD)));
+        }
+
+        // Act
+        let views = totals
+            .iter()
+            .step_by(97)
+            .rev()
+            .map(|(seq, _)| accounts.as_of(AsOf::Seq(*seq)).unwrap())
+            .collect::<Vec<_>>();
+
+        // Assert
+        for ((seq, total), view) in totals.iter().step_by(97).rev().zip(&views) {
+            assert_eq!(*seq, view.last_seq());
+            assert_eq!(*total, view.total_supply(USD));
+        }
+        assert_eq!(2, accounts.checkpoints.states().len());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{errors::AccountingError, tx::Tx};
+    #[test]
+    fn disputing_a_deposit_holds_its_funds_until_it_is_resolved() {
+        // Arrange
+        let mut accounts = accounts_with_assets();
+        let deposit = accounts.deposit("client_1", USD, 100).unwrap().seq;
+        accounts.deposit("client_1", USD, 50).unwrap();
+
+        // Act
+        let disputed = accounts.dispute(deposit);
+        let available_while_disputed = accounts.available_balance("client_1", USD);
+        let disputed_twice = accounts.dispute(deposit);
+        let resolved = accounts.resolve(deposit);
+        let resolved_twice = accounts.resolve(deposit);
 
-    use super::Accounts;
+        // Assert
+        assert!(disputed.is_ok());
+        assert_eq!(50, available_while_disputed);
+        assert_eq!(
+            Err(AccountingError::DepositAlreadyDisputed(deposit)),
+            disputed_twice
+        );
+        assert!(resolved.is_ok());
+        assert_eq!(
+            Err(AccountingError::DepositNotDisputed(deposit)),
+            resolved_twice
+        );
+        assert_eq!(
+            Some(DisputeState::Resolved),
+            accounts.dispute_state(deposit)
+        );
+        assert_eq!(150, accounts.available_balance("client_1", USD));
+    }
 
     #[test]
-    fn when_a_new_user_makes_a_deposit_it_is_added_in_accounts() {
+    fn errors_when_disputing_an_unknown_or_spent_deposit() {
         // Arrange
-        let mut accounts = Accounts::new();
-        let signer = "client_1";
-        let deposit = 100;
+        let mut accounts = accounts_with_assets();
+        let deposit = accounts.deposit("client_1", USD, 100).unwrap().seq;
+        let withdrawal = accounts.withdraw("client_1", USD, 60).unwrap().seq;
 
         // Act
-        let sut = accounts.deposit(signer, deposit);
+        let unknown = accounts.dispute(99);
+        let not_a_deposit = accounts.dispute(withdrawal);
+        let spent = accounts.dispute(deposit);
 
         // Assert
+        assert_eq!(Err(AccountingError::DepositNotFound(99)), unknown);
         assert_eq!(
-            Tx::Deposit {
-                account: signer.to_string(),
-                amount: deposit
-            },
-            sut.unwrap()
+            Err(AccountingError::DepositNotFound(withdrawal)),
+            not_a_deposit
+        );
+        assert_eq!(
+            Err(AccountingError::InsufficientAvailableFunds(
+                "client_1".to_string(),
+                100
+            )),
+            spent
         );
-        assert_eq!(accounts.accounts[signer], deposit);
+        assert_eq!(None, accounts.dispute_state(deposit));
     }
 
     #[test]
-    fn when_an_existent_user_makes_a_transaction_the_amount_is_correctly_updated() {
+    fn charging_back_a_deposit_withdraws_its_funds_and_locks_the_account() {
         // Arrange
-        let mut accounts = Accounts::new();
-        let signer = "client_1";
-        let first_deposit = 100;
-        let second_deposit = 150;
+        let mut accounts = Accounts::with_double_entry();
+        accounts.register_asset(USD, 2).unwrap();
+        let deposit = accounts.deposit("client_1", USD, 100).unwrap().seq;
+        accounts.deposit("client_1", USD, 30).unwrap();
+        accounts.dispute(deposit).unwrap();
 
-        accounts
-            .deposit(signer, first_deposit)
-            .expect("first deposit failed");
+        // Act
+        let sut = accounts.chargeback(deposit);
+
+        // Assert
+        assert!(sut.is_ok());
+        assert_eq!(30, accounts.total_balance("client_1", USD));
+        assert_eq!(
+            Some(AccountState::Locked),
+            accounts.account_state("client_1")
+        );
+        assert_eq!(
+            Err(AccountingError::AccountLocked("client_1".to_string())),
+            accounts.deposit("client_1", USD, 10)
+        );
+        assert_eq!(
+            Err(AccountingError::AccountLocked("client_1".to_string())),
+            accounts.withdraw("client_1", USD, 10)
+        );
+        assert_eq!(Ok(()), accounts.trial_balance().unwrap().assert_balanced());
+        let replayed = Accounts::replay_from(
+            Accounts::with_double_entry(),
+            accounts.history_since(0).to_vec(),
+        );
+        assert_eq!(Ok(accounts.clone()), replayed);
+        let mut encoder = Encoder::new();
+        accounts.encode(&mut encoder);
+        let bytes = encoder.into_bytes();
+        let mut decoded = Accounts::decode(&mut Decoder::new(&bytes)).unwrap();
+        decoded
+            .restore_history(accounts.history_since(0).to_vec())
+            .unwrap();
+        assert_eq!(accounts, decoded);
+    }
+
+    #[test]
+    fn reversing_a_transfer_appends_a_compensating_tx_and_survives_replay() {
+        // Arrange
+        let mut accounts = Accounts::with_double_entry();
+        accounts.register_asset(USD, 2).unwrap();
+        accounts.deposit("client_1", USD, 100).unwrap();
+        let transfer = accounts.send("client_1", "client_2", USD, 40).unwrap();
 
         // Act
-        let sut = accounts.deposit(signer, second_deposit);
+        let sut = accounts.reverse(transfer.seq).unwrap();
 
         // Assert
         assert_eq!(
-            Tx::Deposit {
-                account: signer.to_string(),
-                amount: second_deposit
+            Tx::Reversal {
+                original: transfer.seq,
+                tx: Box::new(transfer.tx.clone()),
             },
-            sut.unwrap()
+            sut.tx
+        );
+        assert_eq!(100, accounts.total_balance("client_1", USD));
+        assert_eq!(0, accounts.total_balance("client_2", USD));
+        assert_eq!(
+            vec![transfer.clone(), sut],
+            accounts.history_since(transfer.seq - 1)
+        );
+        assert_eq!(
+            Err(AccountingError::TxAlreadyReversed(transfer.seq)),
+            accounts.reverse(transfer.seq)
+        );
+        assert_eq!(Ok(()), accounts.trial_balance().unwrap().assert_balanced());
+        assert_eq!(
+            Ok(true),
+            accounts.verify_against(accounts.history_since(0).to_vec())
         );
-        assert_eq!(accounts.accounts[signer], first_deposit + second_deposit);
     }
 
     #[test]
-    fn errors_when_a_deposit_causes_an_overflow() {
+    fn errors_when_reversing_spent_funds_or_an_irreversible_tx() {
         // Arrange
-        let mut accounts = Accounts::new();
-        let signer = "client_1";
-        let first_deposit = 100;
-        let second_deposit = u64::MAX;
+        let mut accounts = accounts_with_assets();
+        let deposit = accounts.deposit("client_1", USD, 100).unwrap();
+        accounts.withdraw("client_1", USD, 80).unwrap();
 
-        accounts
-            .deposit(signer, first_deposit)
-            .expect("deposit failed");
+        // Act
+        let spent = accounts.reverse(deposit.seq);
+        let register = accounts.reverse(1);
+        let unknown = accounts.reverse(999);
+
+        // Assert
+        assert_eq!(Err(AccountingError::TxFundsSpent(deposit.seq)), spent);
+        assert_eq!(Err(AccountingError::TxNotReversible(1)), register);
+        assert_eq!(Err(AccountingError::TxNotFound(999)), unknown);
+        assert_eq!(20, accounts.total_balance("client_1", USD));
+    }
+
+    fn leg(from: &str, to: &str, amount: u64) -> Leg {
+        Leg {
+            from: from.to_string(),
+            to: to.to_string(),
+            asset: USD.to_string(),
+            amount,
+        }
+    }
+
+    #[test]
+    fn batch_legs_are_checked_against_the_balances_earlier_legs_leave() {
+        // Arrange
+        let fee = Fee {
+            rule: FeeRule::Flat(5),
+            min: 0,
+            max: None,
+        };
+        let mut accounts = accounts_with_fee(FeeOn::Transfer, fee);
+        accounts.deposit("client_1", USD, 100).unwrap();
 
         // Act
+        let sut = accounts
+            .apply_batch(vec![
+                leg("client_1", "client_2", 60),
+                leg("client_2", "client_3", 50),
+            ])
+            .unwrap();
+
+        // Assert
+        let txs: Vec<&Tx> = sut.iter().map(|envelope| &envelope.tx).collect();
+        assert!(matches!(
+            txs[..],
+            [
+                Tx::Transfer { amount: 60, .. },
+                Tx::Fee { amount: 5, .. },
+                Tx::Transfer { amount: 50, .. },
+                Tx::Fee { amount: 5, .. }
+            ]
+        ));
+        assert_eq!(accounts.history_since(3), &sut[..]);
+        assert_eq!(35, accounts.total_balance("client_1", USD));
+        assert_eq!(5, accounts.total_balance("client_2", USD));
+        assert_eq!(50, accounts.total_balance("client_3", USD));
+        assert_eq!(10, accounts.total_balance(FEE_ACCOUNT, USD));
+    }
+
+    #[test]
+    fn batch_legs_above_the_approval_threshold_fail_the_batch() {
+        // Arrange
+        let mut accounts = accounts_with_approvals();
         let previous_accounts = accounts.clone();
-        let sut = accounts.deposit(signer, second_deposit);
+
+        // Act
+        let sut = accounts.apply_batch(vec![
+            leg("client_1", "client_2", 1_000),
+            leg("client_1", "client_3", 1_001),
+        ]);
 
         // Assert
         assert_eq!(
-            Err(AccountingError::AccountOverFunded(
-                signer.to_string(),
-                second_deposit
+            Err(AccountingError::BatchLegFailed(
+                1,
+                Box::new(AccountingError::ApprovalRequired(USD.to_string(), 1_000))
             )),
             sut
         );
@@ -160,109 +4123,341 @@ mod tests {
     }
 
     #[test]
-    fn withdrawing_correctly_updates_the_account_on_the_happy_path() {
+    fn a_failing_batch_leg_leaves_the_accounts_unchanged() {
         // Arrange
-        let mut accounts = Accounts::new();
-        let signer = "client_1";
-        let deposit = 100;
-        let withdraw = 50;
-
-        accounts.deposit(signer, deposit).expect("deposit failed");
+        let mut accounts = accounts_with_assets();
+        accounts.deposit("client_1", USD, 100).unwrap();
+        let previous_accounts = accounts.clone();
 
         // Act
-        let sut = accounts.withdraw(signer, withdraw);
+        let sut = accounts.apply_batch(vec![
+            leg("client_1", "client_2", 60),
+            leg("client_1", "client_3", 50),
+        ]);
 
         // Assert
         assert_eq!(
-            Tx::Withdraw {
-                account: signer.to_string(),
-                amount: withdraw
-            },
-            sut.unwrap()
+            Err(AccountingError::BatchLegFailed(
+                1,
+                Box::new(AccountingError::AccountUnderFunded(
+                    "client_1".to_string(),
+                    50
+                ))
+            )),
+            sut
         );
-        assert_eq!(accounts.accounts[signer], deposit - withdraw);
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    /// A ledger where withdrawals and transfers above 1,000 wait for approval
+    fn accounts_with_approvals() -> Accounts {
+        let mut accounts = accounts_with_assets();
+        accounts.set_approval_threshold(USD, Some(1_000)).unwrap();
+        accounts.deposit("client_1", USD, 5_000).unwrap();
+        accounts
     }
 
     #[test]
-    fn errors_when_withdrawing_from_a_nonexistent_account() {
+    fn retried_transfers_with_an_idempotency_key_are_sent_once() {
         // Arrange
-        let mut accounts = Accounts::new();
-        let signer = "client_1";
-        let withdraw = 100;
+        let mut accounts = accounts_with_approvals();
+        let sent = accounts
+            .send_idempotent("rent-1", "client_1", "client_2", USD, 500)
+            .unwrap();
+        let requested = accounts
+            .send_idempotent("rent-2", "client_1", "client_2", USD, 1_500)
+            .unwrap();
+        let Tx::ApprovalRequested { id, .. } = requested.tx else {
+            panic!("expected a request, got {:?}", requested.tx);
+        };
 
         // Act
-        let previous_accounts = accounts.clone();
-        let sut = accounts.withdraw(signer, withdraw);
+        let retried = accounts.send_idempotent("rent-1", "client_1", "client_2", USD, 500);
+        let other_recipient = accounts.send_idempotent("rent-1", "client_1", "client_3", USD, 500);
+        let waiting = accounts.send_idempotent("rent-2", "client_1", "client_2", USD, 1_500);
+        let approved = accounts.approve(id, "bob").unwrap();
 
         // Assert
+        assert_eq!(Ok(sent), retried);
         assert_eq!(
-            Err(AccountingError::AccountNotFound(signer.to_string())),
-            sut
+            Err(AccountingError::IdempotencyKeyReused("rent-1".to_string())),
+            other_recipient
         );
-        assert_eq!(previous_accounts, accounts);
+        assert_eq!(Ok(requested), waiting);
+        assert!(matches!(approved.tx, Tx::Transfer { .. }));
+        assert_eq!(
+            Ok(approved),
+            accounts.send_idempotent("rent-2", "client_1", "client_2", USD, 1_500)
+        );
+        assert_eq!(2_000, accounts.total_balance("client_2", USD));
+        let replayed = Accounts::replay_from(Accounts::new(), accounts.history_since(0).to_vec());
+        assert_eq!(Ok(accounts), replayed);
     }
 
     #[test]
-    fn errors_when_withdrawing_more_than_is_available() {
+    fn large_withdrawals_wait_for_a_second_operator() {
         // Arrange
-        let mut accounts = Accounts::new();
-        let signer = "client_1";
-        let deposit = 100;
-        let withdraw = 200;
+        let mut accounts = accounts_with_approvals();
+        let requested = accounts
+            .as_operator("alice", |ledger| ledger.withdraw("client_1", USD, 2_000))
+            .unwrap();
+
+        // Act
+        let by_maker = accounts.approve(1, "alice");
+        let sut = accounts.approve(1, "bob").unwrap();
 
-        accounts.deposit(signer, deposit).expect("deposit failed");
+        // Assert
+        assert!(matches!(
+            requested.tx,
+            Tx::ApprovalRequested { id: 1, ref maker, .. } if maker == "alice"
+        ));
+        assert_eq!(
+            Err(AccountingError::MakerCannotApprove("alice".to_string())),
+            by_maker
+        );
+        assert!(matches!(sut.tx, Tx::Withdraw { amount: 2_000, .. }));
+        assert!(matches!(
+            accounts.history_since(requested.seq)[0].tx,
+            Tx::Approved { id: 1, .. }
+        ));
+        assert_eq!(3_000, accounts.total_balance("client_1", USD));
+        assert_eq!(3_000, accounts.available_balance("client_1", USD));
+        assert!(accounts.pending_approvals().is_empty());
+        assert_eq!(
+            Err(AccountingError::ApprovalNotFound(1)),
+            accounts.reject(1, "bob")
+        );
+    }
+
+    #[test]
+    fn requests_hold_their_funds_until_rejected_and_keep_waiting_if_approval_fails() {
+        // Arrange
+        let mut accounts = accounts_with_approvals();
+        accounts.send("client_1", "client_2", USD, 4_000).unwrap();
+        accounts.withdraw("client_1", USD, 1_000).unwrap();
 
         // Act
-        let previous_accounts = accounts.clone();
-        let sut = accounts.withdraw(signer, withdraw);
+        let overdrawn = accounts.withdraw("client_1", USD, 10);
+        accounts.freeze("client_1").unwrap();
+        let frozen = accounts.approve(1, "bob");
+        accounts.unfreeze("client_1").unwrap();
+        let sut = accounts.reject(1, "bob");
 
         // Assert
         assert_eq!(
-            Err(AccountingError::AccountUnderFunded(
-                signer.to_string(),
-                withdraw
+            Err(AccountingError::InsufficientAvailableFunds(
+                "client_1".to_string(),
+                10
             )),
-            sut
+            overdrawn
         );
-        assert_eq!(previous_accounts, accounts);
+        assert_eq!(
+            Err(AccountingError::AccountFrozen("client_1".to_string())),
+            frozen
+        );
+        assert!(matches!(
+            sut.unwrap().tx,
+            Tx::ApprovalRejected { id: 1, ref checker, .. } if checker == "bob"
+        ));
+        assert_eq!(4_000, accounts.available_balance("client_1", USD));
+        assert_eq!(0, accounts.total_balance("client_2", USD));
     }
 
     #[test]
-    fn sending_money_correctly_updates_both_accounts_on_the_happy_path() {
+    fn waiting_requests_survive_replay_and_decoding() {
         // Arrange
-        let mut accounts = Accounts::new();
+        let mut accounts = accounts_with_approvals();
+        let requested = accounts
+            .withdraw_idempotent("payout-1", "client_1", USD, 1_500)
+            .unwrap();
+        accounts.send("client_1", "client_2", USD, 2_000).unwrap();
+        accounts.approve(2, "bob").unwrap();
 
-        let sender = "client_1";
-        let sender_deposit = 100;
+        // Act
+        let replayed =
+            Accounts::replay_from(Accounts::new(), accounts.history_since(0).to_vec()).unwrap();
+        let mut encoder = Encoder::new();
+        accounts.encode(&mut encoder);
+        let bytes = encoder.into_bytes();
+        let mut sut = Accounts::decode(&mut Decoder::new(&bytes)).unwrap();
+        sut.restore_history(accounts.history_since(0).to_vec())
+            .unwrap();
+
+        // Assert
+        assert_eq!(accounts, replayed);
+        assert_eq!(accounts, sut);
+        assert_eq!(1_500, sut.available_balance("client_1", USD));
+        assert_eq!(
+            Ok(requested),
+            sut.withdraw_idempotent("payout-1", "client_1", USD, 1_500)
+        );
+        assert_eq!(
+            Err(AccountingError::ApprovalPending(1)),
+            sut.withdraw_idempotent("payout-1", "client_1", USD, 1_600)
+        );
+        let withdrawal = sut.approve(1, "bob").unwrap();
+        assert_eq!(
+            Ok(withdrawal),
+            sut.withdraw_idempotent("payout-1", "client_1", USD, 1_500)
+        );
+    }
+
+    fn accounts_with_velocity_rules(clock: &ManualClock) -> Accounts {
+        let mut accounts = accounts_at(clock);
+        let rules = "\
+tier standard USD per-tx 10 daily 15
+tier premium USD daily 100
+account client_2 premium
+default standard
+";
+        let rules = VelocityRules::parse(rules, &accounts.assets).unwrap();
+        accounts.set_velocity_rules(rules);
+        accounts.deposit("client_1", USD, 5_000).unwrap();
+        accounts.deposit("client_2", USD, 5_000).unwrap();
         accounts
-            .deposit(sender, sender_deposit)
-            .expect("deposit failed");
+    }
 
-        let recipient = "client_2";
+    #[test]
+    fn velocity_limits_cap_each_operation_and_the_last_24_hours_by_tier() {
+        // Arrange
+        let clock = ManualClock::new(1_000_000);
+        let mut accounts = accounts_with_velocity_rules(&clock);
 
-        let transferred_amount = 50;
+        // Act
+        let over_tx = accounts.withdraw("client_1", USD, 1_100);
+        accounts.withdraw("client_1", USD, 1_000).unwrap();
+        clock.advance(3_600);
+        accounts.send("client_1", "client_3", USD, 400).unwrap();
+        let over_day = accounts.send("client_1", "client_3", USD, 200);
+        let premium = accounts.withdraw("client_2", USD, 3_000);
+        clock.advance(WINDOW - 3_600);
+        let after_a_day = accounts.send("client_1", "client_3", USD, 1_000);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::TxLimitExceeded(
+                "client_1".to_string(),
+                "standard".to_string(),
+                1_000
+            )),
+            over_tx
+        );
+        assert_eq!(
+            Err(AccountingError::DailyLimitExceeded(
+                "client_1".to_string(),
+                "standard".to_string(),
+                100
+            )),
+            over_day
+        );
+        assert!(premium.is_ok());
+        assert!(after_a_day.is_ok());
+        assert_eq!(2_600, accounts.total_balance("client_1", USD));
+        let replayed =
+            Accounts::replay_from(accounts.empty_like(), accounts.history_since(0).to_vec());
+        assert_eq!(Ok(accounts), replayed);
+    }
+
+    #[test]
+    fn holds_and_captures_are_checked_like_withdrawals() {
+        // Arrange
+        let clock = ManualClock::new(1_000_000);
+        let mut accounts = accounts_with_velocity_rules(&clock);
+        accounts.set_approval_threshold(USD, Some(900)).unwrap();
 
         // Act
-        let sut = accounts.send(sender, recipient, transferred_amount);
+        let over_tx = accounts.hold("client_1", USD, 1_100);
+        let over_threshold = accounts.hold("client_2", USD, 950);
+        accounts.hold("client_1", USD, 800).unwrap();
+        accounts.withdraw("client_1", USD, 800).unwrap();
+        clock.advance(60);
+        let over_day = accounts.capture(1);
+        accounts.withdraw("client_1", USD, 100).unwrap();
 
         // Assert
         assert_eq!(
-            (
-                Tx::Withdraw {
-                    account: sender.to_string(),
-                    amount: transferred_amount
-                },
-                Tx::Deposit {
-                    account: recipient.to_string(),
-                    amount: transferred_amount
-                }
-            ),
-            sut.unwrap()
+            Err(AccountingError::TxLimitExceeded(
+                "client_1".to_string(),
+                "standard".to_string(),
+                1_000
+            )),
+            over_tx
         );
         assert_eq!(
-            accounts.accounts[sender],
-            sender_deposit - transferred_amount
+            Err(AccountingError::ApprovalRequired(USD.to_string(), 900)),
+            over_threshold
+        );
+        assert_eq!(
+            Err(AccountingError::DailyLimitExceeded(
+                "client_1".to_string(),
+                "standard".to_string(),
+                700
+            )),
+            over_day
+        );
+        assert_eq!(3_300, accounts.available_balance("client_1", USD));
+        clock.advance(WINDOW);
+        accounts.capture(1).unwrap();
+        assert_eq!(800, accounts.outflow("client_1", USD));
+        assert_eq!(
+            Err(AccountingError::DailyLimitExceeded(
+                "client_1".to_string(),
+                "standard".to_string(),
+                700
+            )),
+            accounts.withdraw("client_1", USD, 800)
+        );
+        let replayed =
+            Accounts::replay_from(accounts.empty_like(), accounts.history_since(0).to_vec());
+        assert_eq!(Ok(accounts), replayed);
+    }
+
+    #[test]
+    fn batches_and_approvals_count_towards_velocity_limits() {
+        // Arrange
+        let clock = ManualClock::new(1_000_000);
+        let mut accounts = accounts_with_velocity_rules(&clock);
+        accounts.set_approval_threshold(USD, Some(900)).unwrap();
+        let requested = accounts
+            .as_operator("alice", |ledger| ledger.withdraw("client_1", USD, 1_000))
+            .unwrap();
+        let Tx::ApprovalRequested { id, .. } = requested.tx else {
+            panic!("expected a request, got {:?}", requested.tx);
+        };
+        let previous_accounts = accounts.clone();
+
+        // Act
+        let batch = accounts.apply_batch(vec![
+            leg("client_1", "client_3", 800),
+            leg("client_1", "client_3", 800),
+        ]);
+        accounts.send("client_1", "client_3", USD, 800).unwrap();
+        let approved = accounts.approve(id, "bob");
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::BatchLegFailed(
+                1,
+                Box::new(AccountingError::DailyLimitExceeded(
+                    "client_1".to_string(),
+                    "standard".to_string(),
+                    700
+                ))
+            )),
+            batch
+        );
+        assert_eq!(
+            Err(AccountingError::DailyLimitExceeded(
+                "client_1".to_string(),
+                "standard".to_string(),
+                700
+            )),
+            approved
+        );
+        assert_eq!(1, accounts.pending_approvals().len());
+        assert_eq!(
+            previous_accounts.total_balance("client_1", USD) - 800,
+            accounts.total_balance("client_1", USD)
         );
-        assert_eq!(accounts.accounts[recipient], transferred_amount);
     }
 }
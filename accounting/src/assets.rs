@@ -0,0 +1,224 @@
+use std::{collections::BTreeMap, io};
+
+use crate::{
+    codec::{Decode, Decoder, Encode, Encoder},
+    errors::AccountingError,
+};
+
+/// The largest number of decimals an [`Asset`] can have while one whole unit still fits in a `u64`
+pub const MAX_DECIMALS: u8 = 18;
+
+/// A currency or instrument that balances can be held in.
+///
+/// Amounts are always stored in minor units, e.g. cents for an asset with two decimals.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Asset {
+    /// A unique identifier such as `"USD"` or `"BTC"`
+    pub code: String,
+    /// Number of digits after the decimal point in the asset's major unit
+    pub decimals: u8,
+}
+
+impl Asset {
+    /// Formats an amount of minor units in major units, e.g. `150` with two decimals as `"1.50"`.
+    pub fn format(&self, amount: u64) -> String {
+        self.format_minor_units(u128::from(amount))
+    }
+
+    /// Formats a signed amount of minor units, e.g. `-150` with two decimals as `"-1.50"`.
+    pub fn format_signed(&self, amount: i128) -> String {
+        let sign = if amount < 0 { "-" } else { "" };
+        format!("{sign}{}", self.format_minor_units(amount.unsigned_abs()))
+    }
+
+    fn format_minor_units(&self, amount: u128) -> String {
+        let decimals = self.decimals as usize;
+        if decimals == 0 {
+            return amount.to_string();
+        }
+
+        let digits = format!("{amount:0>width$}", width = decimals + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - decimals);
+        format!("{whole}.{fraction}")
+    }
+
+    /// Parses an amount in major units into minor units, e.g. `"1.5"` with two decimals as `150`.
+    ///
+    /// Returns `None` if `value` is not a non-negative decimal number, has more decimals
+    /// than the asset or does not fit in a `u64`.
+    pub fn parse(&self, value: &str) -> Option<u64> {
+        let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        if fraction.len() > self.decimals as usize
+            || !whole
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+
+        let scale = 10u64.pow(u32::from(self.decimals));
+        let whole = if whole.is_empty() {
+            0
+        } else {
+            whole.parse::<u64>().ok()?
+        };
+        let fraction = if fraction.is_empty() {
+            0
+        } else {
+            fraction.parse::<u64>().ok()?
+                * 10u64.pow((self.decimals as usize - fraction.len()) as u32)
+        };
+
+        whole.checked_mul(scale)?.checked_add(fraction)
+    }
+}
+
+/// The assets an [`crate::accounts::Accounts`] ledger accepts, keyed by code
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AssetRegistry {
+    assets: BTreeMap<String, Asset>,
+}
+
+impl AssetRegistry {
+    /// Returns the asset registered under `code`
+    pub fn get(&self, code: &str) -> Option<&Asset> {
+        self.assets.get(code)
+    }
+
+    /// Iterates over the registered assets ordered by code
+    pub fn iter(&self) -> impl Iterator<Item = &Asset> {
+        self.assets.values()
+    }
+
+    /// Adds `asset` to the registry.
+    ///
+    /// # Errors
+    /// - an asset with the same code already exists
+    /// - the asset has more than [`MAX_DECIMALS`] decimals
+    pub(crate) fn register(&mut self, asset: Asset) -> Result<(), AccountingError> {
+        if asset.decimals > MAX_DECIMALS {
+            return Err(AccountingError::InvalidAssetPrecision(
+                asset.code,
+                asset.decimals,
+            ));
+        }
+        if self.assets.contains_key(&asset.code) {
+            return Err(AccountingError::AssetAlreadyRegistered(asset.code));
+        }
+
+        self.assets.insert(asset.code.clone(), asset);
+        Ok(())
+    }
+
+    /// Returns the asset registered under `code`.
+    ///
+    /// # Errors
+    /// - no asset is registered under `code`
+    pub(crate) fn require(&self, code: &str) -> Result<&Asset, AccountingError> {
+        self.get(code)
+            .ok_or_else(|| AccountingError::UnknownAsset(code.to_string()))
+    }
+}
+
+impl Encode for AssetRegistry {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_u64(self.assets.len() as u64);
+        for asset in self.assets.values() {
+            encoder.put_str(&asset.code);
+            encoder.put_u8(asset.decimals);
+        }
+    }
+}
+
+impl Decode for AssetRegistry {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        let mut assets = BTreeMap::new();
+        for _ in 0..decoder.get_u64()? {
+            let code = decoder.get_str()?;
+            let decimals = decoder.get_u8()?;
+            assets.insert(code.clone(), Asset { code, decimals });
+        }
+        Ok(AssetRegistry { assets })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{errors::AccountingError, test_utils::usd};
+
+    use super::{Asset, AssetRegistry};
+
+    #[test]
+    fn amounts_are_formatted_with_the_asset_precision() {
+        let btc = Asset {
+            code: "BTC".to_string(),
+            decimals: 8,
+        };
+        let jpy = Asset {
+            code: "JPY".to_string(),
+            decimals: 0,
+        };
+
+        assert_eq!("1.50", usd().format(150));
+        assert_eq!("0.05", usd().format(5));
+        assert_eq!("0.00000001", btc.format(1));
+        assert_eq!("150", jpy.format(150));
+        assert_eq!("-0.05", usd().format_signed(-5));
+        assert_eq!("-150", jpy.format_signed(-150));
+    }
+
+    #[test]
+    fn amounts_are_parsed_into_minor_units() {
+        assert_eq!(Some(150), usd().parse("1.5"));
+        assert_eq!(Some(150), usd().parse("1.50"));
+        assert_eq!(Some(100), usd().parse("1"));
+        assert_eq!(Some(5), usd().parse(".05"));
+        assert_eq!(None, usd().parse("1.505"));
+        assert_eq!(None, usd().parse("-1"));
+        assert_eq!(None, usd().parse("abc"));
+        assert_eq!(None, usd().parse("."));
+        assert_eq!(None, usd().parse("184467440737095516.16"));
+    }
+
+    #[test]
+    fn errors_when_registering_an_asset_twice() {
+        // Arrange
+        let mut registry = AssetRegistry::default();
+        registry.register(usd()).unwrap();
+
+        // Act
+        let sut = registry.register(usd());
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::AssetAlreadyRegistered("USD".to_string())),
+            sut
+        );
+    }
+
+    #[test]
+    fn errors_when_an_asset_is_too_precise() {
+        // Arrange
+        let mut registry = AssetRegistry::default();
+
+        // Act
+        let sut = registry.register(Asset {
+            code: "DUST".to_string(),
+            decimals: 19,
+        });
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::InvalidAssetPrecision(
+                "DUST".to_string(),
+                19
+            )),
+            sut
+        );
+        assert_eq!(None, registry.get("DUST"));
+    }
+}
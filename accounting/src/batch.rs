@@ -0,0 +1,493 @@
+//! Non-interactive processing of transactions read from CSV.
+//!
+//! Every row is `type, client, tx, amount`, e.g. `deposit, 1, 7, 1.5`, with amounts in
+//! the major units of the single asset the batch is in and an optional header row.
+//! Disputes, resolutions and chargebacks refer to an earlier deposit of the same client
+//! by its tx id and leave the amount empty, e.g. `dispute, 1, 7,`.
+//! Rows are read and applied one at a time and the ledger keeps no history, so the
+//! input never has to fit in memory. Besides balances, only the client, amount and
+//! sequence id of each deposit and the id of each withdrawal are kept.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    accounts::{AccountOrder, AccountState, Accounts},
+    assets::Asset,
+    errors::AccountingError,
+};
+
+/// The first field of a header row
+const HEADER: &str = "type";
+
+/// A row of the input
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Deposit {
+        client: String,
+        tx: u64,
+        amount: u64,
+    },
+    Withdrawal {
+        client: String,
+        tx: u64,
+        amount: u64,
+    },
+    Dispute {
+        client: String,
+        tx: u64,
+    },
+    Resolve {
+        client: String,
+        tx: u64,
+    },
+    Chargeback {
+        client: String,
+        tx: u64,
+    },
+}
+
+/// Why a row was not applied
+#[derive(Debug, PartialEq, Eq)]
+pub enum RowError {
+    /// The row is not `type, client, tx, amount` with a known type and, for deposits and
+    /// withdrawals, a valid amount
+    Malformed(String),
+    /// The ledger refused the operation
+    Rejected(AccountingError),
+}
+
+/// How many rows a call to [`BatchProcessor::process`] read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub applied: u64,
+    pub rejected: u64,
+}
+
+impl Operation {
+    /// Parses a row with its amount in `asset`'s major units; fields may be padded with spaces.
+    ///
+    /// # Errors
+    /// - [`RowError::Malformed`] if a field is missing, unknown or invalid
+    pub fn parse(row: &str, asset: &Asset) -> Result<Operation, RowError> {
+        let malformed = |reason: &str| RowError::Malformed(format!("{reason} in '{row}'"));
+        let mut fields = row.split(',').map(str::trim);
+        let kind = fields.next().unwrap_or_default();
+        let client = match fields.next() {
+            Some(client) if !client.is_empty() => client.to_string(),
+            _ => return Err(malformed("missing client")),
+        };
+        let tx = fields
+            .next()
+            .and_then(|tx| tx.parse().ok())
+            .ok_or_else(|| malformed("invalid tx id"))?;
+        let amount = fields.next().filter(|amount| !amount.is_empty());
+        if fields.next().is_some() {
+            return Err(malformed("too many fields"));
+        }
+        if amount.is_some() && matches!(kind, "dispute" | "resolve" | "chargeback") {
+            return Err(malformed("unexpected amount"));
+        }
+        let amount = || {
+            amount
+                .and_then(|amount| asset.parse(amount))
+                .ok_or_else(|| malformed("invalid amount"))
+        };
+
+        match kind {
+            "deposit" => Ok(Operation::Deposit {
+                client,
+                tx,
+                amount: amount()?,
+            }),
+            "withdrawal" => Ok(Operation::Withdrawal {
+                client,
+                tx,
+                amount: amount()?,
+            }),
+            "dispute" => Ok(Operation::Dispute { client, tx }),
+            "resolve" => Ok(Operation::Resolve { client, tx }),
+            "chargeback" => Ok(Operation::Chargeback { client, tx }),
+            _ => Err(malformed("unknown type")),
+        }
+    }
+}
+
+/// Applies [`Operation`]s in a single asset to a ledger without history.
+///
+/// Every deposit and withdrawal is remembered by its tx id for as long as the processor
+/// lives, so memory grows with the number of rows applied. Deposits cannot be forgotten,
+/// as any of them may be disputed later; withdrawals cost one id each and are kept too,
+/// so a repeated row is caught however far apart the copies are.
+#[derive(Clone, Debug)]
+pub struct BatchProcessor {
+    accounts: Accounts,
+    asset: Asset,
+    /// The client, amount and sequence id of every deposit, keyed by its tx id
+    deposits: HashMap<u64, (String, u64, u64)>,
+    /// The tx id of every withdrawal
+    withdrawals: HashSet<u64>,
+}
+
+impl BatchProcessor {
+    /// A processor with an empty ledger holding only `asset`.
+    ///
+    /// # Errors
+    /// - the same errors as [`Accounts::register_asset`]
+    pub fn new(asset: Asset) -> Result<Self, AccountingError> {
+        let mut accounts = Accounts::new();
+        accounts.discard_history();
+        accounts.register_asset(&asset.code, asset.decimals)?;
+        Ok(BatchProcessor {
+            accounts,
+            asset,
+            deposits: HashMap::new(),
+            withdrawals: HashSet::new(),
+        })
+    }
+
+    /// The ledger operations are applied to
+    pub fn accounts(&self) -> &Accounts {
+        &self.accounts
+    }
+
+    /// Applies `operation`; a deposit or withdrawal whose tx id was already applied is
+    /// skipped, so a repeated row is only applied once.
+    ///
+    /// # Errors
+    /// - a deposit reuses the tx id of a withdrawal or of a different deposit
+    /// - a withdrawal reuses the tx id of a deposit
+    /// - a dispute, resolution or chargeback refers to a deposit of another client
+    /// - the same errors as the [`Accounts`] method the operation maps to
+    pub fn apply(&mut self, operation: &Operation) -> Result<(), AccountingError> {
+        let asset = self.asset.code.as_str();
+        match operation {
+            Operation::Deposit { client, tx, amount } => {
+                match self.deposits.get(tx) {
+                    Some((account, deposited, _)) if account == client && deposited == amount => {
+                        return Ok(())
+                    }
+                    None if !self.withdrawals.contains(tx) => {}
+                    _ => return Err(AccountingError::DuplicateTxId(*tx)),
+                }
+                let deposit = self.accounts.deposit(client, asset, *amount)?;
+                self.deposits
+                    .insert(*tx, (client.clone(), *amount, deposit.seq));
+            }
+            Operation::Withdrawal { client, tx, amount } => {
+                if self.deposits.contains_key(tx) {
+                    return Err(AccountingError::DuplicateTxId(*tx));
+                }
+                if self.withdrawals.contains(tx) {
+                    return Ok(());
+                }
+                self.accounts.withdraw(client, asset, *amount)?;
+                self.withdrawals.insert(*tx);
+            }
+            Operation::Dispute { client, tx } => {
+                // The ledger keeps no history to look the deposit up in
+                let (amount, seq) = self.deposit(client, *tx)?;
+                self.accounts.dispute_deposit(seq, client, asset, amount)?;
+            }
+            Operation::Resolve { client, tx } => {
+                let (_, seq) = self.deposit(client, *tx)?;
+                self.accounts.resolve(seq)?;
+            }
+            Operation::Chargeback { client, tx } => {
+                let (_, seq) = self.deposit(client, *tx)?;
+                self.accounts.chargeback(seq)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The amount and sequence id of the `client`'s deposit with id `tx`.
+    ///
+    /// # Errors
+    /// - the client made no deposit with this id
+    fn deposit(&self, client: &str, tx: u64) -> Result<(u64, u64), AccountingError> {
+        match self.deposits.get(&tx) {
+            Some((account, amount, seq)) if account == client => Ok((*amount, *seq)),
+            _ => Err(AccountingError::DepositNotFound(tx)),
+        }
+    }
+
+    /// Reads and applies `input` row by row, reporting every rejected row with its line
+    /// number and [`RowError`] to `rejected`. Blank lines and a header row are skipped.
+    ///
+    /// # Errors
+    /// - reading `input` or writing to `rejected` fails
+    pub fn process(
+        &mut self,
+        mut input: impl BufRead,
+        mut rejected: impl Write,
+    ) -> io::Result<BatchSummary> {
+        let mut summary = BatchSummary::default();
+        let mut row = String::new();
+        let mut line = 0;
+        loop {
+            row.clear();
+            if input.read_line(&mut row)? == 0 {
+                return Ok(summary);
+            }
+            line += 1;
+            let row = row.trim();
+            let is_header = line == 1 && row.split(',').next().map(str::trim) == Some(HEADER);
+            if row.is_empty() || is_header {
+                continue;
+            }
+
+            let outcome = Operation::parse(row, &self.asset)
+                .and_then(|operation| self.apply(&operation).map_err(RowError::Rejected));
+            match outcome {
+                Ok(()) => summary.applied += 1,
+                Err(error) => {
+                    summary.rejected += 1;
+                    writeln!(rejected, "line {line}: {error:?}")?;
+                }
+            }
+        }
+    }
+
+    /// Reads and applies `input` like [`BatchProcessor::process`], but to a copy of the
+    /// ledger, so this processor is left as it was.
+    ///
+    /// # Errors
+    /// - reading `input` or writing to `rejected` fails
+    pub fn dry_run(&self, input: impl BufRead, rejected: impl Write) -> io::Result<BatchSummary> {
+        self.clone().process(input, rejected)
+    }
+
+    /// Writes every account's balance as CSV with a header row, ordered by client.
+    ///
+    /// `total` is net of drawn credit and `locked` is `true` for accounts locked by a chargeback.
+    ///
+    /// # Errors
+    /// - writing to `output` fails
+    pub fn write_balances(&self, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "client,available,held,total,locked")?;
+        for account in self.accounts.list_accounts(AccountOrder::Name) {
+            let balance = account
+                .balances
+                .get(self.asset.code.as_str())
+                .copied()
+                .unwrap_or_default();
+            writeln!(
+                output,
+                "{},{},{},{},{}",
+                account.signer,
+                self.asset
+                    .format(balance.total.saturating_sub(balance.held)),
+                self.asset.format(balance.held),
+                self.asset.format_signed(balance.net()),
+                account.state == AccountState::Locked
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assets::Asset, errors::AccountingError, test_utils::usd};
+
+    use super::{BatchProcessor, BatchSummary, Operation, RowError};
+
+    /// USD with the four decimals of the input format
+    fn usd_4dp() -> Asset {
+        Asset {
+            decimals: 4,
+            ..usd()
+        }
+    }
+
+    #[test]
+    fn rows_are_parsed_with_padded_fields() {
+        // Arrange
+        let rows = [
+            "deposit, 1, 7, 1.5",
+            "withdrawal,2,8,0.0001",
+            "deposit, 1, 9",
+            "refund, 1, 10, 1.0",
+            "dispute, 1, 7,",
+        ];
+
+        // Act
+        let sut = rows.map(|row| Operation::parse(row, &usd_4dp()));
+
+        // Assert
+        assert_eq!(
+            Ok(Operation::Deposit {
+                client: "1".to_string(),
+                tx: 7,
+                amount: 15_000
+            }),
+            sut[0]
+        );
+        assert_eq!(
+            Ok(Operation::Withdrawal {
+                client: "2".to_string(),
+                tx: 8,
+                amount: 1
+            }),
+            sut[1]
+        );
+        assert!(matches!(sut[2], Err(RowError::Malformed(_))));
+        assert!(matches!(sut[3], Err(RowError::Malformed(_))));
+        assert_eq!(
+            Ok(Operation::Dispute {
+                client: "1".to_string(),
+                tx: 7
+            }),
+            sut[4]
+        );
+    }
+
+    #[test]
+    fn processing_applies_valid_rows_and_reports_rejected_ones() {
+        // Arrange
+        let input = "type, client, tx, amount\n\
+                     deposit, 2, 1, 2.0\n\
+                     deposit, 1, 2, 10.0\n\
+                     withdrawal, 1, 3, 4.5\n\
+                     withdrawal, 2, 4, 3.0\n\
+                     \n\
+                     deposit, 1, 2, 10.0\n";
+        let mut sut = BatchProcessor::new(usd_4dp()).unwrap();
+        let mut rejected = vec![];
+        let mut balances = vec![];
+
+        // Act
+        let summary = sut.process(input.as_bytes(), &mut rejected).unwrap();
+        sut.write_balances(&mut balances).unwrap();
+
+        // Assert
+        assert_eq!(
+            BatchSummary {
+                applied: 4,
+                rejected: 1
+            },
+            summary
+        );
+        assert_eq!(
+            format!(
+                "line 5: {:?}\n",
+                RowError::Rejected(AccountingError::AccountUnderFunded("2".to_string(), 30_000))
+            ),
+            String::from_utf8(rejected).unwrap()
+        );
+        assert_eq!(
+            "client,available,held,total,locked\n\
+             1,5.5000,0.0000,5.5000,false\n\
+             2,2.0000,0.0000,2.0000,false\n",
+            String::from_utf8(balances).unwrap()
+        );
+    }
+
+    #[test]
+    fn dry_runs_report_rejected_rows_and_apply_nothing() {
+        // Arrange
+        let input = "deposit, 1, 1, 5.0\n\
+                     withdrawal, 1, 2, 6.0\n";
+        let sut = BatchProcessor::new(usd_4dp()).unwrap();
+        let mut rejected = vec![];
+        let mut balances = vec![];
+
+        // Act
+        let summary = sut.dry_run(input.as_bytes(), &mut rejected).unwrap();
+        sut.write_balances(&mut balances).unwrap();
+
+        // Assert
+        assert_eq!(
+            BatchSummary {
+                applied: 1,
+                rejected: 1
+            },
+            summary
+        );
+        assert!(String::from_utf8(rejected).unwrap().starts_with("line 2: "));
+        assert_eq!(
+            "client,available,held,total,locked\n",
+            String::from_utf8(balances).unwrap()
+        );
+    }
+
+    #[test]
+    fn repeated_tx_ids_are_skipped_or_rejected_if_they_differ() {
+        // Arrange
+        let input = "deposit, 1, 1, 5.0\n\
+                     withdrawal, 1, 2, 1.0\n\
+                     deposit, 1, 1, 5.0\n\
+                     withdrawal, 1, 2, 1.0\n\
+                     deposit, 1, 1, 6.0\n\
+                     deposit, 1, 2, 1.0\n\
+                     withdrawal, 1, 1, 1.0\n";
+        let mut sut = BatchProcessor::new(usd_4dp()).unwrap();
+        let mut rejected = vec![];
+        let mut balances = vec![];
+
+        // Act
+        let summary = sut.process(input.as_bytes(), &mut rejected).unwrap();
+        sut.write_balances(&mut balances).unwrap();
+
+        // Assert
+        assert_eq!(
+            BatchSummary {
+                applied: 4,
+                rejected: 3
+            },
+            summary
+        );
+        assert_eq!(
+            format!(
+                "line 5: {:?}\nline 6: {:?}\nline 7: {:?}\n",
+                RowError::Rejected(AccountingError::DuplicateTxId(1)),
+                RowError::Rejected(AccountingError::DuplicateTxId(2)),
+                RowError::Rejected(AccountingError::DuplicateTxId(1))
+            ),
+            String::from_utf8(rejected).unwrap()
+        );
+        assert_eq!(
+            "client,available,held,total,locked\n\
+             1,4.0000,0.0000,4.0000,false\n",
+            String::from_utf8(balances).unwrap()
+        );
+    }
+
+    #[test]
+    fn disputes_refer_to_deposits_of_the_same_client_by_tx_id() {
+        // Arrange
+        let input = "deposit, 1, 1, 5.0\n\
+                     deposit, 1, 2, 1.0\n\
+                     deposit, 2, 3, 1.0\n\
+                     dispute, 2, 1,\n\
+                     dispute, 1, 1,\n\
+                     chargeback, 1, 1,\n\
+                     dispute, 2, 3,\n\
+                     resolve, 2, 3,\n";
+        let mut sut = BatchProcessor::new(usd_4dp()).unwrap();
+        let mut rejected = vec![];
+        let mut balances = vec![];
+
+        // Act
+        sut.process(input.as_bytes(), &mut rejected).unwrap();
+        sut.write_balances(&mut balances).unwrap();
+
+        // Assert
+        assert_eq!(
+            format!(
+                "line 4: {:?}\n",
+                RowError::Rejected(AccountingError::DepositNotFound(1))
+            ),
+            String::from_utf8(rejected).unwrap()
+        );
+        assert_eq!(
+            "client,available,held,total,locked\n\
+             1,1.0000,0.0000,1.0000,true\n\
+             2,1.0000,0.0000,1.0000,false\n",
+            String::from_utf8(balances).unwrap()
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Applies a CSV of transactions to an empty ledger and writes the final balances.
+//!
+//! Usage: `accounting-batch [<input.csv>] [--asset <code>] [--decimals <n>] [--dry-run]`
+//!
+//! Reads stdin when no input file is given. Balances go to stdout as CSV and every
+//! rejected row is reported on stderr. With `--dry-run` no balances are written: the
+//! rejected rows and a count of the rows that would be applied go to stdout instead.
+
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    process,
+};
+
+use accounting::{assets::Asset, batch::BatchProcessor};
+
+/// Used when `--asset` is not set
+const DEFAULT_ASSET: &str = "USD";
+/// Used when `--decimals` is not set
+const DEFAULT_DECIMALS: u8 = 4;
+
+/// Flags that take no value
+const SWITCHES: [&str; 1] = ["--dry-run"];
+
+/// Returns the value following `--<name>` on the command line, if any.
+fn flag(name: &str) -> Option<String> {
+    let flag = format!("--{name}");
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+    }
+    None
+}
+
+/// Returns whether `--<name>` is on the command line.
+fn switch(name: &str) -> bool {
+    let switch = format!("--{name}");
+    env::args().skip(1).any(|arg| arg == switch)
+}
+
+/// Returns the first command line argument that is neither a flag nor a flag's value.
+fn input_path() -> Option<String> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if SWITCHES.contains(&arg.as_str()) {
+            continue;
+        }
+        if arg.starts_with("--") {
+            args.next();
+        } else {
+            return Some(arg);
+        }
+    }
+    None
+}
+
+fn main() {
+    let asset = Asset {
+        code: flag("asset").unwrap_or_else(|| DEFAULT_ASSET.to_string()),
+        decimals: flag("decimals")
+            .map(|decimals| decimals.parse().expect("decimals must be a number"))
+            .unwrap_or(DEFAULT_DECIMALS),
+    };
+    let mut processor = BatchProcessor::new(asset).unwrap_or_else(|accounting_error| {
+        eprintln!("{accounting_error:?}");
+        process::exit(2);
+    });
+
+    let input: Box<dyn BufRead> = match input_path() {
+        Some(path) => match File::open(&path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                eprintln!("Cannot open {path}: {e}");
+                process::exit(2);
+            }
+        },
+        None => Box::new(io::stdin().lock()),
+    };
+
+    if switch("dry-run") {
+        match processor.dry_run(input, io::stdout().lock()) {
+            Ok(summary) => println!(
+                "{} rows would be applied and {} rejected",
+                summary.applied, summary.rejected
+            ),
+            Err(e) => {
+                eprintln!("Cannot process the input: {e}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Err(e) = processor.process(input, io::stderr().lock()) {
+        eprintln!("Cannot process the input: {e}");
+        process::exit(1);
+    }
+
+    let mut balances = BufWriter::new(io::stdout().lock());
+    if let Err(e) = processor
+        .write_balances(&mut balances)
+        .and_then(|()| balances.flush())
+    {
+        eprintln!("Cannot write the balances: {e}");
+        process::exit(1);
+    }
+}
@@ -0,0 +1,509 @@
+//! Double-entry bookkeeping on top of the [`Tx`] log.
+//!
+//! Every transaction that moves money becomes a [`JournalEntry`] whose debit and credit
+//! postings cancel out. Money entering or leaving the platform is posted against the
+//! system clearing accounts, so customer balances are always matched by an opposite
+//! posting somewhere in the books.
+
+use std::{collections::BTreeMap, io};
+
+use crate::{
+    accounts::{AccountOrder, Accounts},
+    codec::{Decode, Decoder, Encode, Encoder},
+    errors::AccountingError,
+    fees::FEE_ACCOUNT,
+    tx::Tx,
+};
+
+/// Clearing account debited when money enters the platform through a deposit
+pub const CASH_IN_ACCOUNT: &str = "system:cash-in";
+/// Clearing account credited when money leaves the platform through a withdrawal
+pub const CASH_OUT_ACCOUNT: &str = "system:cash-out";
+/// Clearing account for interest: debited when it is paid, credited when it is charged
+pub const INTEREST_ACCOUNT: &str = "system:interest";
+/// Prefix of the platform's own accounts, as opposed to customer accounts
+pub const SYSTEM_ACCOUNT_PREFIX: &str = "system:";
+
+/// Which column of the books a [`Posting`] is written to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PostingSide {
+    Debit,
+    Credit,
+}
+
+/// A single line of a [`JournalEntry`]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Posting {
+    pub account: String,
+    pub asset: String,
+    pub side: PostingSide,
+    pub amount: u64,
+}
+
+/// The postings recorded for one [`Tx`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub postings: Vec<Posting>,
+}
+
+impl JournalEntry {
+    /// Builds the entry for `tx`; `None` if the transaction does not move money.
+    pub fn for_tx(tx: &Tx) -> Option<JournalEntry> {
+        let postings = match tx {
+            Tx::Deposit {
+                account,
+                asset,
+                amount,
+                ..
+            } => pair(CASH_IN_ACCOUNT, account, asset, *amount),
+            Tx::Withdraw {
+                account,
+                asset,
+                amount,
+                ..
+            } => pair(account, CASH_OUT_ACCOUNT, asset, *amount),
+            Tx::Transfer {
+                from,
+                to,
+                asset,
+                amount,
+                ..
+            } => pair(from, to, asset, *amount),
+            Tx::Capture {
+                account,
+                asset,
+                amount,
+                ..
+            } => pair(account, CASH_OUT_ACCOUNT, asset, *amount),
+            Tx::Fee {
+                account,
+                asset,
+                amount,
+            } => pair(account, FEE_ACCOUNT, asset, *amount),
+            // Charging a deposit back returns its funds to where they came from
+            Tx::Chargeback {
+                account,
+                asset,
+                amount,
+                ..
+            } => pair(account, CASH_IN_ACCOUNT, asset, *amount),
+            Tx::InterestPaid {
+                account,
+                asset,
+                amount,
+                ..
+            } => pair(INTEREST_ACCOUNT, account, asset, *amount),
+            Tx::InterestCharged {
+                account,
+                asset,
+                amount,
+                ..
+            } => pair(account, INTEREST_ACCOUNT, asset, *amount),
+            // A reversal posts the entry of the transaction it undoes with the sides swapped
+            Tx::Reversal { tx, .. } => {
+                let mut postings = JournalEntry::for_tx(tx)?.postings;
+                for posting in &mut postings {
+                    posting.side = match posting.side {
+                        PostingSide::Debit => PostingSide::Credit,
+                        PostingSide::Credit => PostingSide::Debit,
+                    };
+                }
+                postings
+            }
+            // Holds and approval requests reserve funds without moving them, and lifecycle
+            // changes move none
+            Tx::RegisterAsset { .. }
+            | Tx::Hold { .. }
+            | Tx::Release { .. }
+            | Tx::Dispute { .. }
+            | Tx::Resolve { .. }
+            | Tx::OpenAccount { .. }
+            | Tx::FreezeAccount { .. }
+            | Tx::UnfreezeAccount { .. }
+            | Tx::CloseAccount { .. }
+            | Tx::SetFee { .. }
+            | Tx::SetCreditLimit { .. }
+            | Tx::SetApprovalThreshold { .. }
+            | Tx::ApprovalRequested { .. }
+            | Tx::Approved { .. }
+            | Tx::ApprovalRejected { .. } => return None,
+        };
+        Some(JournalEntry { postings })
+    }
+
+    /// Returns `true` if, for every asset, the debits equal the credits
+    pub fn is_balanced(&self) -> bool {
+        let mut totals = BTreeMap::<&str, Totals>::new();
+        for posting in &self.postings {
+            totals
+                .entry(posting.asset.as_str())
+                .or_default()
+                .add(posting.side, posting.amount);
+        }
+        totals
+            .values()
+            .all(|totals| totals.debits == totals.credits)
+    }
+}
+
+/// Whether `account` belongs to the platform rather than a customer
+pub fn is_system_account(account: &str) -> bool {
+    account.starts_with(SYSTEM_ACCOUNT_PREFIX)
+}
+
+/// Debits `debit` and credits `credit` with the same amount
+fn pair(debit: &str, credit: &str, asset: &str, amount: u64) -> Vec<Posting> {
+    vec![
+        Posting {
+            account: debit.to_string(),
+            asset: asset.to_string(),
+            side: PostingSide::Debit,
+            amount,
+        },
+        Posting {
+            account: credit.to_string(),
+            asset: asset.to_string(),
+            side: PostingSide::Credit,
+            amount,
+        },
+    ]
+}
+
+/// Running debit and credit totals; wide enough to never overflow on `u64` postings
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub debits: u128,
+    pub credits: u128,
+}
+
+impl Totals {
+    fn add(&mut self, side: PostingSide, amount: u64) {
+        match side {
+            PostingSide::Debit => self.debits += u128::from(amount),
+            PostingSide::Credit => self.credits += u128::from(amount),
+        }
+    }
+}
+
+/// Debit and credit totals of every account that has been posted to, per asset
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GeneralLedger {
+    /// Keyed by `(account, asset)`
+    totals: BTreeMap<(String, String), Totals>,
+}
+
+impl GeneralLedger {
+    /// Posts the journal entry for `tx`, if it has one, and returns it.
+    pub fn post(&mut self, tx: &Tx) -> Option<JournalEntry> {
+        let entry = JournalEntry::for_tx(tx)?;
+        for posting in &entry.postings {
+            self.totals
+                .entry((posting.account.clone(), posting.asset.clone()))
+                .or_default()
+                .add(posting.side, posting.amount);
+        }
+        Some(entry)
+    }
+
+    /// Summarizes the totals of every account into a [`TrialBalance`]
+    pub fn trial_balance(&self) -> TrialBalance {
+        TrialBalance {
+            rows: self
+                .totals
+                .iter()
+                .map(|((account, asset), totals)| TrialBalanceRow {
+                    account: account.clone(),
+                    asset: asset.clone(),
+                    debits: totals.debits,
+                    credits: totals.credits,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Encode for GeneralLedger {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_u64(self.totals.len() as u64);
+        for ((account, asset), totals) in &self.totals {
+            encoder.put_str(account);
+            encoder.put_str(asset);
+            encoder.put_u128(totals.debits);
+            encoder.put_u128(totals.credits);
+        }
+    }
+}
+
+impl Decode for GeneralLedger {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        let mut totals = BTreeMap::new();
+        for _ in 0..decoder.get_u64()? {
+            let key = (decoder.get_str()?, decoder.get_str()?);
+            let value = Totals {
+                debits: decoder.get_u128()?,
+                credits: decoder.get_u128()?,
+            };
+            totals.insert(key, value);
+        }
+        Ok(GeneralLedger { totals })
+    }
+}
+
+/// One line of a [`TrialBalance`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrialBalanceRow {
+    pub account: String,
+    pub asset: String,
+    pub debits: u128,
+    pub credits: u128,
+}
+
+/// The debit and credit totals of every account, ordered by account and asset
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrialBalance {
+    pub rows: Vec<TrialBalanceRow>,
+}
+
+impl TrialBalance {
+    /// Sums the rows of each asset
+    pub fn totals(&self) -> BTreeMap<String, Totals> {
+        let mut totals = BTreeMap::<String, Totals>::new();
+        for row in &self.rows {
+            let asset_totals = totals.entry(row.asset.clone()).or_default();
+            asset_totals.debits += row.debits;
+            asset_totals.credits += row.credits;
+        }
+        totals
+    }
+
+    /// Checks that total debits equal total credits for every asset.
+    ///
+    /// # Errors
+    /// - the first asset whose books do not balance
+    pub fn assert_balanced(&self) -> Result<(), AccountingError> {
+        for (asset, totals) in self.totals() {
+            if totals.debits != totals.credits {
+                return Err(AccountingError::TrialBalanceMismatch(
+                    asset,
+                    totals.debits,
+                    totals.credits,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the net credit of every customer account in the books equals its
+    /// balance in `accounts`.
+    ///
+    /// Debits equal credits by construction of every [`JournalEntry`], so this is what
+    /// catches a balance that moved without being posted, or a posting without a move.
+    ///
+    /// # Errors
+    /// - the first account and asset whose balances differ
+    pub fn reconcile(&self, accounts: &Accounts) -> Result<(), AccountingError> {
+        let mut books = BTreeMap::new();
+        for row in self
+            .rows
+            .iter()
+            .filter(|row| !is_system_account(&row.account))
+        {
+            let net = row.credits as i128 - row.debits as i128;
+            books.insert((row.account.as_str(), row.asset.as_str()), net);
+        }
+
+        for summary in accounts.list_accounts(AccountOrder::Name) {
+            if is_system_account(summary.signer) {
+                continue;
+            }
+            for (asset, balance) in summary.balances {
+                let booked = books.remove(&(summary.signer, asset)).unwrap_or(0);
+                if booked != balance.net() {
+                    return Err(AccountingError::ReconciliationMismatch(
+                        summary.signer.to_string(),
+                        asset.to_string(),
+                        booked,
+                        balance.net(),
+                    ));
+                }
+            }
+        }
+        // Posted to in the books without a balance in the ledger
+        match books.into_iter().find(|(_, booked)| *booked != 0) {
+            Some(((account, asset), booked)) => Err(AccountingError::ReconciliationMismatch(
+                account.to_string(),
+                asset.to_string(),
+                booked,
+                0,
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{accounts::Accounts, errors::AccountingError, tx::Tx};
+
+    use super::{
+        GeneralLedger, JournalEntry, Posting, PostingSide, TrialBalance, TrialBalanceRow,
+        CASH_IN_ACCOUNT, CASH_OUT_ACCOUNT,
+    };
+
+    fn posting(account: &str, side: PostingSide, amount: u64) -> Posting {
+        Posting {
+            account: account.to_string(),
+            asset: "USD".to_string(),
+            side,
+            amount,
+        }
+    }
+
+    #[test]
+    fn deposits_are_debited_to_the_cash_in_account() {
+        // Arrange
+        let tx = Tx::Deposit {
+            account: "client_1".to_string(),
+            asset: "USD".to_string(),
+            amount: 100,
+            idempotency_key: None,
+        };
+
+        // Act
+        let sut = JournalEntry::for_tx(&tx).unwrap();
+
+        // Assert
+        assert_eq!(
+            vec![
+                posting(CASH_IN_ACCOUNT, PostingSide::Debit, 100),
+                posting("client_1", PostingSide::Credit, 100),
+            ],
+            sut.postings
+        );
+        assert!(sut.is_balanced());
+    }
+
+    #[test]
+    fn withdrawals_are_credited_to_the_cash_out_account() {
+        // Arrange
+        let tx = Tx::Withdraw {
+            account: "client_1".to_string(),
+            asset: "USD".to_string(),
+            amount: 40,
+            idempotency_key: None,
+        };
+
+        // Act
+        let sut = JournalEntry::for_tx(&tx).unwrap();
+
+        // Assert
+        assert_eq!(
+            vec![
+                posting("client_1", PostingSide::Debit, 40),
+                posting(CASH_OUT_ACCOUNT, PostingSide::Credit, 40),
+            ],
+            sut.postings
+        );
+    }
+
+    #[test]
+    fn the_trial_balance_of_posted_transactions_balances() {
+        // Arrange
+        let mut ledger = GeneralLedger::default();
+        let txs = [
+            Tx::Deposit {
+                account: "client_1".to_string(),
+                asset: "USD".to_string(),
+                amount: 100,
+                idempotency_key: None,
+            },
+            Tx::Transfer {
+                id: 1,
+                from: "client_1".to_string(),
+                to: "client_2".to_string(),
+                asset: "USD".to_string(),
+                amount: 30,
+                idempotency_key: None,
+            },
+            Tx::Withdraw {
+                account: "client_2".to_string(),
+                asset: "USD".to_string(),
+                amount: 10,
+                idempotency_key: None,
+            },
+        ];
+        for tx in &txs {
+            ledger.post(tx);
+        }
+
+        // Act
+        let sut = ledger.trial_balance();
+
+        // Assert
+        assert_eq!(Ok(()), sut.assert_balanced());
+        let totals = &sut.totals()["USD"];
+        assert_eq!(140, totals.debits);
+        assert_eq!(140, totals.credits);
+    }
+
+    #[test]
+    fn errors_when_the_trial_balance_does_not_balance() {
+        // Arrange
+        let sut = TrialBalance {
+            rows: vec![TrialBalanceRow {
+                account: "client_1".to_string(),
+                asset: "USD".to_string(),
+                debits: 0,
+                credits: 5,
+            }],
+        };
+
+        // Act
+        let result = sut.assert_balanced();
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::TrialBalanceMismatch(
+                "USD".to_string(),
+                0,
+                5
+            )),
+            result
+        );
+    }
+
+    #[test]
+    fn errors_when_a_customer_balance_differs_from_the_books() {
+        // Arrange
+        let mut accounts = Accounts::with_double_entry();
+        accounts.register_asset("USD", 2).unwrap();
+        accounts.deposit("client_1", "USD", 100).unwrap();
+        accounts.deposit("client_2", "USD", 50).unwrap();
+        let books = accounts.trial_balance().unwrap();
+        let mut tampered = books.clone();
+        for row in &mut tampered.rows {
+            if row.account == "client_2" {
+                row.credits -= 20;
+            }
+            if row.account == CASH_IN_ACCOUNT {
+                row.debits -= 20;
+            }
+        }
+
+        // Act
+        let sut = tampered.reconcile(&accounts);
+
+        // Assert
+        assert_eq!(Ok(()), books.reconcile(&accounts));
+        assert_eq!(Ok(()), tampered.assert_balanced());
+        assert_eq!(
+            Err(AccountingError::ReconciliationMismatch(
+                "client_2".to_string(),
+                "USD".to_string(),
+                30,
+                50
+            )),
+            sut
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Calendar dates in the proleptic Gregorian calendar, in UTC.
+//!
+//! Timestamps throughout the crate are seconds since the Unix epoch; a [`Date`] is
+//! the UTC day such a timestamp falls on.
+
+use std::fmt;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// A day in the calendar
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    pub year: i32,
+    /// `1` to `12`
+    pub month: u8,
+    /// `1` to the number of days in the month
+    pub day: u8,
+}
+
+impl Date {
+    /// Returns `None` if `month` or `day` is out of range
+    pub fn new(year: i32, month: u8, day: u8) -> Option<Date> {
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return None;
+        }
+        Some(Date { year, month, day })
+    }
+
+    /// The day `timestamp`, in seconds since the Unix epoch, falls on
+    pub fn from_timestamp(timestamp: u64) -> Date {
+        Date::from_days((timestamp / SECONDS_PER_DAY) as i64)
+    }
+
+    /// The first second of the day, in seconds since the Unix epoch; `0` before the epoch
+    pub fn timestamp(&self) -> u64 {
+        u64::try_from(self.days()).map_or(0, |days| days * SECONDS_PER_DAY)
+    }
+
+    /// The date `days` after 1970-01-01
+    pub fn from_days(days: i64) -> Date {
+        // Howard Hinnant's civil_from_days, with eras of 400 years starting on March 1st
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        } as u8;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        Date {
+            year: year as i32,
+            month,
+            day,
+        }
+    }
+
+    /// Days since 1970-01-01; negative before it
+    pub fn days(&self) -> i64 {
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let shifted_month = (i64::from(self.month) + 9) % 12;
+        let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    /// The date `days` later; earlier if negative
+    pub fn add_days(&self, days: i64) -> Date {
+        Date::from_days(self.days() + days)
+    }
+
+    /// The day after this one
+    pub fn next(&self) -> Date {
+        self.add_days(1)
+    }
+
+    /// The same day `months` later, clamped to the end of shorter months,
+    /// e.g. one month after January 31st is the last day of February
+    pub fn add_months(&self, months: u32) -> Date {
+        let index = i64::from(self.year) * 12 + i64::from(self.month) - 1 + i64::from(months);
+        let year = index.div_euclid(12) as i32;
+        let month = index.rem_euclid(12) as u8 + 1;
+        Date {
+            year,
+            month,
+            day: self.day.min(days_in_month(year, month)),
+        }
+    }
+
+    /// Whether this is the last day of its month
+    pub fn is_month_end(&self) -> bool {
+        self.day == days_in_month(self.year, self.month)
+    }
+
+    /// Parses an ISO 8601 date such as `2024-02-29`
+    pub fn parse(value: &str) -> Option<Date> {
+        let mut parts = value.splitn(3, '-');
+        let year = parts.next()?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let day = parts.next()?.parse().ok()?;
+        Date::new(year, month, day)
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+pub fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+pub fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Date;
+
+    #[test]
+    fn converts_between_days_and_dates() {
+        // Arrange
+        let dates = [
+            (0, Date::new(1970, 1, 1)),
+            (-1, Date::new(1969, 12, 31)),
+            (11_016, Date::new(2000, 2, 29)),
+            (19_782, Date::new(2024, 2, 29)),
+        ];
+
+        // Act & Assert
+        for (days, date) in dates {
+            let date = date.unwrap();
+            assert_eq!(date, Date::from_days(days));
+            assert_eq!(days, date.days());
+        }
+    }
+
+    #[test]
+    fn timestamps_fall_on_their_utc_day() {
+        // Arrange
+        let timestamp = 1_709_251_199; // 2024-02-29T23:59:59Z
+
+        // Act
+        let sut = Date::from_timestamp(timestamp);
+
+        // Assert
+        assert_eq!("2024-02-29", sut.to_string());
+        assert_eq!(timestamp + 1, sut.next().timestamp());
+    }
+
+    #[test]
+    fn adding_months_clamps_to_the_end_of_shorter_months() {
+        // Arrange
+        let sut = Date::parse("2023-01-31").unwrap();
+
+        // Act & Assert
+        assert_eq!(Date::new(2023, 2, 28), Some(sut.add_months(1)));
+        assert_eq!(Date::new(2024, 2, 29), Some(sut.add_months(13)));
+        assert_eq!(Date::new(2023, 12, 31), Some(sut.add_months(11)));
+        assert!(sut.add_months(1).is_month_end());
+    }
+}
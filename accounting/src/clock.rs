@@ -0,0 +1,108 @@
+//! Sources of the timestamps stamped on every [`crate::tx::Envelope`].
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Tells the current time as seconds since the Unix epoch
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> u64;
+}
+
+/// The operating system's wall clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs())
+    }
+}
+
+/// A clock that only moves when told to, for tests and simulations.
+///
+/// Clones share the same time, so a test can keep one to advance the clock
+/// installed in an [`crate::accounts::Accounts`].
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    now: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new(now: u64) -> Self {
+        ManualClock {
+            now: Arc::new(AtomicU64::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+/// The clock installed in a ledger.
+///
+/// It is not part of the ledger's state: any two handles compare equal, so
+/// ledgers that only differ in their clock are still equal.
+#[derive(Clone, Debug)]
+pub(crate) struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub(crate) fn new(clock: impl Clock + 'static) -> Self {
+        SharedClock(Arc::new(clock))
+    }
+
+    pub(crate) fn now(&self) -> u64 {
+        self.0.now()
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock::new(SystemClock)
+    }
+}
+
+impl PartialEq for SharedClock {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for SharedClock {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, ManualClock};
+
+    #[test]
+    fn clones_of_a_manual_clock_share_the_same_time() {
+        // Arrange
+        let clock = ManualClock::new(1_000);
+        let sut = clock.clone();
+
+        // Act
+        clock.advance(60);
+
+        // Assert
+        assert_eq!(1_060, sut.now());
+        sut.set(5);
+        assert_eq!(5, clock.now());
+    }
+}
@@ -0,0 +1,211 @@
+//! A minimal binary encoding used for everything the accounting crate writes to disk.
+//!
+//! Integers are little-endian and strings are prefixed with their length as a `u32`.
+
+use std::io;
+
+/// Types that can be written with an [`Encoder`]
+pub trait Encode {
+    fn encode(&self, encoder: &mut Encoder);
+}
+
+/// Types that can be read back with a [`Decoder`]
+pub trait Decode: Sized {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self>;
+}
+
+/// Accumulates encoded values in memory
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buffer: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder { buffer: Vec::new() }
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u128(&mut self, value: u128) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_str(&mut self, value: &str) {
+        self.put_u32(value.len() as u32);
+        self.buffer.extend_from_slice(value.as_bytes());
+    }
+
+    pub fn put_option_str(&mut self, value: Option<&str>) {
+        match value {
+            Some(value) => {
+                self.put_u8(1);
+                self.put_str(value);
+            }
+            None => self.put_u8(0),
+        }
+    }
+
+    pub fn put_option_u64(&mut self, value: Option<u64>) {
+        match value {
+            Some(value) => {
+                self.put_u8(1);
+                self.put_u64(value);
+            }
+            None => self.put_u8(0),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+/// Reads values back from a byte slice produced by an [`Encoder`]
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Decoder { bytes, position: 0 }
+    }
+
+    /// Returns `true` once every byte has been consumed
+    pub fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| invalid_data("unexpected end of input"))?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    pub fn get_u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn get_u32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(
+            bytes.try_into().expect("slice has 4 bytes"),
+        ))
+    }
+
+    pub fn get_u64(&mut self) -> io::Result<u64> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(
+            bytes.try_into().expect("slice has 8 bytes"),
+        ))
+    }
+
+    pub fn get_u128(&mut self) -> io::Result<u128> {
+        let bytes = self.take(16)?;
+        Ok(u128::from_le_bytes(
+            bytes.try_into().expect("slice has 16 bytes"),
+        ))
+    }
+
+    pub fn get_str(&mut self) -> io::Result<String> {
+        let len = self.get_u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid_data("string is not valid UTF-8"))
+    }
+
+    pub fn get_option_str(&mut self) -> io::Result<Option<String>> {
+        match self.get_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.get_str()?)),
+            _ => Err(invalid_data("invalid option tag")),
+        }
+    }
+
+    pub fn get_option_u64(&mut self) -> io::Result<Option<u64>> {
+        match self.get_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.get_u64()?)),
+            _ => Err(invalid_data("invalid option tag")),
+        }
+    }
+}
+
+/// Builds the error returned for malformed input
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Computes the CRC-32 (IEEE) checksum of `bytes`
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, Decoder, Encoder};
+
+    #[test]
+    fn values_round_trip_through_the_encoder_and_decoder() {
+        // Arrange
+        let mut encoder = Encoder::new();
+        encoder.put_u8(7);
+        encoder.put_u64(u64::MAX);
+        encoder.put_str("client_1");
+        encoder.put_option_str(Some("key"));
+        encoder.put_option_str(None);
+        let bytes = encoder.into_bytes();
+
+        // Act
+        let mut sut = Decoder::new(&bytes);
+
+        // Assert
+        assert_eq!(7, sut.get_u8().unwrap());
+        assert_eq!(u64::MAX, sut.get_u64().unwrap());
+        assert_eq!("client_1", sut.get_str().unwrap());
+        assert_eq!(Some("key".to_string()), sut.get_option_str().unwrap());
+        assert_eq!(None, sut.get_option_str().unwrap());
+        assert!(sut.is_empty());
+    }
+
+    #[test]
+    fn decoding_past_the_end_errors() {
+        // Arrange
+        let mut sut = Decoder::new(&[1, 2, 3]);
+
+        // Act
+        let result = sut.get_u64();
+
+        // Assert
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn crc32_matches_the_reference_check_value() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+}
@@ -4,4 +4,66 @@ pub enum AccountingError {
     AccountNotFound(String),
     AccountUnderFunded(String, u64),
     AccountOverFunded(String, u64),
+    UnknownAsset(String),
+    AssetAlreadyRegistered(String),
+    InvalidAssetPrecision(String, u8),
+    /// The account holds enough, but part of it is reserved by a hold
+    InsufficientAvailableFunds(String, u64),
+    HoldNotFound(u64),
+    /// A request would draw more than the account's credit line: `(account, excess)`
+    CreditLimitExceeded(String, u64),
+    AccountAlreadyExists(String),
+    /// Frozen accounts can receive but not send funds
+    AccountFrozen(String),
+    AccountNotFrozen(String),
+    AccountClosed(String),
+    /// Locked by a chargeback; the account can neither send nor receive funds
+    AccountLocked(String),
+    /// Only accounts without balances can be closed
+    AccountHasBalance(String),
+    /// An idempotency key was reused with different parameters than its first use
+    IdempotencyKeyReused(String),
+    /// A batch row reused the tx id of a different deposit or withdrawal
+    DuplicateTxId(u64),
+    /// No deposit was recorded with this sequence id
+    DepositNotFound(u64),
+    /// The deposit was already disputed; a deposit can only be disputed once
+    DepositAlreadyDisputed(u64),
+    /// The deposit has no open dispute to resolve or charge back
+    DepositNotDisputed(u64),
+    /// No envelope with this sequence id is in the ledger's history
+    TxNotFound(u64),
+    /// Only deposits, withdrawals, transfers and fees can be reversed
+    TxNotReversible(u64),
+    TxAlreadyReversed(u64),
+    /// The funds a reversal would take back are no longer available in the account
+    TxFundsSpent(u64),
+    /// A leg of [`crate::accounts::Accounts::apply_batch`] failed, so none was applied:
+    /// `(leg index, error)`
+    BatchLegFailed(usize, Box<AccountingError>),
+    ScheduleNotFound(u64),
+    ApprovalNotFound(u64),
+    /// A request with the same idempotency key is waiting for approval: `(request id)`
+    ApprovalPending(u64),
+    /// Only an operator other than the one who made a request can approve or reject it
+    MakerCannotApprove(String),
+    /// The amount is above the asset's approval threshold, but the operation cannot wait
+    /// for approval: `(asset, threshold)`
+    ApprovalRequired(String, u64),
+    /// The amount is above the per-transaction limit of the account's velocity tier:
+    /// `(account, tier, limit)`
+    TxLimitExceeded(String, String, u64),
+    /// The amount would take what the account moved out over the last 24 hours above its
+    /// velocity tier's daily limit: `(account, tier, amount still allowed)`
+    DailyLimitExceeded(String, String, u64),
+    /// A replayed envelope's sequence id is not after the last one applied
+    EnvelopeOutOfOrder(u64),
+    /// The history up to this sequence id was left out of the snapshot the ledger was
+    /// decoded from and is not restored yet
+    HistoryUnavailable(u64),
+    /// Total debits and credits of an asset differ: `(asset, debits, credits)`
+    TrialBalanceMismatch(String, u128, u128),
+    /// A customer account's balance in the books differs from its balance in the ledger:
+    /// `(account, asset, books, ledger)`, with drawn credit counted as negative
+    ReconciliationMismatch(String, String, i128, i128),
 }
@@ -0,0 +1,299 @@
+//! Fees charged on withdrawals and transfers.
+//!
+//! A [`FeeSchedule`] holds one [`Fee`] per operation and asset. The fee is charged
+//! together with the operation and recorded as its own [`crate::tx::Tx::Fee`], which
+//! moves the money into [`FEE_ACCOUNT`].
+
+use std::{collections::BTreeMap, io};
+
+use crate::codec::{invalid_data, Decode, Decoder, Encode, Encoder};
+
+/// House account every fee is credited to
+pub const FEE_ACCOUNT: &str = "system:fees";
+
+/// Basis points in a whole, i.e. 100%
+const BASIS_POINTS: u128 = 10_000;
+
+/// The operations fees can be charged on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FeeOn {
+    Withdrawal,
+    Transfer,
+}
+
+/// How a fee is computed from the amount of the operation it is charged on
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FeeRule {
+    /// The same amount regardless of the operation's amount
+    Flat(u64),
+    /// Hundredths of a percent of the amount, rounded down
+    BasisPoints(u32),
+    /// The rule of the first tier covering the amount; tiers are sorted by `up_to`
+    Tiered(Vec<FeeTier>),
+}
+
+/// A bracket of a [`FeeRule::Tiered`] rule
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FeeTier {
+    /// The largest amount, inclusive, this tier applies to; `None` for no upper bound
+    pub up_to: Option<u64>,
+    pub rule: FeeRule,
+}
+
+/// A fee rule bounded by a minimum and an optional maximum
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fee {
+    pub rule: FeeRule,
+    pub min: u64,
+    pub max: Option<u64>,
+}
+
+impl FeeRule {
+    /// The uncapped fee for `amount`; `0` if no tier covers it
+    fn apply(&self, amount: u64) -> u64 {
+        match self {
+            FeeRule::Flat(fee) => *fee,
+            FeeRule::BasisPoints(rate) => {
+                let fee = u128::from(amount) * u128::from(*rate) / BASIS_POINTS;
+                u64::try_from(fee).unwrap_or(u64::MAX)
+            }
+            FeeRule::Tiered(tiers) => tiers
+                .iter()
+                .find(|tier| tier.up_to.is_none_or(|up_to| amount <= up_to))
+                .map_or(0, |tier| tier.rule.apply(amount)),
+        }
+    }
+}
+
+impl Fee {
+    /// The fee charged on an operation moving `amount`
+    pub fn charge(&self, amount: u64) -> u64 {
+        let fee = self.rule.apply(amount).max(self.min);
+        self.max.map_or(fee, |max| fee.min(max))
+    }
+}
+
+/// The fees charged per operation and asset; operations without a fee are free
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeeSchedule {
+    fees: BTreeMap<(FeeOn, String), Fee>,
+}
+
+impl FeeSchedule {
+    /// The fee configured for `on` in `asset`, if any
+    pub fn get(&self, on: FeeOn, asset: &str) -> Option<&Fee> {
+        self.fees.get(&(on, asset.to_string()))
+    }
+
+    /// Iterates over every configured fee
+    pub fn iter(&self) -> impl Iterator<Item = (FeeOn, &str, &Fee)> {
+        self.fees
+            .iter()
+            .map(|((on, asset), fee)| (*on, asset.as_str(), fee))
+    }
+
+    /// The fee charged on an operation moving `amount` of `asset`
+    pub fn charge(&self, on: FeeOn, asset: &str, amount: u64) -> u64 {
+        self.get(on, asset).map_or(0, |fee| fee.charge(amount))
+    }
+
+    /// Replaces the fee for `on` in `asset`; `None` makes the operation free
+    pub(crate) fn set(&mut self, on: FeeOn, asset: &str, fee: Option<Fee>) {
+        let key = (on, asset.to_string());
+        match fee {
+            Some(fee) => self.fees.insert(key, fee),
+            None => self.fees.remove(&key),
+        };
+    }
+}
+
+impl Encode for FeeOn {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_u8(match self {
+            FeeOn::Withdrawal => 0,
+            FeeOn::Transfer => 1,
+        });
+    }
+}
+
+impl Decode for FeeOn {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        match decoder.get_u8()? {
+            0 => Ok(FeeOn::Withdrawal),
+            1 => Ok(FeeOn::Transfer),
+            _ => Err(invalid_data("unknown fee operation")),
+        }
+    }
+}
+
+impl Encode for FeeRule {
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            FeeRule::Flat(fee) => {
+                encoder.put_u8(0);
+                encoder.put_u64(*fee);
+            }
+            FeeRule::BasisPoints(rate) => {
+                encoder.put_u8(1);
+                encoder.put_u32(*rate);
+            }
+            FeeRule::Tiered(tiers) => {
+                encoder.put_u8(2);
+                encoder.put_u64(tiers.len() as u64);
+                for tier in tiers {
+                    encoder.put_option_u64(tier.up_to);
+                    tier.rule.encode(encoder);
+                }
+            }
+        }
+    }
+}
+
+impl Decode for FeeRule {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        match decoder.get_u8()? {
+            0 => Ok(FeeRule::Flat(decoder.get_u64()?)),
+            1 => Ok(FeeRule::BasisPoints(decoder.get_u32()?)),
+            2 => {
+                let mut tiers = vec![];
+                for _ in 0..decoder.get_u64()? {
+                    tiers.push(FeeTier {
+                        up_to: decoder.get_option_u64()?,
+                        rule: FeeRule::decode(decoder)?,
+                    });
+                }
+                Ok(FeeRule::Tiered(tiers))
+            }
+            _ => Err(invalid_data("unknown fee rule")),
+        }
+    }
+}
+
+impl Encode for Fee {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.rule.encode(encoder);
+        encoder.put_u64(self.min);
+        encoder.put_option_u64(self.max);
+    }
+}
+
+impl Decode for Fee {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        Ok(Fee {
+            rule: FeeRule::decode(decoder)?,
+            min: decoder.get_u64()?,
+            max: decoder.get_option_u64()?,
+        })
+    }
+}
+
+impl Encode for FeeSchedule {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_u64(self.fees.len() as u64);
+        for ((on, asset), fee) in &self.fees {
+            on.encode(encoder);
+            encoder.put_str(asset);
+            fee.encode(encoder);
+        }
+    }
+}
+
+impl Decode for FeeSchedule {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        let mut schedule = FeeSchedule::default();
+        for _ in 0..decoder.get_u64()? {
+            let on = FeeOn::decode(decoder)?;
+            let asset = decoder.get_str()?;
+            schedule.set(on, &asset, Some(Fee::decode(decoder)?));
+        }
+        Ok(schedule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{Decode, Decoder, Encode, Encoder};
+
+    use super::{Fee, FeeOn, FeeRule, FeeSchedule, FeeTier};
+
+    fn uncapped(rule: FeeRule) -> Fee {
+        Fee {
+            rule,
+            min: 0,
+            max: None,
+        }
+    }
+
+    #[test]
+    fn basis_points_are_charged_on_the_amount_and_rounded_down() {
+        // Arrange
+        let sut = uncapped(FeeRule::BasisPoints(25));
+
+        // Act & Assert
+        assert_eq!(25, sut.charge(10_000));
+        assert_eq!(2, sut.charge(999));
+        assert_eq!(0, sut.charge(39));
+    }
+
+    #[test]
+    fn tiered_fees_use_the_first_tier_covering_the_amount() {
+        // Arrange
+        let sut = uncapped(FeeRule::Tiered(vec![
+            FeeTier {
+                up_to: Some(1_000),
+                rule: FeeRule::Flat(50),
+            },
+            FeeTier {
+                up_to: None,
+                rule: FeeRule::BasisPoints(100),
+            },
+        ]));
+
+        // Act & Assert
+        assert_eq!(50, sut.charge(1_000));
+        assert_eq!(100, sut.charge(10_000));
+    }
+
+    #[test]
+    fn fees_are_bounded_by_their_minimum_and_maximum() {
+        // Arrange
+        let sut = Fee {
+            rule: FeeRule::BasisPoints(100),
+            min: 10,
+            max: Some(500),
+        };
+
+        // Act & Assert
+        assert_eq!(10, sut.charge(100));
+        assert_eq!(200, sut.charge(20_000));
+        assert_eq!(500, sut.charge(1_000_000));
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        // Arrange
+        let mut schedule = FeeSchedule::default();
+        schedule.set(FeeOn::Withdrawal, "USD", Some(uncapped(FeeRule::Flat(25))));
+        schedule.set(
+            FeeOn::Transfer,
+            "USD",
+            Some(Fee {
+                rule: FeeRule::Tiered(vec![FeeTier {
+                    up_to: Some(100),
+                    rule: FeeRule::BasisPoints(30),
+                }]),
+                min: 1,
+                max: Some(99),
+            }),
+        );
+        let mut encoder = Encoder::new();
+        schedule.encode(&mut encoder);
+        let bytes = encoder.into_bytes();
+
+        // Act
+        let sut = FeeSchedule::decode(&mut Decoder::new(&bytes));
+
+        // Assert
+        assert_eq!(schedule, sut.unwrap());
+    }
+}
@@ -0,0 +1,671 @@
+//! Interest accrued daily on customer balances and posted to [`Accounts`] at month end.
+//!
+//! Positive balances earn interest at their asset's credit rate and drawn credit is
+//! charged at its debit rate. Accruals are kept in billionths of a minor unit so small
+//! balances still add up over a period; only whole minor units are posted, and the
+//! fraction left over carries into the next period.
+//!
+//! Each day accrues on the balances the day closed with, read back from the ledger's
+//! history, so the engine can catch up on days it was not run on.
+//! [`InterestEngine::save`] keeps the policies and the unposted accruals in a file next
+//! to the journal, as `[CRC-32 of payload: u32][payload]`. Postings carry an idempotency
+//! key per account, asset and period, so posting a period again after a crash between
+//! journaling it and saving the engine returns the original postings instead of paying
+//! twice.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    accounts::{AccountOrder, AccountState, Accounts, AsOf},
+    bookkeeping::is_system_account,
+    calendar::Date,
+    codec::{crc32, invalid_data, Decode, Decoder, Encode, Encoder},
+    errors::AccountingError,
+    tx::Envelope,
+};
+
+/// Accruals are kept in units of one minor unit divided by this scale
+pub const ACCRUAL_SCALE: u128 = 1_000_000_000;
+
+/// The file [`InterestEngine::save`] writes in the data directory
+const INTEREST_FILE: &str = "interest.bin";
+
+/// Basis points in a whole, i.e. 100%
+const BASIS_POINTS: u128 = 10_000;
+
+/// How the days between two dates and the length of a year are counted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DayCount {
+    /// Actual days elapsed over a fixed 365-day year
+    Act365,
+    /// Every month counts as 30 days of a 360-day year (US 30/360)
+    Thirty360,
+}
+
+impl DayCount {
+    /// The days counted from `start` to `end`
+    pub fn days(&self, start: Date, end: Date) -> i64 {
+        match self {
+            DayCount::Act365 => end.days() - start.days(),
+            DayCount::Thirty360 => {
+                let start_day = i64::from(start.day.min(30));
+                let end_day = if end.day == 31 && start_day == 30 {
+                    30
+                } else {
+                    i64::from(end.day)
+                };
+                360 * i64::from(end.year - start.year)
+                    + 30 * (i64::from(end.month) - i64::from(start.month))
+                    + (end_day - start_day)
+            }
+        }
+    }
+
+    /// The days in a year
+    pub fn year_days(&self) -> i64 {
+        match self {
+            DayCount::Act365 => 365,
+            DayCount::Thirty360 => 360,
+        }
+    }
+}
+
+/// A bracket of a [`RateTable`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateBand {
+    /// The largest balance, inclusive, this band applies to; `None` for no upper bound
+    pub up_to: Option<u64>,
+    /// Annual rate in basis points
+    pub rate_bps: u32,
+}
+
+/// Annual rates by balance; the whole balance earns the rate of the first band covering it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RateTable {
+    /// Sorted by `up_to`
+    pub bands: Vec<RateBand>,
+}
+
+impl RateTable {
+    /// The same rate for every balance
+    pub fn flat(rate_bps: u32) -> Self {
+        RateTable {
+            bands: vec![RateBand {
+                up_to: None,
+                rate_bps,
+            }],
+        }
+    }
+
+    /// The annual rate in basis points for `balance`; `0` if no band covers it
+    pub fn rate_for(&self, balance: u64) -> u32 {
+        self.bands
+            .iter()
+            .find(|band| band.up_to.is_none_or(|up_to| balance <= up_to))
+            .map_or(0, |band| band.rate_bps)
+    }
+}
+
+/// How interest is computed for one asset
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterestPolicy {
+    /// Paid on positive balances
+    pub credit_rates: RateTable,
+    /// Charged on drawn credit
+    pub debit_rates: RateTable,
+    pub day_count: DayCount,
+}
+
+/// Interest accrued but not posted yet, in units of [`ACCRUAL_SCALE`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Accrual {
+    /// Owed to the customer
+    pub payable: u128,
+    /// Owed by the customer
+    pub receivable: u128,
+}
+
+/// Accrues interest on an [`Accounts`] ledger and posts it as [`crate::tx::Tx`] entries
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterestEngine {
+    /// Keyed by asset code; assets without a policy earn no interest
+    policies: BTreeMap<String, InterestPolicy>,
+    /// Keyed by `(account, asset)`
+    accrued: BTreeMap<(String, String), Accrual>,
+    /// Interest has been accrued for every day before this one
+    accrued_until: Date,
+}
+
+impl InterestEngine {
+    /// An engine that starts accruing on `start`
+    pub fn new(start: Date) -> Self {
+        InterestEngine {
+            policies: BTreeMap::new(),
+            accrued: BTreeMap::new(),
+            accrued_until: start,
+        }
+    }
+
+    /// Replaces the policy for `asset`; interest already accrued is kept
+    pub fn set_policy(&mut self, asset: &str, policy: InterestPolicy) {
+        self.policies.insert(asset.to_string(), policy);
+    }
+
+    /// Interest has been accrued for every day before this one
+    pub fn accrued_until(&self) -> Date {
+        self.accrued_until
+    }
+
+    /// Interest accrued on the `signer`'s balance in `asset` and not posted yet
+    pub fn accrued(&self, signer: &str, asset: &str) -> Accrual {
+        self.accrued
+            .get(&(signer.to_string(), asset.to_string()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Accrues a day of interest for every day from [`InterestEngine::accrued_until`] up
+    /// to, but not including, `until`, each on the balances at the end of that day.
+    ///
+    /// The balances of a day the ledger changed after are rebuilt with
+    /// [`Accounts::as_of`]; a ledger that discarded its history accrues every day on
+    /// its current balances.
+    ///
+    /// # Errors
+    /// - the same errors as [`Accounts::as_of`]
+    pub fn accrue(&mut self, accounts: &Accounts, until: Date) -> Result<(), AccountingError> {
+        while self.accrued_until < until {
+            let day = self.accrued_until;
+            let end_of_day = day.next().timestamp() - 1;
+            let changed_since = accounts
+                .history_since(0)
+                .last()
+                .is_some_and(|envelope| envelope.timestamp > end_of_day);
+            if changed_since {
+                self.accrue_day(&accounts.as_of(AsOf::Timestamp(end_of_day))?, day);
+            } else {
+                self.accrue_day(accounts, day);
+            }
+            self.accrued_until = day.next();
+        }
+        Ok(())
+    }
+
+    /// Accrues the interest of `day` on the balances of `accounts`
+    fn accrue_day(&mut self, accounts: &Accounts, day: Date) {
+        let positions = accounts
+            .list_accounts(AccountOrder::Name)
+            .into_iter()
+            .filter(|summary| !is_system_account(summary.signer))
+            .flat_map(|summary| {
+                let signer = summary.signer;
+                summary
+                    .balances
+                    .into_iter()
+                    .map(move |(asset, balance)| (signer, asset, balance))
+            });
+        for (signer, asset, balance) in positions {
+            let Some(policy) = self.policies.get(asset) else {
+                continue;
+            };
+
+            let days = policy.day_count.days(day, day.next());
+            let interest = |amount: u64, rates: &RateTable| {
+                u128::from(amount)
+                    * u128::from(rates.rate_for(amount))
+                    * days.max(0) as u128
+                    * ACCRUAL_SCALE
+                    / (BASIS_POINTS * policy.day_count.year_days() as u128)
+            };
+            let payable = interest(balance.total, &policy.credit_rates);
+            let receivable = interest(balance.credit_used, &policy.debit_rates);
+            if payable == 0 && receivable == 0 {
+                continue;
+            }
+
+            let accrual = self
+                .accrued
+                .entry((signer.to_string(), asset.to_string()))
+                .or_default();
+            accrual.payable += payable;
+            accrual.receivable += receivable;
+        }
+    }
+
+    /// Posts the whole minor units accrued so far and returns the recorded envelopes;
+    /// the fractions stay accrued, and so does interest owed to locked or closed accounts.
+    ///
+    /// Each posting is keyed by its account, asset and [`InterestEngine::accrued_until`],
+    /// so posting the same accruals again returns the original envelopes.
+    ///
+    /// # Errors
+    /// - the first error returned by [`Accounts::pay_interest_idempotent`] or
+    ///   [`Accounts::charge_interest_idempotent`]; accruals posted before it are not
+    ///   rolled back
+    pub fn post(&mut self, accounts: &mut Accounts) -> Result<Vec<Envelope>, AccountingError> {
+        let until = self.accrued_until;
+        let mut envelopes = vec![];
+        for ((signer, asset), accrual) in &mut self.accrued {
+            let paid = whole_units(accrual.payable);
+            let can_receive = !matches!(
+                accounts.account_state(signer),
+                Some(AccountState::Locked | AccountState::Closed)
+            );
+            if paid > 0 && can_receive {
+                let key = posting_key("paid", signer, asset, until);
+                envelopes.push(accounts.pay_interest_idempotent(&key, signer, asset, paid)?);
+                accrual.payable -= u128::from(paid) * ACCRUAL_SCALE;
+            }
+            let charged = whole_units(accrual.receivable);
+            if charged > 0 {
+                let key = posting_key("charged", signer, asset, until);
+                envelopes.push(accounts.charge_interest_idempotent(&key, signer, asset, charged)?);
+                accrual.receivable -= u128::from(charged) * ACCRUAL_SCALE;
+            }
+        }
+        self.accrued
+            .retain(|_, accrual| *accrual != Accrual::default());
+        Ok(envelopes)
+    }
+
+    /// Catches up to the day of the ledger's clock, posting at the end of every month
+    /// it crosses, and returns the recorded envelopes.
+    ///
+    /// # Errors
+    /// - the first error returned by [`InterestEngine::accrue`] or [`InterestEngine::post`]
+    pub fn run(&mut self, accounts: &mut Accounts) -> Result<Vec<Envelope>, AccountingError> {
+        let today = Date::from_timestamp(accounts.now());
+        let mut envelopes = vec![];
+        while self.accrued_until < today {
+            let start = self.accrued_until;
+            let next_month = Date {
+                day: 1,
+                ..start.add_months(1)
+            };
+            let until = next_month.min(today);
+            self.accrue(accounts, until)?;
+            if until == next_month {
+                envelopes.extend(self.post(accounts)?);
+            }
+        }
+        Ok(envelopes)
+    }
+
+    /// Writes the policies and accruals to their file in `dir`, replacing the previous one.
+    ///
+    /// The file is written under a temporary name and renamed once it is synced, so a
+    /// crash leaves either the old or the new accruals behind.
+    ///
+    /// # Errors
+    /// - the file cannot be written, synced or renamed
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let mut encoder = Encoder::new();
+        self.encode(&mut encoder);
+        let payload = encoder.into_bytes();
+
+        let path = dir.join(INTEREST_FILE);
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&crc32(&payload).to_le_bytes())?;
+        file.write_all(&payload)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        File::open(dir)?.sync_all()
+    }
+
+    /// Reads the engine saved in `dir`; an engine that starts accruing on `start` if none
+    /// was saved.
+    ///
+    /// # Errors
+    /// - the file cannot be read
+    /// - the checksum does not match or the contents cannot be decoded
+    pub fn load(dir: &Path, start: Date) -> io::Result<InterestEngine> {
+        let bytes = match fs::read(dir.join(INTEREST_FILE)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(InterestEngine::new(start)),
+            Err(e) => return Err(e),
+        };
+        if bytes.len() < 4 {
+            return Err(invalid_data("interest file is too short"));
+        }
+
+        let (checksum, payload) = bytes.split_at(4);
+        if crc32(payload) != u32::from_le_bytes(checksum.try_into().expect("slice has 4 bytes")) {
+            return Err(invalid_data("interest checksum mismatch"));
+        }
+        let mut decoder = Decoder::new(payload);
+        let engine = InterestEngine::decode(&mut decoder)?;
+        if !decoder.is_empty() {
+            return Err(invalid_data("trailing bytes in interest file"));
+        }
+        Ok(engine)
+    }
+}
+
+impl Encode for InterestEngine {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_u64(self.accrued_until.timestamp());
+        encoder.put_u32(self.policies.len() as u32);
+        for (asset, policy) in &self.policies {
+            encoder.put_str(asset);
+            encode_rates(encoder, &policy.credit_rates);
+            encode_rates(encoder, &policy.debit_rates);
+            encoder.put_u8(match policy.day_count {
+                DayCount::Act365 => 0,
+                DayCount::Thirty360 => 1,
+            });
+        }
+        encoder.put_u32(self.accrued.len() as u32);
+        for ((signer, asset), accrual) in &self.accrued {
+            encoder.put_str(signer);
+            encoder.put_str(asset);
+            encoder.put_u128(accrual.payable);
+            encoder.put_u128(accrual.receivable);
+        }
+    }
+}
+
+impl Decode for InterestEngine {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        let mut engine = InterestEngine::new(Date::from_timestamp(decoder.get_u64()?));
+        for _ in 0..decoder.get_u32()? {
+            let asset = decoder.get_str()?;
+            let policy = InterestPolicy {
+                credit_rates: decode_rates(decoder)?,
+                debit_rates: decode_rates(decoder)?,
+                day_count: match decoder.get_u8()? {
+                    0 => DayCount::Act365,
+                    1 => DayCount::Thirty360,
+                    _ => return Err(invalid_data("unknown day count")),
+                },
+            };
+            engine.policies.insert(asset, policy);
+        }
+        for _ in 0..decoder.get_u32()? {
+            let key = (decoder.get_str()?, decoder.get_str()?);
+            let accrual = Accrual {
+                payable: decoder.get_u128()?,
+                receivable: decoder.get_u128()?,
+            };
+            engine.accrued.insert(key, accrual);
+        }
+        Ok(engine)
+    }
+}
+
+fn encode_rates(encoder: &mut Encoder, rates: &RateTable) {
+    encoder.put_u32(rates.bands.len() as u32);
+    for band in &rates.bands {
+        encoder.put_option_u64(band.up_to);
+        encoder.put_u32(band.rate_bps);
+    }
+}
+
+fn decode_rates(decoder: &mut Decoder) -> io::Result<RateTable> {
+    let mut bands = vec![];
+    for _ in 0..decoder.get_u32()? {
+        bands.push(RateBand {
+            up_to: decoder.get_option_u64()?,
+            rate_bps: decoder.get_u32()?,
+        });
+    }
+    Ok(RateTable { bands })
+}
+
+/// The idempotency key of the interest `kind` ("paid" or "charged") posted to the
+/// `signer`'s balance in `asset` for the days before `until`
+fn posting_key(kind: &str, signer: &str, asset: &str, until: Date) -> String {
+    format!("interest-{kind}-{signer}-{asset}-{until}")
+}
+
+/// The whole minor units in an accrual; saturates beyond `u64`
+fn whole_units(accrued: u128) -> u64 {
+    u64::try_from(accrued / ACCRUAL_SCALE).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        accounts::Accounts,
+        clock::ManualClock,
+        errors::AccountingError,
+        test_utils::{accounts_at, date, temp_dir},
+        tx::Tx,
+    };
+
+    use super::{DayCount, InterestEngine, InterestPolicy, RateBand, RateTable, ACCRUAL_SCALE};
+
+    #[test]
+    fn thirty_360_counts_every_month_as_thirty_days() {
+        // Arrange
+        let sut = DayCount::Thirty360;
+
+        // Act & Assert
+        assert_eq!(30, sut.days(date("2023-01-01"), date("2023-02-01")));
+        assert_eq!(30, sut.days(date("2023-02-01"), date("2023-03-01")));
+        assert_eq!(3, sut.days(date("2023-02-28"), date("2023-03-01")));
+        assert_eq!(0, sut.days(date("2023-01-30"), date("2023-01-31")));
+        assert_eq!(
+            29,
+            DayCount::Act365.days(date("2024-02-01"), date("2024-03-01"))
+        );
+    }
+
+    #[test]
+    fn interest_accrues_daily_and_is_posted_at_month_end() {
+        // Arrange
+        let clock = ManualClock::new(date("2024-01-15").timestamp());
+        let mut accounts = Accounts::with_double_entry();
+        accounts.set_clock(clock.clone());
+        accounts.register_asset("USD", 2).unwrap();
+        accounts.deposit("client_1", "USD", 100_000).unwrap();
+        let mut sut = InterestEngine::new(date("2024-01-01"));
+        sut.set_policy(
+            "USD",
+            InterestPolicy {
+                credit_rates: RateTable::flat(365),
+                debit_rates: RateTable::default(),
+                day_count: DayCount::Act365,
+            },
+        );
+
+        // Act
+        let mid_month = sut.run(&mut accounts).unwrap();
+        clock.set(date("2024-02-03").timestamp());
+        let posted = sut.run(&mut accounts).unwrap();
+
+        // Assert
+        assert!(mid_month.is_empty());
+        assert_eq!(1, posted.len());
+        assert_eq!(
+            Tx::InterestPaid {
+                account: "client_1".to_string(),
+                asset: "USD".to_string(),
+                amount: 170,
+                idempotency_key: Some("interest-paid-client_1-USD-2024-02-01".to_string()),
+            },
+            posted[0].tx
+        );
+        assert_eq!(100_170, accounts.total_balance("client_1", "USD"));
+        // February 1st and 2nd closed before the interest was posted on the 3rd and wait
+        // for the next period
+        assert_eq!(20_000_000_000, sut.accrued("client_1", "USD").payable);
+        assert_eq!(Ok(()), accounts.trial_balance().unwrap().assert_balanced());
+    }
+
+    #[test]
+    fn a_period_posted_again_after_a_crash_is_not_paid_twice() {
+        // Arrange
+        let clock = ManualClock::new(date("2024-01-15").timestamp());
+        let mut accounts = accounts_at(&clock);
+        accounts.deposit("client_1", "USD", 100_000).unwrap();
+        let mut sut = InterestEngine::new(date("2024-01-01"));
+        sut.set_policy(
+            "USD",
+            InterestPolicy {
+                credit_rates: RateTable::flat(365),
+                debit_rates: RateTable::default(),
+                day_count: DayCount::Act365,
+            },
+        );
+        clock.set(date("2024-02-03").timestamp());
+        // What a crash after journaling the postings, before saving the engine, leaves
+        let saved = sut.clone();
+        let posted = sut.run(&mut accounts).unwrap();
+        let last_seq = accounts.last_seq();
+
+        // Act
+        let mut restarted = saved;
+        let posted_again = restarted.run(&mut accounts).unwrap();
+
+        // Assert
+        assert_eq!(1, posted.len());
+        assert_eq!(posted, posted_again);
+        assert_eq!(last_seq, accounts.last_seq());
+        assert_eq!(100_170, accounts.total_balance("client_1", "USD"));
+        assert_eq!(sut, restarted);
+    }
+
+    #[test]
+    fn interest_owed_to_a_locked_account_stays_accrued() {
+        // Arrange
+        let mut accounts = accounts_at(&ManualClock::new(date("2024-01-01").timestamp()));
+        accounts.deposit("client_1", "USD", 100_000).unwrap();
+        let disputed = accounts.deposit("client_1", "USD", 500).unwrap();
+        accounts.dispute(disputed.seq).unwrap();
+        accounts.chargeback(disputed.seq).unwrap();
+        let mut sut = InterestEngine::new(date("2024-01-01"));
+        sut.set_policy(
+            "USD",
+            InterestPolicy {
+                credit_rates: RateTable::flat(365),
+                debit_rates: RateTable::default(),
+                day_count: DayCount::Act365,
+            },
+        );
+        sut.accrue(&accounts, date("2024-02-01")).unwrap();
+
+        // Act
+        let posted = sut.post(&mut accounts).unwrap();
+
+        // Assert
+        assert!(posted.is_empty());
+        assert_eq!(
+            31 * 10 * ACCRUAL_SCALE,
+            sut.accrued("client_1", "USD").payable
+        );
+        assert_eq!(100_000, accounts.total_balance("client_1", "USD"));
+        assert_eq!(
+            Err(AccountingError::AccountLocked("client_1".to_string())),
+            accounts.pay_interest("client_1", "USD", 310)
+        );
+    }
+
+    #[test]
+    fn drawn_credit_is_charged_and_fractions_carry_over() {
+        // Arrange
+        let mut accounts = accounts_at(&ManualClock::new(date("2023-01-01").timestamp()));
+        accounts.open("business_1").unwrap();
+        accounts
+            .set_credit_limit("business_1", "USD", 5_000)
+            .unwrap();
+        accounts.withdraw("business_1", "USD", 1_000).unwrap();
+        let mut sut = InterestEngine::new(date("2023-01-01"));
+        sut.set_policy(
+            "USD",
+            InterestPolicy {
+                credit_rates: RateTable::default(),
+                debit_rates: RateTable::flat(1_000),
+                day_count: DayCount::Thirty360,
+            },
+        );
+
+        // Act
+        sut.accrue(&accounts, date("2023-02-01")).unwrap();
+        let posted = sut.post(&mut accounts).unwrap();
+
+        // Assert
+        assert!(matches!(
+            posted[0].tx,
+            Tx::InterestCharged { amount: 8, .. }
+        ));
+        assert_eq!(1_008, accounts.credit_used("business_1", "USD"));
+        assert_eq!(333_333_310, sut.accrued("business_1", "USD").receivable);
+    }
+
+    #[test]
+    fn days_missed_accrue_on_the_balances_they_closed_with() {
+        // Arrange
+        let clock = ManualClock::new(date("2024-03-01").timestamp());
+        let mut accounts = accounts_at(&clock);
+        accounts.deposit("client_1", "USD", 100_000).unwrap();
+        clock.set(date("2024-03-03").timestamp());
+        accounts.deposit("client_1", "USD", 100_000).unwrap();
+        clock.set(date("2024-03-04").timestamp());
+        let mut sut = InterestEngine::new(date("2024-03-01"));
+        sut.set_policy(
+            "USD",
+            InterestPolicy {
+                credit_rates: RateTable::flat(365),
+                debit_rates: RateTable::default(),
+                day_count: DayCount::Act365,
+            },
+        );
+
+        // Act
+        sut.run(&mut accounts).unwrap();
+
+        // Assert
+        // The 1st and 2nd closed at 1,000.00 and the 3rd at 2,000.00
+        assert_eq!(40 * ACCRUAL_SCALE, sut.accrued("client_1", "USD").payable);
+    }
+
+    #[test]
+    fn engines_round_trip_through_disk() {
+        // Arrange
+        let dir = temp_dir("round-trip");
+        let mut accounts = Accounts::new();
+        accounts.register_asset("USD", 2).unwrap();
+        accounts.deposit("client_1", "USD", 12_345).unwrap();
+        let mut engine = InterestEngine::new(date("2024-01-01"));
+        engine.set_policy(
+            "USD",
+            InterestPolicy {
+                credit_rates: RateTable {
+                    bands: vec![
+                        RateBand {
+                            up_to: Some(10_000),
+                            rate_bps: 100,
+                        },
+                        RateBand {
+                            up_to: None,
+                            rate_bps: 250,
+                        },
+                    ],
+                },
+                debit_rates: RateTable::flat(1_500),
+                day_count: DayCount::Thirty360,
+            },
+        );
+        engine.accrue(&accounts, date("2024-01-10")).unwrap();
+        engine.save(&dir).unwrap();
+
+        // Act
+        let sut = InterestEngine::load(&dir, date("2024-06-01"));
+
+        // Assert
+        assert_eq!(engine, sut.unwrap());
+        assert_eq!(
+            InterestEngine::new(date("2024-06-01")),
+            InterestEngine::load(&dir.join("empty"), date("2024-06-01")).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,715 @@
+//! An append-only, on-disk log of [`Envelope`] records.
+//!
+//! The log is split into segments named after the sequence number of their first record.
+//! Every record is framed as `[payload length: u32][CRC-32 of payload: u32][payload]`
+//! and flushed to disk before [`Journal::append`] returns. A record that was only
+//! partially written when the process crashed is detected on [`Journal::open`] and
+//! cut off; corruption anywhere else is reported as an error.
+//!
+//! [`Journal::snapshot`] writes a [`Snapshot`] of the ledger, starts a new segment and
+//! deletes the snapshots that are no longer needed for recovery. Segments they no longer
+//! need are moved to the `archive` directory, which [`Journal::open`] does not read: a
+//! snapshot leaves the ledger's history out, and [`Journal::history`] reads it back from
+//! there for the queries that need it.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    accounts::Accounts,
+    codec::{crc32, invalid_data, Decode, Decoder, Encode, Encoder},
+    snapshot::{self, Snapshot},
+    tx::Envelope,
+};
+
+/// The single journal file written before the log was split into segments
+const LEGACY_JOURNAL_FILE: &str = "journal.log";
+const SEGMENT_PREFIX: &str = "journal-";
+const SEGMENT_EXTENSION: &str = ".log";
+/// Where segments that recovery no longer needs are kept, within the data directory
+const ARCHIVE_DIR: &str = "archive";
+const HEADER_LEN: usize = 8;
+/// Older snapshots are kept as a fallback in case the newest one is corrupted
+const SNAPSHOTS_TO_KEEP: usize = 2;
+
+/// A write-ahead log of transactions stored in a data directory
+#[derive(Debug)]
+pub struct Journal {
+    dir: PathBuf,
+    /// The segment new records are appended to
+    path: PathBuf,
+    file: File,
+    /// Sequence number of the last record in the log
+    last_seq: u64,
+    /// Sequence number covered by the newest snapshot
+    snapshot_seq: u64,
+}
+
+/// What [`Journal::open`] found on disk
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Recovered {
+    /// The newest snapshot that passed its checksum
+    pub snapshot: Option<Snapshot>,
+    /// Every intact envelope after the snapshot, in the order it was appended
+    pub envelopes: Vec<Envelope>,
+    /// Number of bytes of a torn final record that were discarded
+    pub discarded_bytes: u64,
+    /// Number of newer snapshots that were ignored because they were corrupted
+    pub skipped_snapshots: usize,
+}
+
+impl Recovered {
+    /// The ledger state the recovered `envelopes` should be replayed onto
+    pub fn base(&self) -> Accounts {
+        self.snapshot
+            .as_ref()
+            .map(|snapshot| snapshot.accounts.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl Journal {
+    /// Opens the journal stored in `dir`, creating the directory and first segment if needed.
+    ///
+    /// # Errors
+    /// - the directory or its files cannot be accessed
+    /// - a record other than the very last one is corrupted or missing
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<(Journal, Recovered)> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let legacy_path = dir.join(LEGACY_JOURNAL_FILE);
+        if legacy_path.exists() && list_segments(&dir)?.is_empty() {
+            fs::rename(&legacy_path, dir.join(segment_file_name(1)))?;
+        }
+
+        let mut recovered = Recovered::default();
+        for (_, path) in snapshot::list(&dir)? {
+            match Snapshot::read(&path) {
+                Ok(snapshot) => {
+                    recovered.snapshot = Some(snapshot);
+                    break;
+                }
+                Err(_) => recovered.skipped_snapshots += 1,
+            }
+        }
+        let snapshot_seq = recovered.snapshot.as_ref().map_or(0, |s| s.seq);
+
+        let segments = list_segments(&dir)?;
+        if let Some((first_seq, _)) = segments.first() {
+            if *first_seq > snapshot_seq + 1 {
+                return Err(invalid_data(
+                    "journal is missing records after the snapshot",
+                ));
+            }
+        }
+
+        let mut last_seq = snapshot_seq;
+        for (i, (first_seq, path)) in segments.iter().enumerate() {
+            if i > 0 && *first_seq != last_seq + 1 {
+                return Err(invalid_data("journal segments are not contiguous"));
+            }
+
+            let bytes = fs::read(path)?;
+            let (envelopes, valid_len) = read_records(&bytes)?;
+            if valid_len < bytes.len() {
+                if i + 1 < segments.len() {
+                    return Err(invalid_data("torn record in a sealed journal segment"));
+                }
+                let file = OpenOptions::new().write(true).open(path)?;
+                file.set_len(valid_len as u64)?;
+                file.sync_all()?;
+                recovered.discarded_bytes = (bytes.len() - valid_len) as u64;
+            }
+
+            for (seq, envelope) in (*first_seq..).zip(envelopes) {
+                if envelope.seq != seq {
+                    return Err(invalid_data("journal record out of sequence"));
+                }
+                if seq > snapshot_seq {
+                    recovered.envelopes.push(envelope);
+                }
+                last_seq = seq;
+            }
+        }
+        if last_seq < snapshot_seq {
+            return Err(invalid_data("journal ends before the snapshot"));
+        }
+
+        let path = match segments.last() {
+            Some((_, path)) => path.clone(),
+            None => dir.join(segment_file_name(snapshot_seq + 1)),
+        };
+        let file = open_segment(&path)?;
+
+        Ok((
+            Journal {
+                dir,
+                path,
+                file,
+                last_seq,
+                snapshot_seq,
+            },
+            recovered,
+        ))
+    }
+
+    /// Appends `envelope`, which must directly follow the last record, and waits until it
+    /// is durably stored.
+    ///
+    /// # Errors
+    /// - `envelope` is not the next record of the log
+    /// - the record cannot be written or synced
+    pub fn append(&mut self, envelope: &Envelope) -> io::Result<()> {
+        if envelope.seq != self.last_seq + 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "record {} does not follow record {}",
+                    envelope.seq, self.last_seq
+                ),
+            ));
+        }
+
+        let mut encoder = Encoder::new();
+        envelope.encode(&mut encoder);
+        let payload = encoder.into_bytes();
+
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+        self.last_seq += 1;
+        Ok(())
+    }
+
+    /// Snapshots `accounts`, which must reflect every appended record, and compacts the log.
+    ///
+    /// # Errors
+    /// - the snapshot cannot be written
+    /// - a new segment cannot be started or an old file cannot be removed
+    pub fn snapshot(&mut self, accounts: &Accounts) -> io::Result<()> {
+        Snapshot {
+            seq: self.last_seq,
+            accounts: accounts.clone(),
+        }
+        .write(&self.dir)?;
+        self.snapshot_seq = self.last_seq;
+
+        if self.file.metadata()?.len() > 0 {
+            self.path = self.dir.join(segment_file_name(self.last_seq + 1));
+            self.file = open_segment(&self.path)?;
+            File::open(&self.dir)?.sync_all()?;
+        }
+
+        self.compact()
+    }
+
+    /// Removes snapshots beyond [`SNAPSHOTS_TO_KEEP`] and archives every sealed segment
+    /// whose records are all covered by the oldest snapshot that is kept.
+    fn compact(&mut self) -> io::Result<()> {
+        let snapshots = snapshot::list(&self.dir)?;
+        for (_, path) in snapshots.iter().skip(SNAPSHOTS_TO_KEEP) {
+            fs::remove_file(path)?;
+        }
+        let Some((oldest_kept_seq, _)) = snapshots.iter().take(SNAPSHOTS_TO_KEEP).next_back()
+        else {
+            return Ok(());
+        };
+
+        let archive = self.dir.join(ARCHIVE_DIR);
+        let segments = list_segments(&self.dir)?;
+        let mut archived = false;
+        for window in segments.windows(2) {
+            let (first_seq, path) = &window[0];
+            let (next_first_seq, _) = &window[1];
+            if next_first_seq - 1 <= *oldest_kept_seq {
+                fs::create_dir_all(&archive)?;
+                fs::rename(path, archive.join(segment_file_name(*first_seq)))?;
+                archived = true;
+            }
+        }
+        if archived {
+            File::open(&archive)?.sync_all()?;
+            File::open(&self.dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// The records up to and including sequence number `until`, oldest first, read from
+    /// the archive and the segments still in the log; e.g. the history a snapshot leaves
+    /// out, for [`Accounts::restore_history`].
+    ///
+    /// # Errors
+    /// - a segment cannot be read or holds a corrupted record
+    pub fn history(&self, until: u64) -> io::Result<Vec<Envelope>> {
+        let archive = self.dir.join(ARCHIVE_DIR);
+        let mut segments = list_segments(&self.dir)?;
+        if archive.exists() {
+            segments.extend(list_segments(&archive)?);
+            segments.sort();
+        }
+
+        let mut history = vec![];
+        for (first_seq, path) in segments {
+            if first_seq > until {
+                break;
+            }
+            let bytes = fs::read(path)?;
+            let (envelopes, valid_len) = read_records(&bytes)?;
+            if valid_len < bytes.len() {
+                return Err(invalid_data("torn record in a sealed journal segment"));
+            }
+            history.extend(
+                envelopes
+                    .into_iter()
+                    .filter(|envelope| envelope.seq <= until),
+            );
+        }
+        Ok(history)
+    }
+
+    /// Sequence number of the last record in the log; `0` when it is empty
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Number of records appended since the newest snapshot
+    pub fn records_since_snapshot(&self) -> u64 {
+        self.last_seq - self.snapshot_seq
+    }
+
+    /// The location of the segment new records are appended to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn open_segment(path: &Path) -> io::Result<File> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
+
+fn segment_file_name(first_seq: u64) -> String {
+    format!("{SEGMENT_PREFIX}{first_seq:020}{SEGMENT_EXTENSION}")
+}
+
+/// Lists the segments in `dir` as `(first_seq, path)` pairs, oldest first.
+fn list_segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let first_seq = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(SEGMENT_PREFIX))
+            .and_then(|name| name.strip_suffix(SEGMENT_EXTENSION))
+            .and_then(|seq| seq.parse::<u64>().ok());
+        if let Some(first_seq) = first_seq {
+            segments.push((first_seq, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Decodes every complete record in `bytes`.
+///
+/// Returns the envelopes and the length of the prefix that holds them; anything
+/// after that prefix is a torn final record.
+///
+/// # Errors
+/// - a record other than the last one is corrupted, including a length field pointing
+///   past the end of `bytes` when a whole record follows its header
+fn read_records(bytes: &[u8]) -> io::Result<(Vec<Envelope>, usize)> {
+    let mut envelopes = vec![];
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let remaining = &bytes[position..];
+        if remaining.len() < HEADER_LEN {
+            break;
+        }
+
+        let len =
+            u32::from_le_bytes(remaining[0..4].try_into().expect("slice has 4 bytes")) as usize;
+        let checksum = u32::from_le_bytes(remaining[4..8].try_into().expect("slice has 4 bytes"));
+        let Some(payload) = remaining.get(HEADER_LEN..HEADER_LEN + len) else {
+            // A crash mid-write leaves a strict prefix of the payload, which never decodes
+            // to a whole envelope; if the rest does, the length field is what is damaged
+            if Envelope::decode(&mut Decoder::new(&remaining[HEADER_LEN..])).is_ok() {
+                return Err(invalid_data(
+                    "journal record length points past its segment",
+                ));
+            }
+            break;
+        };
+
+        let is_last = HEADER_LEN + len == remaining.len();
+        if crc32(payload) != checksum {
+            // A bad checksum on the final record is what a crash mid-write looks like
+            if is_last {
+                break;
+            }
+            return Err(invalid_data("journal record checksum mismatch"));
+        }
+
+        let mut decoder = Decoder::new(payload);
+        let envelope = Envelope::decode(&mut decoder)?;
+        if !decoder.is_empty() {
+            return Err(invalid_data("trailing bytes in journal record"));
+        }
+
+        envelopes.push(envelope);
+        position += HEADER_LEN + len;
+    }
+
+    Ok((envelopes, position))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{self, Write},
+    };
+
+    use crate::{
+        accounts::Accounts,
+        snapshot,
+        test_utils::temp_dir,
+        tx::{Details, Envelope, Tx},
+    };
+
+    use super::{list_segments, Journal, HEADER_LEN};
+
+    fn envelope(seq: u64, tx: Tx) -> Envelope {
+        Envelope {
+            seq,
+            timestamp: 1_700_000_000 + seq,
+            details: Details::default(),
+            tx,
+        }
+    }
+
+    fn sample_envelopes() -> Vec<Envelope> {
+        let txs = vec![
+            Tx::RegisterAsset {
+                asset: "USD".to_string(),
+                decimals: 2,
+            },
+            Tx::Deposit {
+                account: "client_1".to_string(),
+                asset: "USD".to_string(),
+                amount: 100,
+                idempotency_key: None,
+            },
+            Tx::Transfer {
+                id: 1,
+                from: "client_1".to_string(),
+                to: "client_2".to_string(),
+                asset: "USD".to_string(),
+                amount: 40,
+                idempotency_key: None,
+            },
+            Tx::Withdraw {
+                account: "client_2".to_string(),
+                asset: "USD".to_string(),
+                amount: 10,
+                idempotency_key: None,
+            },
+        ];
+        (1..).zip(txs).map(|(seq, tx)| envelope(seq, tx)).collect()
+    }
+
+    /// Appends every envelope to both the journal and the ledger
+    fn commit_all(journal: &mut Journal, accounts: &mut Accounts, envelopes: &[Envelope]) {
+        for envelope in envelopes {
+            accounts.apply(envelope).unwrap();
+            journal.append(envelope).unwrap();
+        }
+    }
+
+    #[test]
+    fn appended_transactions_are_recovered_on_reopen() {
+        // Arrange
+        let dir = temp_dir("round-trip");
+        let (mut journal, recovered) = Journal::open(&dir).unwrap();
+        assert!(recovered.envelopes.is_empty());
+        for envelope in sample_envelopes() {
+            journal.append(&envelope).unwrap();
+        }
+        drop(journal);
+
+        // Act
+        let (journal, sut) = Journal::open(&dir).unwrap();
+
+        // Assert
+        assert_eq!(sample_envelopes(), sut.envelopes);
+        assert_eq!(None, sut.snapshot);
+        assert_eq!(0, sut.discarded_bytes);
+        assert_eq!(4, journal.last_seq());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn approved_withdrawals_with_idempotency_keys_are_debited_again_on_reopen() {
+        // Arrange
+        let dir = temp_dir("approved-withdrawal");
+        let (mut journal, _) = Journal::open(&dir).unwrap();
+        let mut accounts = Accounts::new();
+        accounts.register_asset("USD", 2).unwrap();
+        accounts.set_approval_threshold("USD", Some(100)).unwrap();
+        accounts.deposit("client_1", "USD", 1_000).unwrap();
+        let request = accounts
+            .withdraw_idempotent("cash-1", "client_1", "USD", 500)
+            .unwrap();
+        let Tx::ApprovalRequested { id, .. } = request.tx else {
+            panic!("expected a request for approval, got {:?}", request.tx);
+        };
+        accounts.approve(id, "checker").unwrap();
+        for envelope in accounts.history_since(0) {
+            journal.append(envelope).unwrap();
+        }
+        drop(journal);
+
+        // Act
+        let (_, recovered) = Journal::open(&dir).unwrap();
+        let sut = Accounts::replay_from(recovered.base(), recovered.envelopes.clone()).unwrap();
+
+        // Assert
+        assert_eq!(500, accounts.total_balance("client_1", "USD"));
+        assert_eq!(500, sut.total_balance("client_1", "USD"));
+        assert_eq!(Ok(true), accounts.verify_against(recovered.envelopes));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_torn_final_record_is_discarded_and_truncated() {
+        // Arrange
+        let dir = temp_dir("torn");
+        let (mut journal, _) = Journal::open(&dir).unwrap();
+        for envelope in sample_envelopes() {
+            journal.append(&envelope).unwrap();
+        }
+        let segment = journal.path().to_path_buf();
+        let intact_len = fs::metadata(&segment).unwrap().len();
+        drop(journal);
+
+        // Simulate a crash halfway through writing another record
+        let mut file = fs::OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(&[20, 0, 0, 0, 1, 2, 3, 4, 0]).unwrap();
+        drop(file);
+
+        // Act
+        let (mut journal, sut) = Journal::open(&dir).unwrap();
+
+        // Assert
+        assert_eq!(sample_envelopes(), sut.envelopes);
+        assert_eq!(9, sut.discarded_bytes);
+        assert_eq!(intact_len, fs::metadata(&segment).unwrap().len());
+
+        // New records are appended right after the last intact one
+        let deposit = envelope(
+            5,
+            Tx::Deposit {
+                account: "client_3".to_string(),
+                asset: "USD".to_string(),
+                amount: 1,
+                idempotency_key: None,
+            },
+        );
+        journal.append(&deposit).unwrap();
+        drop(journal);
+        let (_, recovered) = Journal::open(&dir).unwrap();
+        assert_eq!(Some(&deposit), recovered.envelopes.last());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn errors_when_a_record_before_the_last_one_is_corrupted() {
+        // Arrange
+        let dir = temp_dir("corrupted");
+        let (mut journal, _) = Journal::open(&dir).unwrap();
+        for envelope in sample_envelopes() {
+            journal.append(&envelope).unwrap();
+        }
+        let segment = journal.path().to_path_buf();
+        drop(journal);
+
+        let mut bytes = fs::read(&segment).unwrap();
+        bytes[10] ^= 0xFF;
+        fs::write(&segment, bytes).unwrap();
+
+        // Act
+        let sut = Journal::open(&dir);
+
+        // Assert
+        assert!(sut.is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn errors_without_truncating_when_the_length_of_an_earlier_record_is_corrupted() {
+        // Arrange
+        let dir = temp_dir("corrupted-length");
+        let (mut journal, _) = Journal::open(&dir).unwrap();
+        for envelope in sample_envelopes() {
+            journal.append(&envelope).unwrap();
+        }
+        let segment = journal.path().to_path_buf();
+        drop(journal);
+
+        let mut bytes = fs::read(&segment).unwrap();
+        let first_len = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+        let second = HEADER_LEN + first_len;
+        bytes[second..second + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&segment, &bytes).unwrap();
+
+        // Act
+        let sut = Journal::open(&dir);
+
+        // Assert
+        assert_eq!(io::ErrorKind::InvalidData, sut.unwrap_err().kind());
+        assert_eq!(bytes, fs::read(&segment).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn records_out_of_sequence_are_rejected_on_append_and_on_open() {
+        // Arrange
+        let dir = temp_dir("out-of-sequence");
+        let (mut journal, _) = Journal::open(&dir).unwrap();
+        let envelopes = sample_envelopes();
+
+        // Act
+        let skipped = journal.append(&envelopes[1]);
+
+        // Assert
+        assert_eq!(io::ErrorKind::InvalidInput, skipped.unwrap_err().kind());
+        assert_eq!(0, journal.last_seq());
+
+        // A segment missing a record in the middle is not replayed as if it were complete
+        for envelope in &envelopes {
+            journal.append(envelope).unwrap();
+        }
+        let segment = journal.path().to_path_buf();
+        drop(journal);
+        let mut bytes = fs::read(&segment).unwrap();
+        let first_len = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+        let second = HEADER_LEN + first_len;
+        let second_len = u32::from_le_bytes(bytes[second..second + 4].try_into().unwrap()) as usize;
+        bytes.drain(second..second + HEADER_LEN + second_len);
+        fs::write(&segment, &bytes).unwrap();
+
+        let sut = Journal::open(&dir);
+
+        assert_eq!(io::ErrorKind::InvalidData, sut.unwrap_err().kind());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn startup_loads_the_snapshot_and_replays_only_the_tail() {
+        // Arrange
+        let dir = temp_dir("snapshot-tail");
+        let (mut journal, _) = Journal::open(&dir).unwrap();
+        let mut accounts = Accounts::new();
+        let envelopes = sample_envelopes();
+        commit_all(&mut journal, &mut accounts, &envelopes[..2]);
+        journal.snapshot(&accounts).unwrap();
+        let snapshotted = accounts.clone();
+        commit_all(&mut journal, &mut accounts, &envelopes[2..]);
+        drop(journal);
+
+        // Act
+        let (journal, sut) = Journal::open(&dir).unwrap();
+
+        // Assert
+        let snapshot = sut.snapshot.as_ref().unwrap();
+        assert_eq!(2, snapshot.seq);
+        assert_eq!(envelopes[2..].to_vec(), sut.envelopes);
+        // The history the snapshot leaves out is read back from the archive
+        let history = journal.history(snapshot.seq).unwrap();
+        assert_eq!(envelopes[..2].to_vec(), history);
+        let mut base = sut.base();
+        base.restore_history(history).unwrap();
+        assert_eq!(snapshotted, base);
+        assert_eq!(
+            accounts,
+            Accounts::replay_from(base, sut.envelopes.clone()).unwrap()
+        );
+        assert_eq!(4, journal.last_seq());
+        assert_eq!(2, journal.records_since_snapshot());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compaction_removes_segments_and_snapshots_that_are_no_longer_needed() {
+        // Arrange
+        let dir = temp_dir("compaction");
+        let (mut journal, _) = Journal::open(&dir).unwrap();
+        let mut accounts = Accounts::new();
+
+        // Act
+        for envelope in sample_envelopes() {
+            commit_all(&mut journal, &mut accounts, &[envelope]);
+            journal.snapshot(&accounts).unwrap();
+        }
+
+        // Assert
+        let snapshots = snapshot::list(&dir).unwrap();
+        assert_eq!(
+            vec![4, 3],
+            snapshots.iter().map(|(seq, _)| *seq).collect::<Vec<_>>()
+        );
+        // Only the records after the oldest kept snapshot remain, plus the empty active segment
+        let segments = list_segments(&dir).unwrap();
+        assert_eq!(
+            vec![4, 5],
+            segments.iter().map(|(seq, _)| *seq).collect::<Vec<_>>()
+        );
+        drop(journal);
+
+        let (_, recovered) = Journal::open(&dir).unwrap();
+        assert_eq!(4, recovered.snapshot.unwrap().seq);
+        assert!(recovered.envelopes.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_corrupted_snapshot_falls_back_to_the_previous_one() {
+        // Arrange
+        let dir = temp_dir("snapshot-fallback");
+        let (mut journal, _) = Journal::open(&dir).unwrap();
+        let mut accounts = Accounts::new();
+        let envelopes = sample_envelopes();
+        commit_all(&mut journal, &mut accounts, &envelopes[..1]);
+        journal.snapshot(&accounts).unwrap();
+        commit_all(&mut journal, &mut accounts, &envelopes[1..]);
+        journal.snapshot(&accounts).unwrap();
+        drop(journal);
+
+        let (_, newest) = &snapshot::list(&dir).unwrap()[0];
+        fs::write(newest, b"garbage").unwrap();
+
+        // Act
+        let (journal, sut) = Journal::open(&dir).unwrap();
+
+        // Assert
+        assert_eq!(1, sut.skipped_snapshots);
+        assert_eq!(1, sut.snapshot.as_ref().unwrap().seq);
+        assert_eq!(envelopes[1..].to_vec(), sut.envelopes);
+        let mut base = sut.base();
+        base.restore_history(journal.history(1).unwrap()).unwrap();
+        assert_eq!(
+            accounts,
+            Accounts::replay_from(base, sut.envelopes.clone()).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,3 +1,19 @@
 pub mod accounts;
+pub mod assets;
+pub mod batch;
+pub mod bookkeeping;
+pub mod calendar;
+pub mod clock;
+pub mod codec;
 pub mod errors;
+pub mod fees;
+pub mod interest;
+pub mod journal;
+pub mod scheduler;
+pub mod simulation;
+pub mod snapshot;
+pub mod statement;
+#[cfg(test)]
+mod test_utils;
 pub mod tx;
+pub mod velocity;
@@ -1,6 +1,30 @@
-use std::{io, process};
+use std::{
+    env, io,
+    path::{Path, PathBuf},
+    process,
+};
 
-use accounting::{accounts::Accounts, tx::Tx};
+use accounting::{
+    accounts::{AccountOrder, Accounts, AsOf, Balance, Leg},
+    assets::Asset,
+    calendar::Date,
+    errors::AccountingError,
+    fees::{Fee, FeeOn, FeeRule, FeeTier},
+    interest::{DayCount, InterestEngine, InterestPolicy, RateTable},
+    journal::Journal,
+    scheduler::{Recurrence, RetryPolicy, RunReport, Schedule, Scheduler},
+    statement::Statement,
+    tx::{Details, Envelope, Tx},
+    velocity::VelocityRules,
+};
+
+/// Used when neither `--data-dir` nor `ACCOUNTING_DATA_DIR` is set
+const DEFAULT_DATA_DIR: &str = "data";
+/// Used when neither `--snapshot-every` nor `ACCOUNTING_SNAPSHOT_EVERY` is set
+const DEFAULT_SNAPSHOT_EVERY: u64 = 1000;
+/// Read from the data directory, if it exists, when neither `--velocity-rules` nor
+/// `ACCOUNTING_VELOCITY_RULES` is set
+const VELOCITY_RULES_FILE: &str = "velocity.rules";
 
 fn read_from_stdin(label: &str) -> String {
     println!("{label}");
@@ -15,68 +39,913 @@ fn read_from_stdin(label: &str) -> String {
     buffer.trim().to_string()
 }
 
+/// Returns the value following `--<name>` on the command line, if any.
+fn flag(name: &str) -> Option<String> {
+    let flag = format!("--{name}");
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+    }
+    None
+}
+
+/// Resolves the data directory from `--data-dir <path>`, then `ACCOUNTING_DATA_DIR`.
+fn data_dir() -> PathBuf {
+    flag("data-dir")
+        .or_else(|| env::var("ACCOUNTING_DATA_DIR").ok())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_DATA_DIR))
+}
+
+/// Resolves how many records to journal between snapshots from `--snapshot-every <n>`,
+/// then `ACCOUNTING_SNAPSHOT_EVERY`.
+fn snapshot_every() -> u64 {
+    flag("snapshot-every")
+        .or_else(|| env::var("ACCOUNTING_SNAPSHOT_EVERY").ok())
+        .map(|every| every.parse().expect("snapshot interval must be a number"))
+        .unwrap_or(DEFAULT_SNAPSHOT_EVERY)
+}
+
+/// Resolves the operator the session acts as from `--operator <name>`, then
+/// `ACCOUNTING_OPERATOR`.
+fn operator() -> Option<String> {
+    flag("operator").or_else(|| env::var("ACCOUNTING_OPERATOR").ok())
+}
+
+/// Resolves the velocity rules file from `--velocity-rules <path>`, then
+/// `ACCOUNTING_VELOCITY_RULES`, then [`VELOCITY_RULES_FILE`] in the data directory.
+fn velocity_rules_path(data_dir: &Path) -> Option<PathBuf> {
+    flag("velocity-rules")
+        .or_else(|| env::var("ACCOUNTING_VELOCITY_RULES").ok())
+        .map(PathBuf::from)
+        .or_else(|| Some(data_dir.join(VELOCITY_RULES_FILE)).filter(|path| path.exists()))
+}
+
 fn main() {
-    let mut ledger = Accounts::new();
-    let mut tx_log = vec![];
+    let snapshot_every = snapshot_every();
+    let operator = operator();
+    let data_dir = data_dir();
+    let (mut journal, recovered) = Journal::open(&data_dir).expect("cannot open journal");
+    if recovered.skipped_snapshots > 0 {
+        println!(
+            "Ignored {} corrupted snapshot(s).",
+            recovered.skipped_snapshots
+        );
+    }
+    if recovered.discarded_bytes > 0 {
+        println!(
+            "Discarded a torn record ({} bytes) at the end of the journal.",
+            recovered.discarded_bytes
+        );
+    }
+
+    // The ledger state `tx_log` is replayed onto when verifying
+    let mut base = match &recovered.snapshot {
+        Some(snapshot) => snapshot.accounts.clone(),
+        None => Accounts::with_double_entry(),
+    };
+    let mut ledger = Accounts::replay_from(base.clone(), recovered.envelopes.iter().cloned())
+        .expect("cannot replay journal");
+    // Only the tail is replayed; the history the snapshot leaves out is only decoded, for
+    // the queries on the past
+    if let Some(snapshot) = &recovered.snapshot {
+        let history = journal
+            .history(snapshot.seq)
+            .expect("cannot read the archived journal");
+        base.restore_history(history.clone())
+            .expect("journal does not match the snapshot");
+        ledger
+            .restore_history(history)
+            .expect("journal does not match the snapshot");
+    }
+    if let Some(path) = velocity_rules_path(&data_dir) {
+        let rules =
+            VelocityRules::load(&path, ledger.assets()).expect("cannot read velocity rules");
+        ledger.set_velocity_rules(rules);
+    }
+    let mut tx_log = recovered.envelopes;
+    let mut scheduler = Scheduler::load(&data_dir).expect("cannot read schedules");
+    let mut interest = InterestEngine::load(&data_dir, Date::from_timestamp(ledger.now()))
+        .expect("cannot read interest accruals");
     loop {
+        // Scheduled transfers and posted interest are journaled before the schedules and
+        // accruals that made them are saved; their idempotency keys make running them again
+        // after a crash in between return the originals
+        let committed = ledger.last_seq();
+        let report = scheduler.run(&mut ledger);
+        let accrued_until = interest.accrued_until();
+        let posted = interest.run(&mut ledger);
+        commit(&mut journal, &mut tx_log, ledger.history_since(committed));
+        if report != RunReport::default() {
+            print_run_report(&report);
+            save_schedules(&scheduler, &data_dir);
+        }
+        match posted {
+            Ok(envelopes) => print_envelopes(&envelopes),
+            Err(accounting_error) => println!("Cannot post interest: {accounting_error:?}"),
+        }
+        if interest.accrued_until() != accrued_until {
+            save_interest(&interest, &data_dir);
+        }
+
         let user_input = read_from_stdin("Enter a command: ");
+        let committed = ledger.last_seq();
+        // What a command reports about the envelopes it recorded, printed once they are
+        // committed
+        let mut acknowledgements = vec![];
 
         match user_input.as_str() {
-            "deposit" => handle_deposit(&mut ledger, &mut tx_log),
-            "withdraw" => handle_withdraw(&mut ledger, &mut tx_log),
-            "send" => handle_send(&mut ledger, &mut tx_log),
+            "register" => handle_register(&mut ledger),
+            "set-fee" => handle_set_fee(&mut ledger),
+            "deposit" => handle_deposit(&mut ledger),
+            "withdraw" => as_session_operator(&mut ledger, operator.as_deref(), handle_withdraw),
+            "send" => as_session_operator(&mut ledger, operator.as_deref(), handle_send),
+            "send-batch" => handle_send_batch(&mut ledger, &mut acknowledgements),
+            "approval-threshold" => handle_approval_threshold(&mut ledger),
+            "pending" => print_pending_approvals(&ledger),
+            "approve" => handle_approval(&mut ledger, operator.as_deref(), Accounts::approve),
+            "reject" => handle_approval(&mut ledger, operator.as_deref(), Accounts::reject),
+            "hold" => handle_hold(&mut ledger, &mut acknowledgements),
+            "release" => handle_settle_hold(&mut ledger, false),
+            "capture" => handle_settle_hold(&mut ledger, true),
+            "open" => handle_lifecycle(&mut ledger, Accounts::open),
+            "freeze" => handle_lifecycle(&mut ledger, Accounts::freeze),
+            "unfreeze" => handle_lifecycle(&mut ledger, Accounts::unfreeze),
+            "close" => handle_lifecycle(&mut ledger, Accounts::close),
+            "credit-limit" => handle_credit_limit(&mut ledger),
+            "dispute" => handle_dispute(&mut ledger, Accounts::dispute),
+            "resolve" => handle_dispute(&mut ledger, Accounts::resolve),
+            "chargeback" => handle_dispute(&mut ledger, Accounts::chargeback),
+            "reverse" => handle_reverse(&mut ledger),
+            "schedule" => handle_schedule(&mut scheduler, &ledger, &data_dir),
+            "unschedule" => handle_unschedule(&mut scheduler, &data_dir),
+            "schedules" => print_schedules(&scheduler),
+            "interest-rate" => handle_interest_rate(&mut interest, &ledger, &data_dir),
             "print" => {
                 println!("{ledger:#?}");
             }
+            "balance" => print_balance(&ledger),
+            "balance-as-of" => print_balance_as_of(&ledger),
+            "accounts" => print_accounts(&ledger),
+            "history" => print_history(&ledger),
+            "statement" => print_statement(&ledger),
+            "trial-balance" => print_trial_balance(&ledger),
+            "log" => print_tx_log(&tx_log),
+            "verify" => match Accounts::replay_from(base.clone(), tx_log.iter().cloned()) {
+                Ok(replayed) if replayed == ledger => {
+                    println!("Ledger matches the transaction log.")
+                }
+                Ok(_) => println!("Ledger does NOT match the transaction log."),
+                Err(accounting_error) => println!("{accounting_error:?}"),
+            },
             "quit" => process::exit(1),
             _ => println!("Command '{user_input}' not found."),
         }
+        commit(&mut journal, &mut tx_log, ledger.history_since(committed));
+        for acknowledgement in acknowledgements {
+            println!("{acknowledgement}");
+        }
+
+        if journal.records_since_snapshot() >= snapshot_every {
+            match journal.snapshot(&ledger) {
+                Ok(()) => {
+                    base = ledger.clone();
+                    tx_log.clear();
+                }
+                Err(e) => println!("Cannot write a snapshot: {e}"),
+            }
+        }
     }
 }
 
-fn handle_deposit(ledger: &mut Accounts, tx_log: &mut Vec<Tx>) {
+/// Makes the `envelopes` a command recorded durable before they are acknowledged by
+/// adding them to the `tx_log`.
+///
+/// The ledger already reflects the `envelopes`, so a failed write leaves memory ahead of
+/// disk; exiting lets the next start rebuild the ledger from the journal alone.
+fn commit(journal: &mut Journal, tx_log: &mut Vec<Envelope>, envelopes: &[Envelope]) {
+    for envelope in envelopes {
+        if let Err(e) = journal.append(envelope) {
+            println!("Cannot write to the journal: {e}");
+            process::exit(1);
+        }
+        tx_log.push(envelope.clone());
+    }
+}
+
+/// Prints the transactions recorded since the last snapshot
+fn print_tx_log(tx_log: &[Envelope]) {
+    print_envelopes(tx_log);
+}
+
+fn print_envelopes<'a>(envelopes: impl IntoIterator<Item = &'a Envelope>) {
+    for envelope in envelopes {
+        println!("{}", describe_envelope(envelope));
+    }
+}
+
+/// The sequence id, timestamp and transaction of `envelope`, with its details on the
+/// lines below
+fn describe_envelope(envelope: &Envelope) -> String {
+    let mut description = format!(
+        "#{} at {}: {:?}",
+        envelope.seq, envelope.timestamp, envelope.tx
+    );
+    if let Some(memo) = &envelope.details.memo {
+        description.push_str(&format!("\n    memo: {memo}"));
+    }
+    if let Some(reference) = &envelope.details.reference {
+        description.push_str(&format!("\n    reference: {reference}"));
+    }
+    description
+}
+
+fn print_balance(ledger: &Accounts) {
     let signer = read_from_stdin("Enter signer: ");
-    let amount = read_from_stdin("Enter amount: ").parse::<u64>();
+    let Some(account) = ledger.account(&signer) else {
+        println!("{:?}", AccountingError::AccountNotFound(signer));
+        return;
+    };
 
-    match amount {
-        Ok(amount) => match ledger.deposit(signer.as_str(), amount) {
-            Ok(tx) => {
-                tx_log.push(tx);
+    println!("{} ({:?})", account.signer, account.state);
+    for (asset, balance) in &account.balances {
+        print_balance_row(asset, balance);
+    }
+}
+
+/// Prints a balance at the end of a past day or right after a past sequence id
+fn print_balance_as_of(ledger: &Accounts) {
+    let signer = read_from_stdin("Enter signer: ");
+    let point = read_from_stdin("Enter a sequence id or a date (YYYY-MM-DD): ");
+    let point = match (point.parse(), Date::parse(&point)) {
+        (Ok(seq), _) => AsOf::Seq(seq),
+        (_, Some(date)) => AsOf::Timestamp(date.next().timestamp() - 1),
+        _ => {
+            println!("Invalid sequence id or date '{point}'.");
+            return;
+        }
+    };
+
+    match ledger.balance_as_of(&signer, point) {
+        Ok(balances) => {
+            for (asset, balance) in &balances {
+                print_balance_row(asset, balance);
             }
-            Err(accounting_error) => println!("{accounting_error:?}"),
+        }
+        Err(accounting_error) => println!("{accounting_error:?}"),
+    }
+}
+
+fn print_balance_row(asset: &str, balance: &Balance) {
+    println!(
+        "{:<8} total {:>20} held {:>20} credit used {:>20}",
+        asset, balance.total, balance.held, balance.credit_used
+    );
+}
+
+fn print_accounts(ledger: &Accounts) {
+    let order = read_from_stdin("Order by (name or an asset code to sort by balance): ");
+    let order = match order.as_str() {
+        "" | "name" => AccountOrder::Name,
+        asset => AccountOrder::Balance(asset),
+    };
+
+    for account in ledger.list_accounts(order) {
+        let balances = account
+            .balances
+            .iter()
+            .map(|(asset, balance)| format!("{asset} {}", balance.net()))
+            .collect::<Vec<_>>();
+        println!(
+            "{:<24} {:<8?} {}",
+            account.signer,
+            account.state,
+            balances.join(", ")
+        );
+    }
+}
+
+/// Prints every transaction touching an account, a page at a time
+fn print_history(ledger: &Accounts) {
+    const PAGE_SIZE: usize = 20;
+
+    let signer = read_from_stdin("Enter signer: ");
+    let mut after = 0;
+    loop {
+        let page = ledger.account_history(&signer, after, PAGE_SIZE);
+        print_envelopes(page.envelopes);
+        let Some(next_after) = page.next_after else {
+            break;
+        };
+        if read_from_stdin("Show more? (y/n): ") != "y" {
+            break;
+        }
+        after = next_after;
+    }
+}
+
+fn print_statement(ledger: &Accounts) {
+    let signer = read_from_stdin("Enter signer: ");
+    let from = read_from_stdin("Enter first day (YYYY-MM-DD): ");
+    let to = read_from_stdin("Enter last day (YYYY-MM-DD): ");
+    let (Some(from), Some(to)) = (Date::parse(&from), Date::parse(&to)) else {
+        println!("Invalid date '{from}' or '{to}'.");
+        return;
+    };
+    let format = read_from_stdin("Enter format (csv or text): ");
+
+    match Statement::generate(ledger, &signer, from, to) {
+        Ok(statement) if format == "csv" => print!("{}", statement.to_csv()),
+        Ok(statement) => print!("{}", statement.to_text()),
+        Err(accounting_error) => println!("{accounting_error:?}"),
+    }
+}
+
+fn print_trial_balance(ledger: &Accounts) {
+    let Some(trial_balance) = ledger.trial_balance() else {
+        println!("Double-entry bookkeeping is not enabled for this ledger.");
+        return;
+    };
+
+    for row in &trial_balance.rows {
+        println!(
+            "{:<24} {:<8} {:>24} {:>24}",
+            row.account, row.asset, row.debits, row.credits
+        );
+    }
+    match trial_balance.assert_balanced() {
+        Ok(()) => println!("Debits equal credits."),
+        Err(accounting_error) => println!("{accounting_error:?}"),
+    }
+    match trial_balance.reconcile(ledger) {
+        Ok(()) => println!("Customer accounts match the books."),
+        Err(accounting_error) => println!("{accounting_error:?}"),
+    }
+}
+
+/// Reads an amount in `asset`'s major units, e.g. `1.5`, and converts it into minor units.
+fn read_amount(ledger: &Accounts, asset: &str) -> Result<u64, String> {
+    let value = read_from_stdin("Enter amount: ");
+    let asset = ledger
+        .assets()
+        .get(asset)
+        .ok_or_else(|| format!("{:?}", AccountingError::UnknownAsset(asset.to_string())))?;
+
+    asset.parse(&value).ok_or_else(|| {
+        format!(
+            "Invalid amount '{value}' for {} ({} decimals).",
+            asset.code, asset.decimals
+        )
+    })
+}
+
+/// Reads the optional memo and external reference attached to a transaction
+fn read_details() -> Details {
+    let optional = |value: String| (!value.is_empty()).then_some(value);
+
+    Details {
+        memo: optional(read_from_stdin("Enter memo (optional): ")),
+        reference: optional(read_from_stdin("Enter reference (optional): ")),
+    }
+}
+
+fn handle_register(ledger: &mut Accounts) {
+    let asset = read_from_stdin("Enter asset: ");
+    let decimals = read_from_stdin("Enter decimals: ").parse::<u8>();
+
+    match decimals {
+        Ok(decimals) => {
+            if let Err(accounting_error) = ledger.register_asset(asset.as_str(), decimals) {
+                println!("{accounting_error:?}");
+            }
+        }
+        Err(e) => println!("{e}"),
+    }
+}
+
+/// Parses a fee rule such as `flat 0.25`, `bps 30` or
+/// `tiered 100=flat 0.50;1000=bps 40;*=bps 25`, with amounts in `asset`'s major units.
+fn parse_fee_rule(asset: &Asset, spec: &str) -> Option<FeeRule> {
+    let (kind, value) = spec.trim().split_once(' ')?;
+    match kind {
+        "flat" => asset.parse(value.trim()).map(FeeRule::Flat),
+        "bps" => value.trim().parse().ok().map(FeeRule::BasisPoints),
+        "tiered" => {
+            let mut tiers = vec![];
+            for tier in value.split(';') {
+                let (up_to, rule) = tier.split_once('=')?;
+                let up_to = match up_to.trim() {
+                    "*" => None,
+                    up_to => Some(asset.parse(up_to)?),
+                };
+                let rule = parse_fee_rule(asset, rule)?;
+                if matches!(rule, FeeRule::Tiered(_)) {
+                    return None;
+                }
+                tiers.push(FeeTier { up_to, rule });
+            }
+            tiers.sort_by_key(|tier| tier.up_to.unwrap_or(u64::MAX));
+            Some(FeeRule::Tiered(tiers))
+        }
+        _ => None,
+    }
+}
+
+/// Reads the fee charged on withdrawals or transfers in an asset; `none` removes it
+fn read_fee(ledger: &Accounts, asset: &str) -> Result<Option<Fee>, String> {
+    let spec = read_from_stdin(
+        "Enter fee rule (flat <amount>, bps <rate>, tiered <up to>=<rule>;...;*=<rule>, or none): ",
+    );
+    if spec == "none" {
+        return Ok(None);
+    }
+    let asset = ledger
+        .assets()
+        .get(asset)
+        .ok_or_else(|| format!("{:?}", AccountingError::UnknownAsset(asset.to_string())))?;
+    let rule = parse_fee_rule(asset, &spec).ok_or_else(|| format!("Invalid fee rule '{spec}'."))?;
+
+    let min = read_from_stdin("Enter minimum fee (optional): ");
+    let max = read_from_stdin("Enter maximum fee (optional): ");
+    let parse_bound = |value: &str| {
+        asset
+            .parse(value)
+            .ok_or_else(|| format!("Invalid fee bound '{value}'."))
+    };
+    Ok(Some(Fee {
+        rule,
+        min: if min.is_empty() {
+            0
+        } else {
+            parse_bound(&min)?
+        },
+        max: if max.is_empty() {
+            None
+        } else {
+            Some(parse_bound(&max)?)
         },
+    }))
+}
+
+fn handle_set_fee(ledger: &mut Accounts) {
+    let on = match read_from_stdin("Enter operation (withdraw or send): ").as_str() {
+        "withdraw" => FeeOn::Withdrawal,
+        "send" => FeeOn::Transfer,
+        other => {
+            println!("Fees cannot be charged on '{other}'.");
+            return;
+        }
+    };
+    let asset = read_from_stdin("Enter asset: ");
+
+    match read_fee(ledger, &asset) {
+        Ok(fee) => {
+            if let Err(accounting_error) = ledger.set_fee(on, asset.as_str(), fee) {
+                println!("{accounting_error:?}");
+            }
+        }
         Err(e) => println!("{e}"),
     }
 }
 
-fn handle_withdraw(ledger: &mut Accounts, tx_log: &mut Vec<Tx>) {
+fn handle_deposit(ledger: &mut Accounts) {
     let signer = read_from_stdin("Enter signer: ");
-    let amount = read_from_stdin("Enter amount: ").parse::<u64>();
+    let asset = read_from_stdin("Enter asset: ");
+    let amount = read_amount(ledger, &asset);
+    let key = read_from_stdin("Enter idempotency key (optional): ");
+    let details = read_details();
 
     match amount {
-        Ok(amount) => match ledger.withdraw(signer.as_str(), amount) {
-            Ok(tx) => {
-                tx_log.push(tx);
+        Ok(amount) => {
+            let is_retry = ledger.idempotent_tx(&key).is_some();
+            let result = ledger.annotated(details, |ledger| {
+                if key.is_empty() {
+                    ledger.deposit(signer.as_str(), asset.as_str(), amount)
+                } else {
+                    ledger.deposit_idempotent(key.as_str(), signer.as_str(), asset.as_str(), amount)
+                }
+            });
+            match result {
+                Ok(_) if is_retry => println!("Already processed."),
+                Ok(_) => {}
+                Err(accounting_error) => println!("{accounting_error:?}"),
             }
-            Err(accounting_error) => println!("{accounting_error:?}"),
-        },
+        }
         Err(e) => println!("{e}"),
     }
 }
 
-fn handle_send(ledger: &mut Accounts, tx_log: &mut Vec<Tx>) {
+fn handle_withdraw(ledger: &mut Accounts) {
+    let signer = read_from_stdin("Enter signer: ");
+    let asset = read_from_stdin("Enter asset: ");
+    let amount = read_amount(ledger, &asset);
+    let key = read_from_stdin("Enter idempotency key (optional): ");
+    let details = read_details();
+
+    match amount {
+        Ok(amount) => {
+            let is_retry = ledger.idempotent_tx(&key).is_some();
+            let result = ledger.annotated(details, |ledger| {
+                if key.is_empty() {
+                    ledger.withdraw(signer.as_str(), asset.as_str(), amount)
+                } else {
+                    ledger.withdraw_idempotent(
+                        key.as_str(),
+                        signer.as_str(),
+                        asset.as_str(),
+                        amount,
+                    )
+                }
+            });
+            match result {
+                Ok(_) if is_retry => println!("Already processed."),
+                Ok(_) => {}
+                Err(accounting_error) => println!("{accounting_error:?}"),
+            }
+        }
+        Err(e) => println!("{e}"),
+    }
+}
+
+fn handle_send(ledger: &mut Accounts) {
     let sender = read_from_stdin("Enter sender: ");
     let recipient = read_from_stdin("Enter recipient: ");
-    let amount = read_from_stdin("Enter amount: ").parse::<u64>();
+    let asset = read_from_stdin("Enter asset: ");
+    let amount = read_amount(ledger, &asset);
+    let details = read_details();
+
+    match amount {
+        Ok(amount) => {
+            let result = ledger.annotated(details, |ledger| {
+                ledger.send(sender.as_str(), recipient.as_str(), asset.as_str(), amount)
+            });
+            if let Err(accounting_error) = result {
+                println!("{accounting_error:?}");
+            }
+        }
+        Err(e) => println!("{e}"),
+    }
+}
+
+fn handle_credit_limit(ledger: &mut Accounts) {
+    let signer = read_from_stdin("Enter signer: ");
+    let asset = read_from_stdin("Enter asset: ");
+    let limit = read_amount(ledger, &asset);
+
+    match limit {
+        Ok(limit) => {
+            if let Err(accounting_error) =
+                ledger.set_credit_limit(signer.as_str(), asset.as_str(), limit)
+            {
+                println!("{accounting_error:?}");
+            }
+        }
+        Err(e) => println!("{e}"),
+    }
+}
+
+fn handle_hold(ledger: &mut Accounts, acknowledgements: &mut Vec<String>) {
+    let signer = read_from_stdin("Enter signer: ");
+    let asset = read_from_stdin("Enter asset: ");
+    let amount = read_amount(ledger, &asset);
 
     match amount {
-        Ok(amount) => match ledger.send(sender.as_str(), recipient.as_str(), amount) {
-            Ok((withdraw_tx, deposit_tx)) => {
-                tx_log.push(withdraw_tx);
-                tx_log.push(deposit_tx);
+        Ok(amount) => match ledger.hold(signer.as_str(), asset.as_str(), amount) {
+            Ok(envelope) => {
+                if let Tx::Hold { id, .. } = &envelope.tx {
+                    acknowledgements.push(format!("Hold id: {id}"));
+                }
             }
             Err(accounting_error) => println!("{accounting_error:?}"),
         },
         Err(e) => println!("{e}"),
     }
 }
+
+/// Releases the hold entered by the user, or captures it if `capture` is set
+fn handle_settle_hold(ledger: &mut Accounts, capture: bool) {
+    let hold_id = read_from_stdin("Enter hold id: ").parse::<u64>();
+
+    match hold_id {
+        Ok(hold_id) => {
+            let result = if capture {
+                ledger.capture(hold_id)
+            } else {
+                ledger.release(hold_id)
+            };
+            if let Err(accounting_error) = result {
+                println!("{accounting_error:?}");
+            }
+        }
+        Err(e) => println!("{e}"),
+    }
+}
+
+/// Applies an account lifecycle `change` to the signer entered by the user
+fn handle_lifecycle(
+    ledger: &mut Accounts,
+    change: fn(&mut Accounts, &str) -> Result<Envelope, AccountingError>,
+) {
+    let signer = read_from_stdin("Enter signer: ");
+
+    if let Err(accounting_error) = change(ledger, signer.as_str()) {
+        println!("{accounting_error:?}");
+    }
+}
+
+fn handle_dispute(
+    ledger: &mut Accounts,
+    step: fn(&mut Accounts, u64) -> Result<Envelope, AccountingError>,
+) {
+    let deposit = read_from_stdin("Enter the sequence id of the deposit: ");
+
+    match deposit.parse() {
+        Ok(deposit) => {
+            if let Err(accounting_error) = step(ledger, deposit) {
+                println!("{accounting_error:?}");
+            }
+        }
+        Err(_) => println!("Invalid sequence id '{deposit}'."),
+    }
+}
+
+/// Reads legs until an empty sender is entered and applies them all or none.
+fn handle_send_batch(ledger: &mut Accounts, acknowledgements: &mut Vec<String>) {
+    let mut legs = vec![];
+    loop {
+        let from = read_from_stdin("Enter sender (empty to apply the batch): ");
+        if from.is_empty() {
+            break;
+        }
+        let to = read_from_stdin("Enter recipient: ");
+        let asset = read_from_stdin("Enter asset: ");
+        match read_amount(ledger, &asset) {
+            Ok(amount) => legs.push(Leg {
+                from,
+                to,
+                asset,
+                amount,
+            }),
+            Err(e) => println!("{e}"),
+        }
+    }
+
+    match ledger.apply_batch(legs) {
+        Ok(envelopes) => acknowledgements.extend(envelopes.iter().map(describe_envelope)),
+        Err(accounting_error) => println!("{accounting_error:?}"),
+    }
+}
+
+/// Writes the schedules next to the journal; a failure is reported and the schedules
+/// stay in memory.
+fn save_schedules(scheduler: &Scheduler, data_dir: &Path) {
+    if let Err(e) = scheduler.save(data_dir) {
+        println!("Cannot write the schedules: {e}");
+    }
+}
+
+/// Writes the interest policies and accruals next to the journal; a failure is reported
+/// and they stay in memory.
+fn save_interest(interest: &InterestEngine, data_dir: &Path) {
+    if let Err(e) = interest.save(data_dir) {
+        println!("Cannot write the interest accruals: {e}");
+    }
+}
+
+fn handle_interest_rate(interest: &mut InterestEngine, ledger: &Accounts, data_dir: &Path) {
+    let asset = read_from_stdin("Enter asset: ");
+    if ledger.assets().get(&asset).is_none() {
+        println!("{:?}", AccountingError::UnknownAsset(asset));
+        return;
+    }
+    let mut rates = [RateTable::default(), RateTable::default()];
+    for (rates, label) in rates.iter_mut().zip(["credit", "debit"]) {
+        let rate = read_from_stdin(&format!(
+            "Enter annual {label} rate in basis points (optional): "
+        ));
+        match rate.as_str() {
+            "" => {}
+            _ => match rate.parse() {
+                Ok(rate_bps) => *rates = RateTable::flat(rate_bps),
+                Err(_) => {
+                    println!("Invalid rate '{rate}'.");
+                    return;
+                }
+            },
+        }
+    }
+    let day_count = match read_from_stdin("Enter day count (act/365 or 30/360): ").as_str() {
+        "act/365" => DayCount::Act365,
+        "30/360" => DayCount::Thirty360,
+        other => {
+            println!("Invalid day count '{other}'.");
+            return;
+        }
+    };
+
+    let [credit_rates, debit_rates] = rates;
+    interest.set_policy(
+        &asset,
+        InterestPolicy {
+            credit_rates,
+            debit_rates,
+            day_count,
+        },
+    );
+    save_interest(interest, data_dir);
+}
+
+fn print_run_report(report: &RunReport) {
+    print_envelopes(&report.executed);
+    for (id, request) in &report.pending {
+        if let Tx::ApprovalRequested { id: request, .. } = request.tx {
+            println!("Schedule #{id} is waiting for approval as request #{request}");
+        }
+    }
+    for (id, failure) in &report.failures {
+        println!(
+            "Schedule #{id} failed on attempt {} for {}: {}",
+            failure.attempt, failure.due, failure.error
+        );
+    }
+}
+
+fn print_schedules(scheduler: &Scheduler) {
+    for transfer in scheduler.transfers() {
+        let schedule = &transfer.schedule;
+        let next_due = transfer
+            .next_due()
+            .map_or_else(|| "-".to_string(), |due| due.to_string());
+        println!(
+            "#{} {} -> {} {} {} {:?} next {next_due}",
+            transfer.id,
+            schedule.from,
+            schedule.to,
+            schedule.amount,
+            schedule.asset,
+            schedule.recurrence
+        );
+    }
+}
+
+fn handle_schedule(scheduler: &mut Scheduler, ledger: &Accounts, data_dir: &Path) {
+    let from = read_from_stdin("Enter sender: ");
+    let to = read_from_stdin("Enter recipient: ");
+    let asset = read_from_stdin("Enter asset: ");
+    let amount = match read_amount(ledger, &asset) {
+        Ok(amount) => amount,
+        Err(e) => {
+            println!("{e}");
+            return;
+        }
+    };
+    let recurrence =
+        match read_from_stdin("Enter recurrence (once, daily, weekly, monthly or month-end): ")
+            .as_str()
+        {
+            "once" => Recurrence::Once,
+            "daily" => Recurrence::Daily,
+            "weekly" => Recurrence::Weekly,
+            "monthly" => Recurrence::Monthly,
+            "month-end" => Recurrence::MonthEnd,
+            other => {
+                println!("Invalid recurrence '{other}'.");
+                return;
+            }
+        };
+    let start = read_from_stdin("Enter first day (YYYY-MM-DD): ");
+    let Some(start) = Date::parse(&start) else {
+        println!("Invalid date '{start}'.");
+        return;
+    };
+    let end = read_from_stdin("Enter last day (YYYY-MM-DD, optional): ");
+    let end = match end.as_str() {
+        "" => None,
+        _ => match Date::parse(&end) {
+            Some(end) => Some(end),
+            None => {
+                println!("Invalid date '{end}'.");
+                return;
+            }
+        },
+    };
+
+    let schedule = Schedule {
+        from,
+        to,
+        asset,
+        amount,
+        recurrence,
+        start,
+        end,
+        retry: RetryPolicy::default(),
+    };
+    match scheduler.add(ledger, schedule) {
+        Ok(id) => {
+            println!("Added schedule #{id}.");
+            save_schedules(scheduler, data_dir);
+        }
+        Err(accounting_error) => println!("{accounting_error:?}"),
+    }
+}
+
+fn handle_unschedule(scheduler: &mut Scheduler, data_dir: &Path) {
+    let id = read_from_stdin("Enter schedule id: ");
+
+    match id.parse().map(|id| scheduler.cancel(id)) {
+        Ok(Ok(_)) => save_schedules(scheduler, data_dir),
+        Ok(Err(accounting_error)) => println!("{accounting_error:?}"),
+        Err(_) => println!("Invalid schedule id '{id}'."),
+    }
+}
+
+/// Runs `command` on behalf of the session's operator, if one is set, so requests for
+/// approval it makes name them as the maker.
+fn as_session_operator(ledger: &mut Accounts, operator: Option<&str>, command: fn(&mut Accounts)) {
+    match operator {
+        Some(operator) => {
+            let _ = ledger.as_operator(operator, |ledger| {
+                command(ledger);
+                Ok(())
+            });
+        }
+        None => command(ledger),
+    }
+}
+
+fn handle_approval_threshold(ledger: &mut Accounts) {
+    let asset = read_from_stdin("Enter asset: ");
+    println!("Leave the amount empty to lift the threshold.");
+    let threshold = read_from_stdin("Enter amount: ");
+    let threshold = match (threshold.as_str(), ledger.assets().get(&asset)) {
+        ("", _) => None,
+        (threshold, Some(registered)) => match registered.parse(threshold) {
+            Some(threshold) => Some(threshold),
+            None => {
+                println!("Invalid amount '{threshold}'.");
+                return;
+            }
+        },
+        (_, None) => {
+            println!("{:?}", AccountingError::UnknownAsset(asset));
+            return;
+        }
+    };
+
+    if let Err(accounting_error) = ledger.set_approval_threshold(&asset, threshold) {
+        println!("{accounting_error:?}");
+    }
+}
+
+fn print_pending_approvals(ledger: &Accounts) {
+    for (id, pending) in ledger.pending_approvals() {
+        let operation = match &pending.to {
+            Some(to) => format!("transfer from {} to {to}", pending.from),
+            None => format!("withdrawal from {}", pending.from),
+        };
+        let amount = ledger.assets().get(&pending.asset).map_or_else(
+            || pending.amount.to_string(),
+            |asset| asset.format(pending.amount),
+        );
+        println!(
+            "#{id} {operation} of {amount} {} requested by {}",
+            pending.asset, pending.maker
+        );
+    }
+}
+
+fn handle_approval(
+    ledger: &mut Accounts,
+    operator: Option<&str>,
+    decide: fn(&mut Accounts, u64, &str) -> Result<Envelope, AccountingError>,
+) {
+    let Some(operator) = operator else {
+        println!("Set --operator or ACCOUNTING_OPERATOR to approve or reject requests.");
+        return;
+    };
+    let id = read_from_stdin("Enter request id: ");
+
+    match id.parse() {
+        Ok(id) => {
+            if let Err(accounting_error) = decide(ledger, id, operator) {
+                println!("{accounting_error:?}");
+            }
+        }
+        Err(_) => println!("Invalid request id '{id}'."),
+    }
+}
+
+fn handle_reverse(ledger: &mut Accounts) {
+    let tx_id = read_from_stdin("Enter the sequence id of the transaction to reverse: ");
+
+    match tx_id.parse() {
+        Ok(tx_id) => {
+            if let Err(accounting_error) = ledger.reverse(tx_id) {
+                println!("{accounting_error:?}");
+            }
+        }
+        Err(_) => println!("Invalid sequence id '{tx_id}'."),
+    }
+}
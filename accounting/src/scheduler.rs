@@ -0,0 +1,624 @@
+//! Standing orders: transfers made with [`Accounts::send`] on a schedule.
+//!
+//! A [`Scheduler`] holds every [`Schedule`] with how far it has got and, on each
+//! [`Scheduler::run`], makes the transfers that have fallen due by the ledger's clock.
+//! An occurrence is due from the start of its day, UTC. A transfer that fails is
+//! retried as its schedule's [`RetryPolicy`] allows and then skipped, and occurrences
+//! missed while the scheduler was not run are made one after the other on the next run.
+//! A transfer above its asset's approval threshold is requested instead and reported as
+//! pending; the schedule then moves on, leaving the request to its checker.
+//!
+//! [`Scheduler::save`] keeps the schedules in a file next to the journal, as
+//! `[CRC-32 of payload: u32][payload]`. Each transfer is sent with an idempotency key made
+//! of its schedule's id and due date, so running an occurrence again after a crash between
+//! journaling its transfer and saving the schedules returns the original instead of
+//! sending twice.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    accounts::Accounts,
+    calendar::{days_in_month, Date},
+    codec::{crc32, invalid_data, Decode, Decoder, Encode, Encoder},
+    errors::AccountingError,
+    tx::{Envelope, Tx},
+};
+
+/// The file [`Scheduler::save`] writes in the data directory
+const SCHEDULES_FILE: &str = "schedules.bin";
+
+/// How often a [`Schedule`] repeats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recurrence {
+    /// Only on the start date
+    Once,
+    Daily,
+    Weekly,
+    /// On the start date's day of the month, or on the last day of shorter months
+    Monthly,
+    /// On the last day of every month from the start date's month
+    MonthEnd,
+}
+
+impl Recurrence {
+    /// The date of the `n`th occurrence from `start`, the first being `0`; `None` past
+    /// the last one
+    pub fn occurrence(&self, start: Date, n: u32) -> Option<Date> {
+        match self {
+            Recurrence::Once => (n == 0).then_some(start),
+            Recurrence::Daily => Some(start.add_days(i64::from(n))),
+            Recurrence::Weekly => Some(start.add_days(7 * i64::from(n))),
+            // Counted from the start date so a day clamped in a short month is not kept
+            Recurrence::Monthly => Some(start.add_months(n)),
+            Recurrence::MonthEnd => {
+                let month = start.add_months(n);
+                Some(Date {
+                    day: days_in_month(month.year, month.month),
+                    ..month
+                })
+            }
+        }
+    }
+}
+
+/// How a failed transfer is retried before its occurrence is skipped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first one
+    pub max_retries: u32,
+    /// Seconds to wait after a failed attempt
+    pub backoff: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            backoff: 3_600,
+        }
+    }
+}
+
+/// A transfer to make on a schedule
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    pub from: String,
+    pub to: String,
+    pub asset: String,
+    pub amount: u64,
+    pub recurrence: Recurrence,
+    /// The date of the first occurrence
+    pub start: Date,
+    /// The last date an occurrence may fall on, if any
+    pub end: Option<Date>,
+    pub retry: RetryPolicy,
+}
+
+/// A failed attempt at an occurrence of a schedule
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Failure {
+    /// The date of the occurrence
+    pub due: Date,
+    /// `1` for the first attempt
+    pub attempt: u32,
+    /// When the attempt was made
+    pub timestamp: u64,
+    /// The error [`Accounts::send`] returned, as debug text
+    pub error: String,
+}
+
+/// A [`Schedule`] and how far it has got
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScheduledTransfer {
+    pub id: u64,
+    pub schedule: Schedule,
+    /// The index of the next occurrence
+    occurrence: u32,
+    /// Failed attempts at the next occurrence
+    attempts: u32,
+    /// When the next occurrence may be retried after a failed attempt
+    retry_at: Option<u64>,
+    /// The most recent failed attempt of any occurrence
+    last_failure: Option<Failure>,
+}
+
+impl ScheduledTransfer {
+    /// The date of the next occurrence; `None` once the schedule has run its course
+    pub fn next_due(&self) -> Option<Date> {
+        self.schedule
+            .recurrence
+            .occurrence(self.schedule.start, self.occurrence)
+            .filter(|due| self.schedule.end.is_none_or(|end| *due <= end))
+    }
+
+    /// The most recent failed attempt of any occurrence
+    pub fn last_failure(&self) -> Option<&Failure> {
+        self.last_failure.as_ref()
+    }
+
+    /// The idempotency key the transfer due on `due` is sent with
+    fn idempotency_key(&self, due: Date) -> String {
+        format!("schedule-{}-{due}", self.id)
+    }
+
+    /// Moves on to the next occurrence
+    fn advance(&mut self) {
+        self.occurrence += 1;
+        self.attempts = 0;
+        self.retry_at = None;
+    }
+}
+
+/// What a call to [`Scheduler::run`] did
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RunReport {
+    /// The transfers made, in order
+    pub executed: Vec<Envelope>,
+    /// The requests for approval made for transfers above the approval threshold, with
+    /// the id of their schedule
+    pub pending: Vec<(u64, Envelope)>,
+    /// Every failed attempt, with the id of its schedule
+    pub failures: Vec<(u64, Failure)>,
+}
+
+/// Keeps standing orders and makes their transfers when due
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Scheduler {
+    /// Keyed by id; schedules are removed once they have run their course
+    transfers: BTreeMap<u64, ScheduledTransfer>,
+    /// The id of the last schedule added
+    last_id: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler::default()
+    }
+
+    /// Adds `schedule` and returns its id.
+    ///
+    /// # Errors
+    /// - [`AccountingError::UnknownAsset`] if `accounts` does not know the asset
+    pub fn add(&mut self, accounts: &Accounts, schedule: Schedule) -> Result<u64, AccountingError> {
+        accounts.assets().require(&schedule.asset)?;
+        self.last_id += 1;
+        self.transfers.insert(
+            self.last_id,
+            ScheduledTransfer {
+                id: self.last_id,
+                schedule,
+                occurrence: 0,
+                attempts: 0,
+                retry_at: None,
+                last_failure: None,
+            },
+        );
+        Ok(self.last_id)
+    }
+
+    /// Removes a schedule before it has run its course.
+    ///
+    /// # Errors
+    /// - [`AccountingError::ScheduleNotFound`] if no schedule has this id
+    pub fn cancel(&mut self, id: u64) -> Result<Schedule, AccountingError> {
+        self.transfers
+            .remove(&id)
+            .map(|transfer| transfer.schedule)
+            .ok_or(AccountingError::ScheduleNotFound(id))
+    }
+
+    /// Every schedule that has not run its course, by id
+    pub fn transfers(&self) -> impl Iterator<Item = &ScheduledTransfer> {
+        self.transfers.values()
+    }
+
+    /// Makes every transfer due by the clock of `accounts`, retrying or skipping the ones
+    /// that fail, and drops the schedules that have run their course.
+    pub fn run(&mut self, accounts: &mut Accounts) -> RunReport {
+        let now = accounts.now();
+        let mut report = RunReport::default();
+        for transfer in self.transfers.values_mut() {
+            while let Some(due) = transfer.next_due() {
+                if now < due.timestamp() || transfer.retry_at.is_some_and(|at| now < at) {
+                    break;
+                }
+
+                let schedule = &transfer.schedule;
+                match accounts.send_idempotent(
+                    &transfer.idempotency_key(due),
+                    &schedule.from,
+                    &schedule.to,
+                    &schedule.asset,
+                    schedule.amount,
+                ) {
+                    Ok(envelope) => {
+                        if matches!(envelope.tx, Tx::ApprovalRequested { .. }) {
+                            report.pending.push((transfer.id, envelope));
+                        } else {
+                            report.executed.push(envelope);
+                        }
+                        transfer.advance();
+                    }
+                    Err(accounting_error) => {
+                        transfer.attempts += 1;
+                        let failure = Failure {
+                            due,
+                            attempt: transfer.attempts,
+                            timestamp: now,
+                            error: format!("{accounting_error:?}"),
+                        };
+                        report.failures.push((transfer.id, failure.clone()));
+                        transfer.last_failure = Some(failure);
+                        if transfer.attempts > schedule.retry.max_retries {
+                            transfer.advance();
+                        } else {
+                            transfer.retry_at = Some(now.saturating_add(schedule.retry.backoff));
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.transfers
+            .retain(|_, transfer| transfer.next_due().is_some());
+        report
+    }
+
+    /// Writes the schedules to their file in `dir`, replacing the previous one.
+    ///
+    /// The file is written under a temporary name and renamed once it is synced, so a
+    /// crash leaves either the old or the new schedules behind.
+    ///
+    /// # Errors
+    /// - the file cannot be written, synced or renamed
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let mut encoder = Encoder::new();
+        self.encode(&mut encoder);
+        let payload = encoder.into_bytes();
+
+        let path = dir.join(SCHEDULES_FILE);
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&crc32(&payload).to_le_bytes())?;
+        file.write_all(&payload)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        File::open(dir)?.sync_all()
+    }
+
+    /// Reads the schedules saved in `dir`; an empty scheduler if none were saved.
+    ///
+    /// # Errors
+    /// - the file cannot be read
+    /// - the checksum does not match or the contents cannot be decoded
+    pub fn load(dir: &Path) -> io::Result<Scheduler> {
+        let bytes = match fs::read(dir.join(SCHEDULES_FILE)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Scheduler::new()),
+            Err(e) => return Err(e),
+        };
+        if bytes.len() < 4 {
+            return Err(invalid_data("schedules file is too short"));
+        }
+
+        let (checksum, payload) = bytes.split_at(4);
+        if crc32(payload) != u32::from_le_bytes(checksum.try_into().expect("slice has 4 bytes")) {
+            return Err(invalid_data("schedules checksum mismatch"));
+        }
+        let mut decoder = Decoder::new(payload);
+        let scheduler = Scheduler::decode(&mut decoder)?;
+        if !decoder.is_empty() {
+            return Err(invalid_data("trailing bytes in schedules file"));
+        }
+        Ok(scheduler)
+    }
+}
+
+impl Encode for Scheduler {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_u64(self.last_id);
+        encoder.put_u32(self.transfers.len() as u32);
+        for transfer in self.transfers.values() {
+            let schedule = &transfer.schedule;
+            encoder.put_u64(transfer.id);
+            encoder.put_str(&schedule.from);
+            encoder.put_str(&schedule.to);
+            encoder.put_str(&schedule.asset);
+            encoder.put_u64(schedule.amount);
+            encoder.put_u8(match schedule.recurrence {
+                Recurrence::Once => 0,
+                Recurrence::Daily => 1,
+                Recurrence::Weekly => 2,
+                Recurrence::Monthly => 3,
+                Recurrence::MonthEnd => 4,
+            });
+            encoder.put_u64(schedule.start.timestamp());
+            encoder.put_option_u64(schedule.end.map(|end| end.timestamp()));
+            encoder.put_u32(schedule.retry.max_retries);
+            encoder.put_u64(schedule.retry.backoff);
+            encoder.put_u32(transfer.occurrence);
+            encoder.put_u32(transfer.attempts);
+            encoder.put_option_u64(transfer.retry_at);
+            match &transfer.last_failure {
+                Some(failure) => {
+                    encoder.put_u8(1);
+                    encoder.put_u64(failure.due.timestamp());
+                    encoder.put_u32(failure.attempt);
+                    encoder.put_u64(failure.timestamp);
+                    encoder.put_str(&failure.error);
+                }
+                None => encoder.put_u8(0),
+            }
+        }
+    }
+}
+
+impl Decode for Scheduler {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        let last_id = decoder.get_u64()?;
+        let mut transfers = BTreeMap::new();
+        for _ in 0..decoder.get_u32()? {
+            let id = decoder.get_u64()?;
+            let schedule = Schedule {
+                from: decoder.get_str()?,
+                to: decoder.get_str()?,
+                asset: decoder.get_str()?,
+                amount: decoder.get_u64()?,
+                recurrence: match decoder.get_u8()? {
+                    0 => Recurrence::Once,
+                    1 => Recurrence::Daily,
+                    2 => Recurrence::Weekly,
+                    3 => Recurrence::Monthly,
+                    4 => Recurrence::MonthEnd,
+                    _ => return Err(invalid_data("unknown recurrence")),
+                },
+                start: Date::from_timestamp(decoder.get_u64()?),
+                end: decoder.get_option_u64()?.map(Date::from_timestamp),
+                retry: RetryPolicy {
+                    max_retries: decoder.get_u32()?,
+                    backoff: decoder.get_u64()?,
+                },
+            };
+            let occurrence = decoder.get_u32()?;
+            let attempts = decoder.get_u32()?;
+            let retry_at = decoder.get_option_u64()?;
+            let last_failure = match decoder.get_u8()? {
+                0 => None,
+                1 => Some(Failure {
+                    due: Date::from_timestamp(decoder.get_u64()?),
+                    attempt: decoder.get_u32()?,
+                    timestamp: decoder.get_u64()?,
+                    error: decoder.get_str()?,
+                }),
+                _ => return Err(invalid_data("invalid option tag")),
+            };
+            transfers.insert(
+                id,
+                ScheduledTransfer {
+                    id,
+                    schedule,
+                    occurrence,
+                    attempts,
+                    retry_at,
+                    last_failure,
+                },
+            );
+        }
+        Ok(Scheduler { transfers, last_id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        calendar::Date,
+        clock::ManualClock,
+        errors::AccountingError,
+        test_utils::{accounts_at, date, temp_dir, USD},
+        tx::{Envelope, Tx},
+    };
+
+    use super::{Recurrence, RetryPolicy, Schedule, Scheduler};
+
+    fn rent(recurrence: Recurrence, start: &str) -> Schedule {
+        Schedule {
+            from: "tenant".to_string(),
+            to: "landlord".to_string(),
+            asset: USD.to_string(),
+            amount: 100,
+            recurrence,
+            start: date(start),
+            end: None,
+            retry: RetryPolicy {
+                max_retries: 1,
+                backoff: 3_600,
+            },
+        }
+    }
+
+    #[test]
+    fn monthly_occurrences_are_counted_from_the_start_date() {
+        // Arrange
+        let start = date("2024-01-31");
+
+        // Act
+        let monthly: Vec<Date> = (0..3)
+            .filter_map(|n| Recurrence::Monthly.occurrence(start, n))
+            .collect();
+        let month_end: Vec<Date> = (0..3)
+            .filter_map(|n| Recurrence::MonthEnd.occurrence(date("2024-04-30"), n))
+            .collect();
+
+        // Assert
+        assert_eq!(
+            vec![date("2024-01-31"), date("2024-02-29"), date("2024-03-31")],
+            monthly
+        );
+        assert_eq!(
+            vec![date("2024-04-30"), date("2024-05-31"), date("2024-06-30")],
+            month_end
+        );
+        assert_eq!(None, Recurrence::Once.occurrence(start, 1));
+    }
+
+    #[test]
+    fn due_transfers_are_made_and_missed_ones_caught_up() {
+        // Arrange
+        let clock = ManualClock::new(date("2024-01-30").timestamp());
+        let mut accounts = accounts_at(&clock);
+        accounts.deposit("tenant", USD, 1_000).unwrap();
+        let mut sut = Scheduler::new();
+        let id = sut
+            .add(&accounts, rent(Recurrence::Monthly, "2024-01-31"))
+            .unwrap();
+
+        // Act
+        let early = sut.run(&mut accounts);
+        clock.set(date("2024-03-31").timestamp());
+        let caught_up = sut.run(&mut accounts);
+
+        // Assert
+        assert!(early.executed.is_empty());
+        assert_eq!(3, caught_up.executed.len());
+        assert_eq!(700, accounts.total_balance("tenant", USD));
+        assert_eq!(
+            Some(date("2024-04-30")),
+            sut.transfers()
+                .next()
+                .and_then(|transfer| transfer.next_due())
+        );
+        assert_eq!(Ok(rent(Recurrence::Monthly, "2024-01-31")), sut.cancel(id));
+        assert_eq!(0, sut.transfers().count());
+    }
+
+    #[test]
+    fn occurrences_run_again_after_a_crash_are_not_sent_twice() {
+        // Arrange
+        let clock = ManualClock::new(date("2024-01-31").timestamp());
+        let mut accounts = accounts_at(&clock);
+        accounts.deposit("tenant", USD, 1_000).unwrap();
+        let mut sut = Scheduler::new();
+        sut.add(&accounts, rent(Recurrence::Monthly, "2024-01-31"))
+            .unwrap();
+        // What a crash after journaling the transfer, before saving the schedules, leaves
+        let saved = sut.clone();
+        let first = sut.run(&mut accounts);
+        let last_seq = accounts.last_seq();
+
+        // Act
+        let mut sut = saved;
+        let again = sut.run(&mut accounts);
+
+        // Assert
+        assert_eq!(1, first.executed.len());
+        assert_eq!(first.executed, again.executed);
+        assert_eq!(last_seq, accounts.last_seq());
+        assert_eq!(900, accounts.total_balance("tenant", USD));
+        assert_eq!(
+            Some(date("2024-02-29")),
+            sut.transfers()
+                .next()
+                .and_then(|transfer| transfer.next_due())
+        );
+    }
+
+    #[test]
+    fn transfers_above_the_approval_threshold_are_reported_as_pending() {
+        // Arrange
+        let clock = ManualClock::new(date("2024-05-01").timestamp());
+        let mut accounts = accounts_at(&clock);
+        accounts.deposit("tenant", USD, 1_000).unwrap();
+        accounts.set_approval_threshold(USD, Some(50)).unwrap();
+        let mut sut = Scheduler::new();
+        let id = sut
+            .add(&accounts, rent(Recurrence::Once, "2024-05-01"))
+            .unwrap();
+
+        // Act
+        let report = sut.run(&mut accounts);
+
+        // Assert
+        assert!(report.executed.is_empty());
+        assert!(matches!(
+            report.pending[..],
+            [(schedule, Envelope { tx: Tx::ApprovalRequested { id: 1, .. }, .. })] if schedule == id
+        ));
+        assert_eq!(1, accounts.pending_approvals().len());
+        assert_eq!(0, accounts.total_balance("landlord", USD));
+    }
+
+    #[test]
+    fn failed_transfers_are_retried_after_the_backoff_then_skipped() {
+        // Arrange
+        let clock = ManualClock::new(date("2024-05-01").timestamp());
+        let mut accounts = accounts_at(&clock);
+        let mut sut = Scheduler::new();
+        sut.add(&accounts, rent(Recurrence::Once, "2024-05-01"))
+            .unwrap();
+
+        // Act
+        let first = sut.run(&mut accounts);
+        clock.advance(60);
+        let too_soon = sut.run(&mut accounts);
+        let pending = sut.clone();
+        clock.advance(3_600);
+        let retried = sut.run(&mut accounts);
+
+        // Assert
+        assert_eq!(1, first.failures.len());
+        assert!(too_soon.failures.is_empty());
+        let failure = &retried.failures[0].1;
+        assert_eq!(2, failure.attempt);
+        assert_eq!(
+            format!(
+                "{:?}",
+                AccountingError::AccountNotFound("tenant".to_string())
+            ),
+            failure.error
+        );
+        assert_eq!(0, sut.transfers().count());
+        assert_eq!(
+            Some(1),
+            pending
+                .transfers()
+                .next()
+                .and_then(|transfer| transfer.last_failure())
+                .map(|failure| failure.attempt)
+        );
+    }
+
+    #[test]
+    fn schedules_round_trip_through_disk() {
+        // Arrange
+        let dir = temp_dir("round-trip");
+        let clock = ManualClock::new(date("2024-05-01").timestamp());
+        let mut accounts = accounts_at(&clock);
+        let mut scheduler = Scheduler::new();
+        scheduler
+            .add(&accounts, rent(Recurrence::Weekly, "2024-05-01"))
+            .unwrap();
+        scheduler
+            .add(&accounts, rent(Recurrence::MonthEnd, "2024-05-31"))
+            .unwrap();
+        scheduler.run(&mut accounts);
+        scheduler.save(&dir).unwrap();
+
+        // Act
+        let sut = Scheduler::load(&dir);
+
+        // Assert
+        assert_eq!(scheduler, sut.unwrap());
+        assert_eq!(
+            Scheduler::new(),
+            Scheduler::load(&dir.join("empty")).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,304 @@
+//! Dry runs of operations against a ledger that leave the ledger untouched.
+//!
+//! A [`View`] reads through to the ledger it is built on and copies an account only
+//! when an operation first touches it, so simulating a few operations against a large
+//! ledger costs little more than the accounts they involve. Operations are applied in
+//! order as they would be on the ledger itself: each sees the balances the ones before
+//! it leave, and one that fails changes nothing and does not stop the rest.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    accounts::{Accounts, Balance},
+    errors::AccountingError,
+    fees::FEE_ACCOUNT,
+    tx::{Envelope, Tx},
+};
+
+/// An operation to simulate, named after the [`Accounts`] method it maps to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Deposit {
+        account: String,
+        asset: String,
+        amount: u64,
+    },
+    Withdraw {
+        account: String,
+        asset: String,
+        amount: u64,
+    },
+    Send {
+        from: String,
+        to: String,
+        asset: String,
+        amount: u64,
+    },
+}
+
+impl Operation {
+    /// The accounts the operation may change, fees aside
+    fn accounts(&self) -> Vec<&str> {
+        match self {
+            Operation::Deposit { account, .. } | Operation::Withdraw { account, .. } => {
+                vec![account]
+            }
+            Operation::Send { from, to, .. } => vec![from, to],
+        }
+    }
+
+    /// The account, asset and amount the operation moves out, if it is limited by the
+    /// velocity rules
+    fn outflow(&self) -> Option<(&str, &str, u64)> {
+        match self {
+            Operation::Deposit { .. } => None,
+            Operation::Withdraw {
+                account,
+                asset,
+                amount,
+            } => Some((account, asset, *amount)),
+            Operation::Send {
+                from,
+                asset,
+                amount,
+                ..
+            } => Some((from, asset, *amount)),
+        }
+    }
+}
+
+/// The outcome of [`simulate`]
+#[derive(Debug, PartialEq, Eq)]
+pub struct Simulation {
+    /// The resulting balances of every account the operations touched, fees included
+    pub balances: BTreeMap<String, BTreeMap<String, Balance>>,
+    /// What the ledger would record, in order
+    pub envelopes: Vec<Envelope>,
+    /// Every operation that would fail, by its index in the sequence
+    pub errors: Vec<(usize, AccountingError)>,
+}
+
+/// A copy-on-write view of an [`Accounts`] ledger
+#[derive(Clone, Debug)]
+pub struct View<'a> {
+    base: &'a Accounts,
+    /// Holds the accounts copied from `base` and every change made through the view
+    overlay: Accounts,
+    /// The accounts copied into `overlay` or created there
+    touched: BTreeSet<String>,
+}
+
+impl<'a> View<'a> {
+    /// A view with no changes yet
+    pub fn new(base: &'a Accounts) -> Self {
+        View {
+            base,
+            overlay: base.scratch(),
+            touched: BTreeSet::new(),
+        }
+    }
+
+    /// Applies `operation` to the view and returns the envelopes it records, fees
+    /// included.
+    ///
+    /// # Errors
+    /// - the same errors as the [`Accounts`] method the operation maps to
+    pub fn apply(&mut self, operation: &Operation) -> Result<Vec<Envelope>, AccountingError> {
+        for signer in operation.accounts() {
+            self.overlay.copy_account(self.base, signer);
+            self.touched.insert(signer.to_string());
+        }
+        // The overlay only has the view's own history, the ledger has the rest
+        if let Some((signer, asset, amount)) = operation.outflow() {
+            let sent = self.overlay.outflow(signer, asset);
+            self.base.check_velocity(signer, asset, amount, sent)?;
+        }
+
+        let seq = self.overlay.last_seq();
+        match operation {
+            Operation::Deposit {
+                account,
+                asset,
+                amount,
+            } => self.overlay.deposit(account, asset, *amount),
+            Operation::Withdraw {
+                account,
+                asset,
+                amount,
+            } => self.overlay.withdraw(account, asset, *amount),
+            Operation::Send {
+                from,
+                to,
+                asset,
+                amount,
+            } => self.overlay.send(from, to, asset, *amount),
+        }?;
+        Ok(self.overlay.history_since(seq).to_vec())
+    }
+
+    /// The `signer`'s balances as the view sees them
+    pub fn balance(&self, signer: &str) -> BTreeMap<&str, Balance> {
+        if self.touched.contains(signer) || signer == FEE_ACCOUNT {
+            self.overlay.balance(signer)
+        } else {
+            self.base.balance(signer)
+        }
+    }
+}
+
+/// Applies `operations` in order to a [`View`] of `accounts` and returns the resulting
+/// balances, what would be recorded and what would fail.
+pub fn simulate<'a>(
+    accounts: &Accounts,
+    operations: impl IntoIterator<Item = &'a Operation>,
+) -> Simulation {
+    let mut view = View::new(accounts);
+    let mut envelopes = vec![];
+    let mut errors = vec![];
+    for (index, operation) in operations.into_iter().enumerate() {
+        match view.apply(operation) {
+            Ok(recorded) => envelopes.extend(recorded),
+            Err(accounting_error) => errors.push((index, accounting_error)),
+        }
+    }
+
+    let charged = envelopes
+        .iter()
+        .any(|envelope| matches!(envelope.tx, Tx::Fee { .. }));
+    let balances = view
+        .touched
+        .iter()
+        .map(String::as_str)
+        .chain(charged.then_some(FEE_ACCOUNT))
+        .map(|signer| {
+            let balance = view
+                .balance(signer)
+                .into_iter()
+                .map(|(asset, balance)| (asset.to_string(), balance))
+                .collect();
+            (signer.to_string(), balance)
+        })
+        .collect();
+    Simulation {
+        balances,
+        envelopes,
+        errors,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::Accounts,
+        errors::AccountingError,
+        fees::{Fee, FeeOn, FeeRule, FEE_ACCOUNT},
+        test_utils::USD,
+        tx::{Envelope, Tx},
+    };
+
+    use super::{simulate, Operation, View};
+
+    fn send(from: &str, to: &str, amount: u64) -> Operation {
+        Operation::Send {
+            from: from.to_string(),
+            to: to.to_string(),
+            asset: USD.to_string(),
+            amount,
+        }
+    }
+
+    fn funded_accounts() -> Accounts {
+        let mut accounts = Accounts::new();
+        accounts.register_asset(USD, 2).unwrap();
+        accounts.deposit("client_1", USD, 100).unwrap();
+        accounts.deposit("client_3", USD, 500).unwrap();
+        accounts
+    }
+
+    #[test]
+    fn simulating_reports_every_outcome_and_leaves_the_ledger_untouched() {
+        // Arrange
+        let accounts = funded_accounts();
+        let previous_accounts = accounts.clone();
+        let operations = [
+            send("client_1", "client_2", 60),
+            Operation::Withdraw {
+                account: "client_2".to_string(),
+                asset: USD.to_string(),
+                amount: 100,
+            },
+            send("client_2", "client_1", 20),
+        ];
+
+        // Act
+        let sut = simulate(&accounts, &operations);
+
+        // Assert
+        assert_eq!(
+            vec![(
+                1,
+                AccountingError::AccountUnderFunded("client_2".to_string(), 100)
+            )],
+            sut.errors
+        );
+        assert_eq!(
+            vec![4, 5],
+            sut.envelopes.iter().map(|e| e.seq).collect::<Vec<_>>()
+        );
+        assert!(matches!(sut.envelopes[1].tx, Tx::Transfer { id: 2, .. }));
+        assert_eq!(
+            vec!["client_1", "client_2"],
+            sut.balances.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(60, sut.balances["client_1"][USD].total);
+        assert_eq!(40, sut.balances["client_2"][USD].total);
+        assert_eq!(previous_accounts, accounts);
+    }
+
+    #[test]
+    fn views_read_through_until_an_account_is_touched() {
+        // Arrange
+        let mut accounts = funded_accounts();
+        let fee = Fee {
+            rule: FeeRule::Flat(5),
+            min: 0,
+            max: None,
+        };
+        accounts.set_fee(FeeOn::Transfer, USD, Some(fee)).unwrap();
+        let mut sut = View::new(&accounts);
+
+        // Act
+        let recorded = sut.apply(&send("client_1", "client_2", 50)).unwrap();
+
+        // Assert
+        assert_eq!(2, recorded.len());
+        assert_eq!(45, sut.balance("client_1")[USD].total);
+        assert_eq!(5, sut.balance(FEE_ACCOUNT)[USD].total);
+        assert_eq!(500, sut.balance("client_3")[USD].total);
+        assert_eq!(0, accounts.total_balance(FEE_ACCOUNT, USD));
+        assert_eq!(100, accounts.total_balance("client_1", USD));
+    }
+
+    #[test]
+    fn sends_above_the_approval_threshold_are_simulated_as_requests() {
+        // Arrange
+        let mut accounts = funded_accounts();
+        accounts.set_approval_threshold(USD, Some(50)).unwrap();
+
+        // Act
+        let sut = simulate(&accounts, &[send("client_3", "client_1", 200)]);
+
+        // Assert
+        assert!(sut.errors.is_empty());
+        assert!(matches!(
+            sut.envelopes[..],
+            [Envelope {
+                tx: Tx::ApprovalRequested { id: 1, .. },
+                ..
+            }]
+        ));
+        assert_eq!(500, sut.balances["client_3"][USD].total);
+        assert_eq!(200, sut.balances["client_3"][USD].held);
+        assert_eq!(100, sut.balances["client_1"][USD].total);
+    }
+}
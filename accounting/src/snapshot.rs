@@ -0,0 +1,188 @@
+//! Point-in-time copies of [`Accounts`] that let startup skip most of the journal.
+//!
+//! A snapshot file is `[CRC-32 of payload: u32][payload]`, where the payload holds the
+//! sequence number of the last transaction included followed by the encoded ledger.
+//! The ledger's history is left out: the journal keeps it.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    accounts::Accounts,
+    codec::{crc32, invalid_data, Decode, Decoder, Encode, Encoder},
+};
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_EXTENSION: &str = ".snap";
+
+/// The state of the ledger after applying every transaction up to and including `seq`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Sequence number of the last transaction reflected in `accounts`
+    pub seq: u64,
+    pub accounts: Accounts,
+}
+
+impl Snapshot {
+    /// Writes the snapshot into `dir` and returns the path of the new file.
+    ///
+    /// The file is written under a temporary name and renamed once it is synced, so a
+    /// crash never leaves a partially written snapshot behind under its final name.
+    ///
+    /// # Errors
+    /// - the file cannot be written, synced or renamed
+    pub fn write(&self, dir: &Path) -> io::Result<PathBuf> {
+        let mut encoder = Encoder::new();
+        encoder.put_u64(self.seq);
+        self.accounts.encode(&mut encoder);
+        let payload = encoder.into_bytes();
+
+        let path = dir.join(file_name(self.seq));
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&crc32(&payload).to_le_bytes())?;
+        file.write_all(&payload)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        File::open(dir)?.sync_all()?;
+
+        Ok(path)
+    }
+
+    /// Reads a snapshot file and verifies its checksum.
+    ///
+    /// # Errors
+    /// - the file cannot be read
+    /// - the checksum does not match or the contents cannot be decoded
+    pub fn read(path: &Path) -> io::Result<Snapshot> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < 4 {
+            return Err(invalid_data("snapshot is too short"));
+        }
+
+        let (checksum, payload) = bytes.split_at(4);
+        if crc32(payload) != u32::from_le_bytes(checksum.try_into().expect("slice has 4 bytes")) {
+            return Err(invalid_data("snapshot checksum mismatch"));
+        }
+
+        let mut decoder = Decoder::new(payload);
+        let snapshot = Snapshot {
+            seq: decoder.get_u64()?,
+            accounts: Accounts::decode(&mut decoder)?,
+        };
+        if !decoder.is_empty() {
+            return Err(invalid_data("trailing bytes in snapshot"));
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Lists the snapshots in `dir` as `(seq, path)` pairs, newest first.
+///
+/// # Errors
+/// - the directory cannot be read
+pub fn list(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut snapshots = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let seq = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(SNAPSHOT_PREFIX))
+            .and_then(|name| name.strip_suffix(SNAPSHOT_EXTENSION))
+            .and_then(|seq| seq.parse::<u64>().ok());
+        if let Some(seq) = seq {
+            snapshots.push((seq, path));
+        }
+    }
+    snapshots.sort_by_key(|(seq, _)| std::cmp::Reverse(*seq));
+    Ok(snapshots)
+}
+
+fn file_name(seq: u64) -> String {
+    format!("{SNAPSHOT_PREFIX}{seq:020}{SNAPSHOT_EXTENSION}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{accounts::Accounts, test_utils::temp_dir};
+
+    use super::{list, Snapshot};
+
+    fn sample_snapshot(seq: u64) -> Snapshot {
+        let mut accounts = Accounts::with_double_entry();
+        accounts.register_asset("USD", 2).unwrap();
+        accounts.register_asset("BTC", 8).unwrap();
+        accounts
+            .deposit_idempotent("payment-1", "client_1", "USD", 100)
+            .unwrap();
+        accounts.deposit("client_1", "BTC", 3).unwrap();
+        accounts.send("client_1", "client_2", "USD", 25).unwrap();
+        accounts.hold("client_1", "USD", 10).unwrap();
+        accounts.hold("client_1", "BTC", 1).unwrap();
+        accounts.release(1).unwrap();
+        Snapshot { seq, accounts }
+    }
+
+    #[test]
+    fn snapshots_round_trip_through_disk() {
+        // Arrange
+        let dir = temp_dir("round-trip");
+        let snapshot = sample_snapshot(2);
+        let path = snapshot.write(&dir).unwrap();
+
+        // Act
+        let sut = Snapshot::read(&path);
+
+        // Assert
+        let mut sut = sut.unwrap();
+        assert!(sut.accounts.history_since(0).is_empty());
+        sut.accounts
+            .restore_history(snapshot.accounts.history_since(0).to_vec())
+            .unwrap();
+        assert_eq!(snapshot, sut);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn errors_when_a_snapshot_is_corrupted() {
+        // Arrange
+        let dir = temp_dir("corrupted");
+        let path = sample_snapshot(2).write(&dir).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        fs::write(&path, bytes).unwrap();
+
+        // Act
+        let sut = Snapshot::read(&path);
+
+        // Assert
+        assert!(sut.is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn snapshots_are_listed_newest_first() {
+        // Arrange
+        let dir = temp_dir("list");
+        sample_snapshot(2).write(&dir).unwrap();
+        sample_snapshot(10).write(&dir).unwrap();
+        sample_snapshot(5).write(&dir).unwrap();
+
+        // Act
+        let sut = list(&dir).unwrap();
+
+        // Assert
+        assert_eq!(
+            vec![10, 5, 2],
+            sut.into_iter().map(|(seq, _)| seq).collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,460 @@
+//! Account statements for a period, rendered as CSV or as fixed-width text.
+//!
+//! A [`Statement`] is built from a ledger's history: the opening balance is the
+//! balance at the end of the day before the period, every transaction in the period
+//! that moves the account's money becomes a line with its running balance, and the
+//! closing balance is where the last line leaves it. Balances are net of drawn credit,
+//! so they go negative while credit is drawn.
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use crate::{
+    accounts::{Accounts, AsOf},
+    assets::Asset,
+    calendar::Date,
+    errors::AccountingError,
+    fees::FEE_ACCOUNT,
+    tx::{Details, Tx},
+};
+
+/// How many envelopes are read from the account's history at a time
+const PAGE_SIZE: usize = 500;
+
+/// The width of a row of [`Statement::to_text`]
+const TEXT_WIDTH: usize = 96;
+
+/// A transaction on a [`Statement`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatementLine {
+    pub seq: u64,
+    pub date: Date,
+    pub description: String,
+    pub details: Details,
+    pub asset: String,
+    /// Positive when money came in
+    pub amount: i128,
+    /// The balance in `asset` after this line
+    pub balance: i128,
+}
+
+/// An account's activity between two dates, both inclusive
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Statement {
+    pub account: String,
+    pub from: Date,
+    pub to: Date,
+    /// Keyed by asset code; includes every asset the account used before or during the period
+    pub opening: BTreeMap<String, i128>,
+    /// Oldest first
+    pub lines: Vec<StatementLine>,
+    /// Keyed by asset code; assets without fees in the period are left out
+    pub fees: BTreeMap<String, u64>,
+    /// Keyed by asset code, like `opening`
+    pub closing: BTreeMap<String, i128>,
+    /// Used to render amounts in major units
+    pub assets: Vec<Asset>,
+}
+
+impl Statement {
+    /// Builds the `signer`'s statement for the days `from` to `to`, both inclusive, out of
+    /// the ledger's history.
+    ///
+    /// # Errors
+    /// - the same errors as [`Accounts::balance_as_of`]
+    pub fn generate(
+        accounts: &Accounts,
+        signer: &str,
+        from: Date,
+        to: Date,
+    ) -> Result<Statement, AccountingError> {
+        let start = from.timestamp();
+        let end = to.next().timestamp();
+        let opening = match start.checked_sub(1) {
+            Some(before) => accounts
+                .balance_as_of(signer, AsOf::Timestamp(before))?
+                .into_iter()
+                .map(|(asset, balance)| (asset, balance.net()))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+
+        let mut closing = opening.clone();
+        let mut lines = vec![];
+        let mut fees = BTreeMap::new();
+        let mut after = 0;
+        loop {
+            let page = accounts.account_history(signer, after, PAGE_SIZE);
+            let in_period = page
+                .envelopes
+                .iter()
+                .filter(|envelope| (start..end).contains(&envelope.timestamp));
+            for envelope in in_period {
+                let Some((asset, amount, description)) = movement(&envelope.tx, signer) else {
+                    continue;
+                };
+                if let Tx::Fee {
+                    account,
+                    amount: fee,
+                    ..
+                } = &envelope.tx
+                {
+                    if account == signer {
+                        *fees.entry(asset.to_string()).or_default() += fee;
+                    }
+                }
+                let balance = closing.entry(asset.to_string()).or_default();
+                *balance += amount;
+                lines.push(StatementLine {
+                    seq: envelope.seq,
+                    date: Date::from_timestamp(envelope.timestamp),
+                    description,
+                    details: envelope.details.clone(),
+                    asset: asset.to_string(),
+                    amount,
+                    balance: *balance,
+                });
+            }
+            let past_the_period = page
+                .envelopes
+                .last()
+                .is_some_and(|envelope| envelope.timestamp >= end);
+            match page.next_after {
+                Some(next_after) if !past_the_period => after = next_after,
+                _ => break,
+            }
+        }
+
+        Ok(Statement {
+            account: signer.to_string(),
+            from,
+            to,
+            opening,
+            lines,
+            fees,
+            closing,
+            assets: accounts.assets().iter().cloned().collect(),
+        })
+    }
+
+    /// Renders the statement as CSV with a header row.
+    ///
+    /// Opening balances, fees and closing balances are rows of their own without a
+    /// sequence id, dated the first and the last day of the period.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("date,seq,description,asset,amount,balance,memo,reference\n");
+        let mut row = |fields: [&str; 8]| {
+            let fields = fields.map(csv_field);
+            csv.push_str(&fields.join(","));
+            csv.push('\n');
+        };
+
+        let (from, to) = (self.from.to_string(), self.to.to_string());
+        for (asset, balance) in &self.opening {
+            let balance = self.format(asset, *balance);
+            row([&from, "", "Opening balance", asset, "", &balance, "", ""]);
+        }
+        for line in &self.lines {
+            let (date, seq) = (line.date.to_string(), line.seq.to_string());
+            let amount = self.format(&line.asset, line.amount);
+            let balance = self.format(&line.asset, line.balance);
+            let memo = line.details.memo.as_deref().unwrap_or_default();
+            let reference = line.details.reference.as_deref().unwrap_or_default();
+            row([
+                &date,
+                &seq,
+                &line.description,
+                &line.asset,
+                &amount,
+                &balance,
+                memo,
+                reference,
+            ]);
+        }
+        for (asset, fees) in &self.fees {
+            let fees = self.format(asset, i128::from(*fees));
+            row([&to, "", "Fees charged", asset, &fees, "", "", ""]);
+        }
+        for (asset, balance) in &self.closing {
+            let balance = self.format(asset, *balance);
+            row([&to, "", "Closing balance", asset, "", &balance, "", ""]);
+        }
+        csv
+    }
+
+    /// Renders the statement as a fixed-width text layout for printing or email
+    pub fn to_text(&self) -> String {
+        let rule = format!("{}\n", "-".repeat(TEXT_WIDTH));
+        let mut text = format!(
+            "Statement for {}\nPeriod {} to {}\n\n",
+            self.account, self.from, self.to
+        );
+        text.push_str(&text_row([
+            "Date",
+            "Seq",
+            "Description",
+            "Asset",
+            "Amount",
+            "Balance",
+        ]));
+        text.push_str(&rule);
+
+        let (from, to) = (self.from.to_string(), self.to.to_string());
+        for (asset, balance) in &self.opening {
+            let balance = self.format(asset, *balance);
+            text.push_str(&text_row([
+                &from,
+                "",
+                "Opening balance",
+                asset,
+                "",
+                &balance,
+            ]));
+        }
+        for line in &self.lines {
+            let (date, seq) = (line.date.to_string(), line.seq.to_string());
+            let amount = self.format(&line.asset, line.amount);
+            let balance = self.format(&line.asset, line.balance);
+            text.push_str(&text_row([
+                &date,
+                &seq,
+                &line.description,
+                &line.asset,
+                &amount,
+                &balance,
+            ]));
+        }
+        text.push_str(&rule);
+        for (asset, fees) in &self.fees {
+            let fees = self.format(asset, i128::from(*fees));
+            text.push_str(&text_row([&to, "", "Fees charged", asset, &fees, ""]));
+        }
+        for (asset, balance) in &self.closing {
+            let balance = self.format(asset, *balance);
+            text.push_str(&text_row([&to, "", "Closing balance", asset, "", &balance]));
+        }
+        text
+    }
+
+    /// `amount` in `asset`'s major units; in minor units if the asset is unknown
+    fn format(&self, asset: &str, amount: i128) -> String {
+        self.assets
+            .iter()
+            .find(|candidate| candidate.code == asset)
+            .map_or_else(|| amount.to_string(), |asset| asset.format_signed(amount))
+    }
+}
+
+/// The asset, signed amount and description of the money `tx` moves in or out of the
+/// `signer`'s account; `None` if it moves none, e.g. a hold or a lifecycle change.
+fn movement<'a>(tx: &'a Tx, signer: &str) -> Option<(&'a str, i128, String)> {
+    let (asset, amount, description) = match tx {
+        Tx::Deposit { asset, amount, .. } => (asset, i128::from(*amount), "Deposit".to_string()),
+        Tx::Withdraw { asset, amount, .. } => {
+            (asset, -i128::from(*amount), "Withdrawal".to_string())
+        }
+        Tx::Transfer {
+            from,
+            to,
+            asset,
+            amount,
+            ..
+        } => {
+            if from == to {
+                return None;
+            }
+            if from == signer {
+                (asset, -i128::from(*amount), format!("Transfer to {to}"))
+            } else {
+                (asset, i128::from(*amount), format!("Transfer from {from}"))
+            }
+        }
+        Tx::Capture {
+            id, asset, amount, ..
+        } => (asset, -i128::from(*amount), format!("Captured hold {id}")),
+        Tx::Fee {
+            account,
+            asset,
+            amount,
+        } if account == signer => (asset, -i128::from(*amount), "Fee".to_string()),
+        Tx::Fee {
+            account,
+            asset,
+            amount,
+        } if signer == FEE_ACCOUNT => (asset, i128::from(*amount), format!("Fee from {account}")),
+        Tx::Chargeback {
+            deposit,
+            asset,
+            amount,
+            ..
+        } => (
+            asset,
+            -i128::from(*amount),
+            format!("Chargeback of deposit #{deposit}"),
+        ),
+        Tx::Reversal { original, tx } => {
+            let (asset, amount, _) = movement(tx, signer)?;
+            return Some((asset, -amount, format!("Reversal of #{original}")));
+        }
+        Tx::InterestPaid { asset, amount, .. } => {
+            (asset, i128::from(*amount), "Interest paid".to_string())
+        }
+        Tx::InterestCharged { asset, amount, .. } => {
+            (asset, -i128::from(*amount), "Interest charged".to_string())
+        }
+        _ => return None,
+    };
+    Some((asset, amount, description))
+}
+
+/// Lays out a row of [`Statement::to_text`], truncating the description to its column
+fn text_row([date, seq, description, asset, amount, balance]: [&str; 6]) -> String {
+    let description: String = description.chars().take(32).collect();
+    format!("{date:<10}  {seq:>8}  {description:<32}  {asset:<6}  {amount:>15}  {balance:>15}\n")
+}
+
+/// Quotes a CSV field if it contains a delimiter, a quote or a line break
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        accounts::Accounts,
+        clock::ManualClock,
+        fees::{Fee, FeeOn, FeeRule},
+        test_utils::{accounts_at, date},
+        tx::Details,
+    };
+
+    use super::Statement;
+
+    /// A ledger with a deposit before January 2024, activity during it and a deposit after it
+    fn january_activity() -> Accounts {
+        let clock = ManualClock::new(date("2023-12-20").timestamp());
+        let mut accounts = accounts_at(&clock);
+        let fee = Fee {
+            rule: FeeRule::Flat(25),
+            min: 0,
+            max: None,
+        };
+        accounts
+            .set_fee(FeeOn::Withdrawal, "USD", Some(fee))
+            .unwrap();
+        accounts.deposit("alice", "USD", 10_000).unwrap();
+        clock.set(date("2024-01-05").timestamp());
+        let details = Details {
+            memo: Some("rent, January".to_string()),
+            reference: None,
+        };
+        accounts
+            .annotated(details, |accounts| {
+                accounts.send("alice", "bob", "USD", 2_500)
+            })
+            .unwrap();
+        clock.set(date("2024-01-31").timestamp() + 3_600);
+        accounts.withdraw("alice", "USD", 1_000).unwrap();
+        clock.set(date("2024-02-01").timestamp());
+        accounts.deposit("alice", "USD", 500).unwrap();
+        accounts
+    }
+
+    #[test]
+    fn statements_list_the_period_with_running_balances_and_fees() {
+        // Arrange
+        let accounts = january_activity();
+
+        // Act
+        let sut = Statement::generate(&accounts, "alice", date("2024-01-01"), date("2024-01-31"))
+            .unwrap();
+
+        // Assert
+        let lines = sut
+            .lines
+            .iter()
+            .map(|line| (line.description.as_str(), line.amount, line.balance))
+            .collect::<Vec<_>>();
+        assert_eq!(Some(&10_000), sut.opening.get("USD"));
+        assert_eq!(
+            vec![
+                ("Transfer to bob", -2_500, 7_500),
+                ("Withdrawal", -1_000, 6_500),
+                ("Fee", -25, 6_475),
+            ],
+            lines
+        );
+        assert_eq!(Some(&25), sut.fees.get("USD"));
+        assert_eq!(Some(&6_475), sut.closing.get("USD"));
+    }
+
+    #[test]
+    fn statements_render_as_csv_and_fixed_width_text() {
+        // Arrange
+        let accounts = january_activity();
+        let sut =
+            Statement::generate(&accounts, "bob", date("2024-01-01"), date("2024-01-31")).unwrap();
+
+        // Act
+        let csv = sut.to_csv();
+        let text = sut.to_text();
+
+        // Assert
+        assert_eq!(
+            "date,seq,description,asset,amount,balance,memo,reference\n\
+             2024-01-05,4,Transfer from alice,USD,25.00,25.00,\"rent, January\",\n\
+             2024-01-31,,Closing balance,USD,,25.00,,\n",
+            csv
+        );
+        let rows = text.lines().skip(3).collect::<Vec<_>>();
+        assert_eq!(5, rows.len());
+        assert!(rows.iter().all(|row| row.len() == super::TEXT_WIDTH));
+        assert!(rows[2].starts_with("2024-01-05         4  Transfer from alice"));
+        assert!(rows[4].ends_with("25.00"));
+    }
+
+    #[test]
+    fn transactions_recorded_after_the_clock_goes_back_stay_in_the_later_period() {
+        // Arrange
+        let clock = ManualClock::new(date("2024-02-01").timestamp());
+        let mut accounts = january_activity();
+        accounts.set_clock(clock.clone());
+        clock.set(date("2024-01-10").timestamp());
+        accounts.deposit("alice", "USD", 300).unwrap();
+
+        // Act
+        let january =
+            Statement::generate(&accounts, "alice", date("2024-01-01"), date("2024-01-31"))
+                .unwrap();
+        let february =
+            Statement::generate(&accounts, "alice", date("2024-02-01"), date("2024-02-29"))
+                .unwrap();
+
+        // Assert
+        assert_eq!(3, january.lines.len());
+        assert_eq!(Some(&6_475), january.closing.get("USD"));
+        assert_eq!(2, february.lines.len());
+        assert_eq!(Some(&7_275), february.closing.get("USD"));
+    }
+
+    #[test]
+    fn reversals_appear_as_lines_undoing_the_original() {
+        // Arrange
+        let mut accounts = january_activity();
+        let deposit = accounts.deposit("alice", "USD", 700).unwrap();
+        accounts.reverse(deposit.seq).unwrap();
+
+        // Act
+        let sut = Statement::generate(&accounts, "alice", date("2024-02-01"), date("2024-02-29"))
+            .unwrap();
+
+        // Assert
+        let last = sut.lines.last().unwrap();
+        assert_eq!(format!("Reversal of #{}", deposit.seq), last.description);
+        assert_eq!(-700, last.amount);
+        assert_eq!(Some(&6_975), sut.closing.get("USD"));
+    }
+}
@@ -0,0 +1,56 @@
+//! Fixtures shared by the unit tests of every module.
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{accounts::Accounts, assets::Asset, calendar::Date, clock::ManualClock};
+
+pub(crate) const USD: &str = "USD";
+pub(crate) const BTC: &str = "BTC";
+
+/// Tells apart the directories of tests that run at the same time
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A `YYYY-MM-DD` date
+pub(crate) fn date(value: &str) -> Date {
+    Date::parse(value).unwrap()
+}
+
+/// [`USD`] with cents
+pub(crate) fn usd() -> Asset {
+    Asset {
+        code: USD.to_string(),
+        decimals: 2,
+    }
+}
+
+/// A new, empty directory for the test `name`, unique to this process and call
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "accounting-{}-{}-{name}",
+        std::process::id(),
+        NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// An empty ledger that accepts [`USD`] and [`BTC`]
+pub(crate) fn accounts_with_assets() -> Accounts {
+    let mut accounts = Accounts::new();
+    accounts.register_asset(USD, 2).expect("register failed");
+    accounts.register_asset(BTC, 8).expect("register failed");
+    accounts
+}
+
+/// An empty ledger that accepts [`USD`] and reads the time from `clock`
+pub(crate) fn accounts_at(clock: &ManualClock) -> Accounts {
+    let mut accounts = Accounts::new();
+    accounts.set_clock(clock.clone());
+    accounts.register_asset(USD, 2).unwrap();
+    accounts
+}
@@ -1,7 +1,658 @@
+use std::io;
+
+use crate::{
+    codec::{invalid_data, Decode, Decoder, Encode, Encoder},
+    fees::{Fee, FeeOn, FEE_ACCOUNT},
+};
+
 /// A transaction type. Transactions should be able to rebuild a ledger's state
 /// when they are applied in the same sequence to an empty state.
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Tx {
-    Deposit { account: String, amount: u64 },
-    Withdraw { account: String, amount: u64 },
+    Deposit {
+        account: String,
+        asset: String,
+        amount: u64,
+        /// Client-supplied key that makes retries of the same deposit safe
+        idempotency_key: Option<String>,
+    },
+    Withdraw {
+        account: String,
+        asset: String,
+        amount: u64,
+        /// Client-supplied key that makes retries of the same withdrawal safe
+        idempotency_key: Option<String>,
+    },
+    /// Moves `amount` from one account to another; both legs share the same `id`
+    /// and are always applied together.
+    Transfer {
+        id: u64,
+        from: String,
+        to: String,
+        asset: String,
+        amount: u64,
+        /// Client-supplied key that makes retries of the same transfer safe
+        idempotency_key: Option<String>,
+    },
+    /// Adds an asset with its decimal precision to the registry
+    RegisterAsset {
+        asset: String,
+        decimals: u8,
+    },
+    /// Reserves `amount` in an account without moving it
+    Hold {
+        id: u64,
+        account: String,
+        asset: String,
+        amount: u64,
+    },
+    /// Cancels the hold with the same `id`, making its funds available again
+    Release {
+        id: u64,
+        account: String,
+        asset: String,
+        amount: u64,
+    },
+    /// Completes the hold with the same `id` by withdrawing its funds
+    Capture {
+        id: u64,
+        account: String,
+        asset: String,
+        amount: u64,
+    },
+    OpenAccount {
+        account: String,
+    },
+    FreezeAccount {
+        account: String,
+    },
+    UnfreezeAccount {
+        account: String,
+    },
+    CloseAccount {
+        account: String,
+    },
+    /// Moves a fee charged on the preceding transaction into [`crate::fees::FEE_ACCOUNT`]
+    Fee {
+        account: String,
+        asset: String,
+        amount: u64,
+    },
+    /// Replaces the fee charged on an operation in `asset`; `None` makes it free
+    SetFee {
+        on: FeeOn,
+        asset: String,
+        fee: Option<Fee>,
+    },
+    /// Lets the account's balance in `asset` go down to `-limit`
+    SetCreditLimit {
+        account: String,
+        asset: String,
+        limit: u64,
+    },
+    /// Credits interest accrued on a positive balance
+    InterestPaid {
+        account: String,
+        asset: String,
+        amount: u64,
+        /// Key that makes posting the same period again safe
+        idempotency_key: Option<String>,
+    },
+    /// Debits interest accrued on drawn credit, even beyond the credit limit
+    InterestCharged {
+        account: String,
+        asset: String,
+        amount: u64,
+        /// Key that makes posting the same period again safe
+        idempotency_key: Option<String>,
+    },
+    /// Holds the funds of the deposit recorded with sequence id `deposit` while it is
+    /// disputed
+    Dispute {
+        deposit: u64,
+        account: String,
+        asset: String,
+        amount: u64,
+    },
+    /// Settles the dispute of a deposit in the account's favour, releasing its funds
+    Resolve {
+        deposit: u64,
+        account: String,
+        asset: String,
+        amount: u64,
+    },
+    /// Settles the dispute of a deposit against the account, withdrawing its funds and
+    /// locking the account
+    Chargeback {
+        deposit: u64,
+        account: String,
+        asset: String,
+        amount: u64,
+    },
+    /// Undoes the transaction recorded with sequence id `original`; `tx` is a copy of it
+    Reversal {
+        original: u64,
+        tx: Box<Tx>,
+    },
+    /// Makes withdrawals and transfers in `asset` above `threshold` wait for approval;
+    /// `None` lifts the requirement
+    SetApprovalThreshold {
+        asset: String,
+        threshold: Option<u64>,
+    },
+    /// Holds the funds of a withdrawal from `from`, or of a transfer to `to`, until an
+    /// operator other than `maker` approves or rejects it
+    ApprovalRequested {
+        id: u64,
+        maker: String,
+        from: String,
+        to: Option<String>,
+        asset: String,
+        amount: u64,
+        /// The key of the withdrawal once it is approved
+        idempotency_key: Option<String>,
+    },
+    /// Approves the request with the same `id`, releasing its funds; the withdrawal or
+    /// transfer itself follows as its own transaction
+    Approved {
+        id: u64,
+        account: String,
+        checker: String,
+    },
+    /// Rejects the request with the same `id`, releasing its funds
+    ApprovalRejected {
+        id: u64,
+        account: String,
+        checker: String,
+    },
+}
+
+impl Tx {
+    /// The client-supplied idempotency key, for transactions that carry one
+    pub fn idempotency_key(&self) -> Option<&str> {
+        match self {
+            Tx::Deposit {
+                idempotency_key, ..
+            }
+            | Tx::Withdraw {
+                idempotency_key, ..
+            }
+            | Tx::Transfer {
+                idempotency_key, ..
+            }
+            | Tx::InterestPaid {
+                idempotency_key, ..
+            }
+            | Tx::InterestCharged {
+                idempotency_key, ..
+            } => idempotency_key.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The accounts whose balances, holds or settings the transaction touches
+    pub fn accounts(&self) -> Vec<&str> {
+        match self {
+            Tx::Deposit { account, .. }
+            | Tx::Withdraw { account, .. }
+            | Tx::Hold { account, .. }
+            | Tx::Release { account, .. }
+            | Tx::Capture { account, .. }
+            | Tx::OpenAccount { account }
+            | Tx::FreezeAccount { account }
+            | Tx::UnfreezeAccount { account }
+            | Tx::CloseAccount { account }
+            | Tx::SetCreditLimit { account, .. }
+            | Tx::InterestPaid { account, .. }
+            | Tx::InterestCharged { account, .. }
+            | Tx::Dispute { account, .. }
+            | Tx::Resolve { account, .. }
+            | Tx::Chargeback { account, .. }
+            | Tx::Approved { account, .. }
+            | Tx::ApprovalRejected { account, .. } => vec![account],
+            Tx::ApprovalRequested { from, .. } => vec![from],
+            Tx::Transfer { from, to, .. } if from == to => vec![from],
+            Tx::Transfer { from, to, .. } => vec![from, to],
+            Tx::Fee { account, .. } => vec![account, FEE_ACCOUNT],
+            Tx::Reversal { tx, .. } => tx.accounts(),
+            Tx::RegisterAsset { .. } | Tx::SetFee { .. } | Tx::SetApprovalThreshold { .. } => {
+                vec![]
+            }
+        }
+    }
+}
+
+/// Free-form context supplied by whoever initiates a [`Tx`]
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Details {
+    /// A human-readable note, e.g. why support staff made an adjustment
+    pub memo: Option<String>,
+    /// An identifier in an external system, e.g. a payment processor's id
+    pub reference: Option<String>,
+}
+
+/// A [`Tx`] as it was recorded by a ledger
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Envelope {
+    /// Position in the ledger's history; strictly increasing, starting at 1
+    pub seq: u64,
+    /// When the transaction was recorded, in seconds since the Unix epoch
+    pub timestamp: u64,
+    pub details: Details,
+    pub tx: Tx,
+}
+
+impl Encode for Envelope {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.put_u64(self.seq);
+        encoder.put_u64(self.timestamp);
+        encoder.put_option_str(self.details.memo.as_deref());
+        encoder.put_option_str(self.details.reference.as_deref());
+        self.tx.encode(encoder);
+    }
+}
+
+impl Decode for Envelope {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        Ok(Envelope {
+            seq: decoder.get_u64()?,
+            timestamp: decoder.get_u64()?,
+            details: Details {
+                memo: decoder.get_option_str()?,
+                reference: decoder.get_option_str()?,
+            },
+            tx: Tx::decode(decoder)?,
+        })
+    }
+}
+
+impl Encode for Tx {
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            Tx::Deposit {
+                account,
+                asset,
+                amount,
+                idempotency_key,
+            } => {
+                encoder.put_u8(0);
+                encoder.put_str(account);
+                encoder.put_str(asset);
+                encoder.put_u64(*amount);
+                encoder.put_option_str(idempotency_key.as_deref());
+            }
+            Tx::Withdraw {
+                account,
+                asset,
+                amount,
+                idempotency_key,
+            } => {
+                encoder.put_u8(1);
+                encoder.put_str(account);
+                encoder.put_str(asset);
+                encoder.put_u64(*amount);
+                encoder.put_option_str(idempotency_key.as_deref());
+            }
+            Tx::Transfer {
+                id,
+                from,
+                to,
+                asset,
+                amount,
+                idempotency_key,
+            } => {
+                encoder.put_u8(2);
+                encoder.put_u64(*id);
+                encoder.put_str(from);
+                encoder.put_str(to);
+                encoder.put_str(asset);
+                encoder.put_u64(*amount);
+                encoder.put_option_str(idempotency_key.as_deref());
+            }
+            Tx::RegisterAsset { asset, decimals } => {
+                encoder.put_u8(3);
+                encoder.put_str(asset);
+                encoder.put_u8(*decimals);
+            }
+            Tx::Hold {
+                id,
+                account,
+                asset,
+                amount,
+            } => encode_hold(encoder, 4, *id, account, asset, *amount),
+            Tx::Release {
+                id,
+                account,
+                asset,
+                amount,
+            } => encode_hold(encoder, 5, *id, account, asset, *amount),
+            Tx::Capture {
+                id,
+                account,
+                asset,
+                amount,
+            } => encode_hold(encoder, 6, *id, account, asset, *amount),
+            Tx::OpenAccount { account } => {
+                encoder.put_u8(7);
+                encoder.put_str(account);
+            }
+            Tx::FreezeAccount { account } => {
+                encoder.put_u8(8);
+                encoder.put_str(account);
+            }
+            Tx::UnfreezeAccount { account } => {
+                encoder.put_u8(9);
+                encoder.put_str(account);
+            }
+            Tx::CloseAccount { account } => {
+                encoder.put_u8(10);
+                encoder.put_str(account);
+            }
+            Tx::Fee {
+                account,
+                asset,
+                amount,
+            } => {
+                encoder.put_u8(11);
+                encoder.put_str(account);
+                encoder.put_str(asset);
+                encoder.put_u64(*amount);
+            }
+            Tx::SetFee { on, asset, fee } => {
+                encoder.put_u8(12);
+                on.encode(encoder);
+                encoder.put_str(asset);
+                match fee {
+                    Some(fee) => {
+                        encoder.put_u8(1);
+                        fee.encode(encoder);
+                    }
+                    None => encoder.put_u8(0),
+                }
+            }
+            Tx::SetCreditLimit {
+                account,
+                asset,
+                limit,
+            } => {
+                encoder.put_u8(13);
+                encoder.put_str(account);
+                encoder.put_str(asset);
+                encoder.put_u64(*limit);
+            }
+            Tx::InterestPaid {
+                account,
+                asset,
+                amount,
+                idempotency_key,
+            } => {
+                encoder.put_u8(14);
+                encoder.put_str(account);
+                encoder.put_str(asset);
+                encoder.put_u64(*amount);
+                encoder.put_option_str(idempotency_key.as_deref());
+            }
+            Tx::InterestCharged {
+                account,
+                asset,
+                amount,
+                idempotency_key,
+            } => {
+                encoder.put_u8(15);
+                encoder.put_str(account);
+                encoder.put_str(asset);
+                encoder.put_u64(*amount);
+                encoder.put_option_str(idempotency_key.as_deref());
+            }
+            Tx::Dispute {
+                deposit,
+                account,
+                asset,
+                amount,
+            } => encode_dispute(encoder, 16, *deposit, account, asset, *amount),
+            Tx::Resolve {
+                deposit,
+                account,
+                asset,
+                amount,
+            } => encode_dispute(encoder, 17, *deposit, account, asset, *amount),
+            Tx::Chargeback {
+                deposit,
+                account,
+                asset,
+                amount,
+            } => encode_dispute(encoder, 18, *deposit, account, asset, *amount),
+            Tx::Reversal { original, tx } => {
+                encoder.put_u8(19);
+                encoder.put_u64(*original);
+                tx.encode(encoder);
+            }
+            Tx::SetApprovalThreshold { asset, threshold } => {
+                encoder.put_u8(20);
+                encoder.put_str(asset);
+                encoder.put_option_u64(*threshold);
+            }
+            Tx::ApprovalRequested {
+                id,
+                maker,
+                from,
+                to,
+                asset,
+                amount,
+                idempotency_key,
+            } => {
+                encoder.put_u8(21);
+                encoder.put_u64(*id);
+                encoder.put_str(maker);
+                encoder.put_str(from);
+                encoder.put_option_str(to.as_deref());
+                encoder.put_str(asset);
+                encoder.put_u64(*amount);
+                encoder.put_option_str(idempotency_key.as_deref());
+            }
+            Tx::Approved {
+                id,
+                account,
+                checker,
+            } => encode_approval(encoder, 22, *id, account, checker),
+            Tx::ApprovalRejected {
+                id,
+                account,
+                checker,
+            } => encode_approval(encoder, 23, *id, account, checker),
+        }
+    }
+}
+
+impl Decode for Tx {
+    fn decode(decoder: &mut Decoder) -> io::Result<Self> {
+        match decoder.get_u8()? {
+            0 => Ok(Tx::Deposit {
+                account: decoder.get_str()?,
+                asset: decoder.get_str()?,
+                amount: decoder.get_u64()?,
+                idempotency_key: decoder.get_option_str()?,
+            }),
+            1 => Ok(Tx::Withdraw {
+                account: decoder.get_str()?,
+                asset: decoder.get_str()?,
+                amount: decoder.get_u64()?,
+                idempotency_key: decoder.get_option_str()?,
+            }),
+            2 => Ok(Tx::Transfer {
+                id: decoder.get_u64()?,
+                from: decoder.get_str()?,
+                to: decoder.get_str()?,
+                asset: decoder.get_str()?,
+                amount: decoder.get_u64()?,
+                idempotency_key: decoder.get_option_str()?,
+            }),
+            3 => Ok(Tx::RegisterAsset {
+                asset: decoder.get_str()?,
+                decimals: decoder.get_u8()?,
+            }),
+            tag @ 4..=6 => {
+                let id = decoder.get_u64()?;
+                let account = decoder.get_str()?;
+                let asset = decoder.get_str()?;
+                let amount = decoder.get_u64()?;
+                Ok(match tag {
+                    4 => Tx::Hold {
+                        id,
+                        account,
+                        asset,
+                        amount,
+                    },
+                    5 => Tx::Release {
+                        id,
+                        account,
+                        asset,
+                        amount,
+                    },
+                    _ => Tx::Capture {
+                        id,
+                        account,
+                        asset,
+                        amount,
+                    },
+                })
+            }
+            7 => Ok(Tx::OpenAccount {
+                account: decoder.get_str()?,
+            }),
+            8 => Ok(Tx::FreezeAccount {
+                account: decoder.get_str()?,
+            }),
+            9 => Ok(Tx::UnfreezeAccount {
+                account: decoder.get_str()?,
+            }),
+            10 => Ok(Tx::CloseAccount {
+                account: decoder.get_str()?,
+            }),
+            11 => Ok(Tx::Fee {
+                account: decoder.get_str()?,
+                asset: decoder.get_str()?,
+                amount: decoder.get_u64()?,
+            }),
+            12 => Ok(Tx::SetFee {
+                on: FeeOn::decode(decoder)?,
+                asset: decoder.get_str()?,
+                fee: match decoder.get_u8()? {
+                    0 => None,
+                    _ => Some(Fee::decode(decoder)?),
+                },
+            }),
+            13 => Ok(Tx::SetCreditLimit {
+                account: decoder.get_str()?,
+                asset: decoder.get_str()?,
+                limit: decoder.get_u64()?,
+            }),
+            14 => Ok(Tx::InterestPaid {
+                account: decoder.get_str()?,
+                asset: decoder.get_str()?,
+                amount: decoder.get_u64()?,
+                idempotency_key: decoder.get_option_str()?,
+            }),
+            15 => Ok(Tx::InterestCharged {
+                account: decoder.get_str()?,
+                asset: decoder.get_str()?,
+                amount: decoder.get_u64()?,
+                idempotency_key: decoder.get_option_str()?,
+            }),
+            tag @ 16..=18 => {
+                let deposit = decoder.get_u64()?;
+                let account = decoder.get_str()?;
+                let asset = decoder.get_str()?;
+                let amount = decoder.get_u64()?;
+                Ok(match tag {
+                    16 => Tx::Dispute {
+                        deposit,
+                        account,
+                        asset,
+                        amount,
+                    },
+                    17 => Tx::Resolve {
+                        deposit,
+                        account,
+                        asset,
+                        amount,
+                    },
+                    _ => Tx::Chargeback {
+                        deposit,
+                        account,
+                        asset,
+                        amount,
+                    },
+                })
+            }
+            19 => Ok(Tx::Reversal {
+                original: decoder.get_u64()?,
+                tx: Box::new(Tx::decode(decoder)?),
+            }),
+            20 => Ok(Tx::SetApprovalThreshold {
+                asset: decoder.get_str()?,
+                threshold: decoder.get_option_u64()?,
+            }),
+            21 => Ok(Tx::ApprovalRequested {
+                id: decoder.get_u64()?,
+                maker: decoder.get_str()?,
+                from: decoder.get_str()?,
+                to: decoder.get_option_str()?,
+                asset: decoder.get_str()?,
+                amount: decoder.get_u64()?,
+                idempotency_key: decoder.get_option_str()?,
+            }),
+            tag @ 22..=23 => {
+                let id = decoder.get_u64()?;
+                let account = decoder.get_str()?;
+                let checker = decoder.get_str()?;
+                Ok(match tag {
+                    22 => Tx::Approved {
+                        id,
+                        account,
+                        checker,
+                    },
+                    _ => Tx::ApprovalRejected {
+                        id,
+                        account,
+                        checker,
+                    },
+                })
+            }
+            _ => Err(invalid_data("unknown transaction type")),
+        }
+    }
+}
+
+/// Hold lifecycle transactions share the same layout and differ only by `tag`
+fn encode_hold(encoder: &mut Encoder, tag: u8, id: u64, account: &str, asset: &str, amount: u64) {
+    encoder.put_u8(tag);
+    encoder.put_u64(id);
+    encoder.put_str(account);
+    encoder.put_str(asset);
+    encoder.put_u64(amount);
+}
+
+/// Dispute lifecycle transactions share the same layout and differ only by `tag`
+fn encode_dispute(
+    encoder: &mut Encoder,
+    tag: u8,
+    deposit: u64,
+    account: &str,
+    asset: &str,
+    amount: u64,
+) {
+    encoder.put_u8(tag);
+    encoder.put_u64(deposit);
+    encoder.put_str(account);
+    encoder.put_str(asset);
+    encoder.put_u64(amount);
+}
+
+/// Approval decisions share the same layout and differ only by `tag`
+fn encode_approval(encoder: &mut Encoder, tag: u8, id: u64, account: &str, checker: &str) {
+    encoder.put_u8(tag);
+    encoder.put_u64(id);
+    encoder.put_str(account);
+    encoder.put_str(checker);
 }
@@ -0,0 +1,303 @@
+//! Limits on how much an account can move out per transaction and per rolling day.
+//!
+//! [`VelocityRules`] put accounts in tiers and give each tier its own [`Limits`] per
+//! asset. [`crate::accounts::Accounts`] checks withdrawals, transfers, holds and captures
+//! against the rules installed with [`crate::accounts::Accounts::set_velocity_rules`],
+//! counting what the account withdrew, sent and captured over the last [`WINDOW`] seconds
+//! from its history.
+//!
+//! Rules are configuration rather than ledger state: they are neither recorded nor
+//! encoded, and replayed operations are not checked against them. A rules file has one
+//! rule per line, with amounts in the asset's major units:
+//!
+//! ```text
+//! # tier <tier> <asset> [per-tx <amount>] [daily <amount>]
+//! tier standard USD per-tx 1000 daily 2500
+//! tier premium USD daily 50000
+//! # account <account> <tier>
+//! account alice premium
+//! # default <tier>, for the accounts not listed; without it they have no limits
+//! default standard
+//! ```
+
+use std::{collections::BTreeMap, fs, io, path::Path, sync::Arc};
+
+use crate::{assets::AssetRegistry, codec::invalid_data, errors::AccountingError};
+
+/// The length of the rolling window daily limits apply to, in seconds
+pub const WINDOW: u64 = 24 * 60 * 60;
+
+/// How much of an asset a tier's accounts can move out; `None` for no limit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The largest amount of a single withdrawal, transfer or hold
+    pub per_tx: Option<u64>,
+    /// The most withdrawn, sent and captured in total over the last [`WINDOW`] seconds
+    pub daily: Option<u64>,
+}
+
+/// The limits of every tier and the tier of every account
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VelocityRules {
+    /// Keyed by tier, then asset; assets without limits are not limited
+    pub tiers: BTreeMap<String, BTreeMap<String, Limits>>,
+    /// The tier of each listed account
+    pub accounts: BTreeMap<String, String>,
+    /// The tier of the accounts not listed; they have no limits if `None`
+    pub default_tier: Option<String>,
+}
+
+impl VelocityRules {
+    /// Reads the rules file at `path`, see [`VelocityRules::parse`].
+    ///
+    /// # Errors
+    /// - the file cannot be read
+    /// - the same errors as [`VelocityRules::parse`]
+    pub fn load(path: &Path, assets: &AssetRegistry) -> io::Result<VelocityRules> {
+        VelocityRules::parse(&fs::read_to_string(path)?, assets)
+    }
+
+    /// Parses rules written as described in the [module documentation](self); `#` starts a
+    /// comment.
+    ///
+    /// # Errors
+    /// - a line is not a rule, naming the line
+    /// - a tier names an unknown asset or an amount it cannot hold
+    /// - an account or the default is put in a tier without limits
+    pub fn parse(text: &str, assets: &AssetRegistry) -> io::Result<VelocityRules> {
+        let mut rules = VelocityRules::default();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| invalid_data(&format!("line {}: {message}", index + 1));
+            let line = line.split('#').next().unwrap_or_default();
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [] => {}
+                ["tier", tier, asset, settings @ ..] => {
+                    let Some(registered) = assets.get(asset) else {
+                        return Err(error(&format!("unknown asset {asset}")));
+                    };
+                    let mut limits = Limits::default();
+                    for setting in settings.chunks(2) {
+                        let [name, amount] = setting else {
+                            return Err(error(&format!("{} needs an amount", setting[0])));
+                        };
+                        let amount = registered
+                            .parse(amount)
+                            .ok_or_else(|| error(&format!("invalid amount {amount}")))?;
+                        match *name {
+                            "per-tx" => limits.per_tx = Some(amount),
+                            "daily" => limits.daily = Some(amount),
+                            _ => return Err(error(&format!("unknown limit {name}"))),
+                        }
+                    }
+                    rules
+                        .tiers
+                        .entry(tier.to_string())
+                        .or_default()
+                        .insert(asset.to_string(), limits);
+                }
+                ["account", account, tier] => {
+                    rules.accounts.insert(account.to_string(), tier.to_string());
+                }
+                ["default", tier] => rules.default_tier = Some(tier.to_string()),
+                _ => return Err(error(&format!("cannot parse {:?}", line.trim()))),
+            }
+        }
+
+        // A misspelt tier would otherwise leave its accounts without limits
+        let assigned = rules.accounts.values().chain(&rules.default_tier);
+        if let Some(tier) = assigned
+            .into_iter()
+            .find(|tier| !rules.tiers.contains_key(*tier))
+        {
+            return Err(invalid_data(&format!("tier {tier} has no limits")));
+        }
+        Ok(rules)
+    }
+
+    /// The `signer`'s tier and its limits in `asset`; `None` if neither is limited
+    pub fn limits(&self, signer: &str, asset: &str) -> Option<(&str, Limits)> {
+        let tier = self.accounts.get(signer).or(self.default_tier.as_ref())?;
+        let limits = self.tiers.get(tier)?.get(asset)?;
+        Some((tier, *limits))
+    }
+
+    /// Checks that `signer` can move `amount` of `asset` out, having already moved
+    /// `spent()` out over the last [`WINDOW`] seconds.
+    ///
+    /// `spent` is only called if the `signer` has a daily limit in `asset`.
+    ///
+    /// # Errors
+    /// - `amount` is above the tier's per-transaction limit
+    /// - `amount` would take what was moved out over the window above the tier's daily limit
+    pub fn check(
+        &self,
+        signer: &str,
+        asset: &str,
+        amount: u64,
+        spent: impl FnOnce() -> u64,
+    ) -> Result<(), AccountingError> {
+        let Some((tier, limits)) = self.limits(signer, asset) else {
+            return Ok(());
+        };
+        if let Some(limit) = limits.per_tx.filter(|limit| amount > *limit) {
+            return Err(AccountingError::TxLimitExceeded(
+                signer.to_string(),
+                tier.to_string(),
+                limit,
+            ));
+        }
+        if let Some(limit) = limits.daily {
+            let spent = spent();
+            if spent.saturating_add(amount) > limit {
+                return Err(AccountingError::DailyLimitExceeded(
+                    signer.to_string(),
+                    tier.to_string(),
+                    limit.saturating_sub(spent),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The rules installed in a ledger.
+///
+/// Like its clock, they are not part of the ledger's state: any two handles compare
+/// equal, so ledgers that only differ in their rules are still equal.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SharedRules(Arc<VelocityRules>);
+
+impl SharedRules {
+    pub(crate) fn new(rules: VelocityRules) -> Self {
+        SharedRules(Arc::new(rules))
+    }
+
+    pub(crate) fn rules(&self) -> &VelocityRules {
+        &self.0
+    }
+}
+
+impl PartialEq for SharedRules {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for SharedRules {}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{
+        assets::AssetRegistry,
+        errors::AccountingError,
+        test_utils::{usd, USD},
+    };
+
+    use super::{Limits, VelocityRules};
+
+    fn assets() -> AssetRegistry {
+        let mut assets = AssetRegistry::default();
+        assets.register(usd()).unwrap();
+        assets
+    }
+
+    #[test]
+    fn rules_files_put_accounts_in_tiers() {
+        // Arrange
+        let text = "\
+# limits in dollars
+tier standard USD per-tx 1000 daily 2500.50
+tier premium  USD daily 50000   # no per-transaction limit
+account alice premium
+default standard
+";
+
+        // Act
+        let sut = VelocityRules::parse(text, &assets()).unwrap();
+
+        // Assert
+        assert_eq!(
+            Some((
+                "standard",
+                Limits {
+                    per_tx: Some(100_000),
+                    daily: Some(250_050),
+                }
+            )),
+            sut.limits("bob", USD)
+        );
+        assert_eq!(
+            Some((
+                "premium",
+                Limits {
+                    per_tx: None,
+                    daily: Some(5_000_000),
+                }
+            )),
+            sut.limits("alice", USD)
+        );
+        assert_eq!(None, sut.limits("bob", "EUR"));
+    }
+
+    #[test]
+    fn invalid_rules_name_the_offending_line() {
+        // Arrange
+        let assets = assets();
+        let texts = [
+            (
+                "tier standard USD per-tx 10\ntier standard USD daily",
+                "line 2",
+            ),
+            ("tier standard EUR daily 10", "unknown asset EUR"),
+            ("tier standard USD weekly 10", "unknown limit weekly"),
+            ("tier standard USD daily 0.001", "invalid amount 0.001"),
+            ("account alice", "cannot parse"),
+            ("tier standard USD\naccount alice premium", "tier premium"),
+        ];
+
+        for (text, expected) in texts {
+            // Act
+            let error = VelocityRules::parse(text, &assets).unwrap_err();
+
+            // Assert
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+            assert!(error.to_string().contains(expected), "{error}");
+        }
+    }
+
+    #[test]
+    fn checks_report_what_the_window_still_allows() {
+        // Arrange
+        let sut = VelocityRules::parse(
+            "tier standard USD per-tx 5 daily 8\ndefault standard",
+            &assets(),
+        )
+        .unwrap();
+
+        // Act
+        let over_tx = sut.check("bob", USD, 600, || 0);
+        let over_day = sut.check("bob", USD, 400, || 500);
+        let within = sut.check("bob", USD, 300, || 500);
+
+        // Assert
+        assert_eq!(
+            Err(AccountingError::TxLimitExceeded(
+                "bob".to_string(),
+                "standard".to_string(),
+                500
+            )),
+            over_tx
+        );
+        assert_eq!(
+            Err(AccountingError::DailyLimitExceeded(
+                "bob".to_string(),
+                "standard".to_string(),
+                300
+            )),
+            over_day
+        );
+        assert_eq!(Ok(()), within);
+    }
+}
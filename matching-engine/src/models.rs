@@ -1,20 +1,15 @@
 use std::cmp::Reverse;
 
 /// Simplified side of a position as well as order.
-#[derive(Clone, Copy, PartialOrd, PartialEq, Eq, Debug, Ord)]
+#[derive(Clone, Copy, PartialOrd, PartialEq, Eq, Debug, Ord, Default)]
 pub enum Side {
     /// Want to buy
+    #[default]
     Buy,
     /// Want to sell
     Sell,
 }
 
-impl Default for Side {
-    fn default() -> Self {
-        Self::Buy
-    }
-}
-
 /// An order to buy or sell an amount at a given price.
 #[derive(Clone, PartialEq, Eq)]
 pub struct Order {
@@ -51,7 +46,7 @@ impl Order {
 }
 
 /// An unfilled order that is kept in the system for later filling.
-#[derive(Clone, PartialEq, Debug, Eq, Ord, Default)]
+#[derive(Clone, PartialEq, Debug, Eq, Default)]
 pub struct PartialOrder {
     /// Price per unit
     pub price: u64,
@@ -67,12 +62,22 @@ pub struct PartialOrder {
     pub ordinal: u64,
 }
 
-impl PartialOrd for PartialOrder {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+/// Orders by `ordinal` alone, which is unique within a book. This is
+/// deliberately coarser than `Eq`: two partial orders with the same
+/// `ordinal` compare as equal even if their other fields differ, so they
+/// must not be kept in ordered collections that rely on `Ord` for identity.
+impl Ord for PartialOrder {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         // this reverses the comparison to create a min heap;
         // therefore, `pop()`ing from a `BinaryHeap` returns the item
         // with the lowest value for `ordinal`
-        Reverse(self.ordinal).partial_cmp(&Reverse(other.ordinal))
+        Reverse(self.ordinal).cmp(&Reverse(other.ordinal))
+    }
+}
+
+impl PartialOrd for PartialOrder {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 